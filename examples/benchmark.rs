@@ -0,0 +1,186 @@
+//! Measures the simulation throughput on a fixed scene, without opening a window.
+//!
+//! Runs `WARMUP_STEPS` steps, then reports the wall-clock throughput and the average GPU time
+//! of each stage over the next `MEASURED_STEPS` steps.
+
+use bevy::app::ScheduleRunnerPlugin;
+use bevy::prelude::*;
+use bevy::render::renderer::RenderDevice;
+use bevy::utils::Instant;
+use bevy::window::ExitCondition;
+use bevy::winit::WinitPlugin;
+use bevy_rapier3d::geometry::RapierColliderHandle;
+use bevy_rapier3d::plugin::ReadRapierContext;
+use bevy_rapier3d::prelude::{Collider, RigidBody};
+use bevy_wgsparkl::prelude::*;
+use bevy_wgsparkl::resources::TimestampProcessing;
+use std::time::Duration;
+
+const WARMUP_STEPS: u64 = 30;
+const MEASURED_STEPS: u64 = 300;
+
+pub fn main() {
+    App::new()
+        .add_plugins(
+            DefaultPlugins
+                .set(WindowPlugin {
+                    primary_window: None,
+                    exit_condition: ExitCondition::DontExit,
+                    close_when_requested: false,
+                })
+                .disable::<WinitPlugin>(),
+        )
+        .add_plugins(ScheduleRunnerPlugin::run_loop(Duration::ZERO))
+        .add_plugins(bevy_rapier3d::plugin::RapierPhysicsPlugin::<()>::default())
+        .add_plugins(WgSparklPlugin::default())
+        .init_resource::<Benchmark>()
+        .add_systems(Startup, setup_scene)
+        .add_systems(
+            Update,
+            setup_mpm_particles.in_set(WgSparklSet::PrepareCoupling),
+        )
+        .add_systems(Update, run_benchmark.after(WgSparklSet::Step))
+        .run();
+}
+
+#[derive(Resource, Default)]
+struct Benchmark {
+    start: Option<(Instant, u64)>,
+    stage_totals: [f64; 9],
+    num_samples: usize,
+    last_sample_step: u64,
+}
+
+fn setup_scene(mut commands: Commands) {
+    commands.spawn((
+        Transform::from_xyz(0.0, -2.0, 0.0),
+        Collider::cuboid(200.0, 2.0, 200.0),
+        RigidBody::Fixed,
+        MpmCouplingEnabled,
+    ));
+}
+
+fn setup_mpm_particles(
+    mut commands: Commands,
+    device: Res<RenderDevice>,
+    mut app_state: ResMut<AppState>,
+    rapier: ReadRapierContext,
+    coupling: Query<&RapierColliderHandle, With<MpmCouplingEnabled>>,
+) {
+    if rapier.rapier_context.get_single().is_err() {
+        return; // Rapier isn’t initialized yet.
+    }
+
+    let rapier = rapier.single();
+
+    if rapier.colliders.colliders.is_empty() || app_state.particles_initialized {
+        return;
+    }
+
+    app_state.particles_initialized = true;
+    app_state.num_substeps = 8;
+    // Wait for the GPU timings of each step, so the wall-clock time includes the GPU work.
+    app_state.timestamp_processing = TimestampProcessing::Blocking;
+
+    let coupling: Vec<_> = coupling
+        .iter()
+        .map(|co_handle| BodyCouplingEntry {
+            body: rapier.colliders.colliders[co_handle.0].parent().unwrap(),
+            collider: co_handle.0,
+            mode: BodyCoupling::OneWay,
+        })
+        .collect();
+
+    let params = SimulationParams {
+        gravity: vector![0.0, -9.81, 0.0],
+        dt: (1.0 / 60.0) / (app_state.num_substeps as f32),
+    };
+    let cell_width = 1.0;
+    let modulus = 10_000_000.0;
+    let poisson = 0.2;
+    let material = ParticleMaterial::new(
+        2700.0,
+        ElasticCoefficients::from_young_modulus(modulus, poisson),
+    )
+    .with_plasticity(DruckerPrager::new(modulus, poisson));
+    let particles = particles_in_box_local(
+        &Transform::from_xyz(0.0, 12.0, 0.0),
+        Vec3::splat(12.5),
+        recommended_spacing(cell_width),
+        &material,
+    );
+
+    commands.insert_resource(PhysicsContext::with_select_coupling(
+        device.wgpu_device(),
+        params,
+        particles,
+        &rapier.rigidbody_set.bodies,
+        &rapier.colliders.colliders,
+        coupling,
+        cell_width,
+        60_000,
+    ));
+}
+
+fn run_benchmark(
+    mut bench: ResMut<Benchmark>,
+    app_state: Res<AppState>,
+    timings: Res<Timestamps>,
+    physics: Option<Res<PhysicsContext>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let Some(physics) = physics else {
+        return;
+    };
+
+    let step = app_state.num_steps;
+    if step < WARMUP_STEPS {
+        return;
+    }
+
+    let Some((start, start_step)) = bench.start else {
+        bench.start = Some((Instant::now(), step));
+        return;
+    };
+
+    if timings.last_updated_step > start_step && timings.last_updated_step > bench.last_sample_step
+    {
+        bench.last_sample_step = timings.last_updated_step;
+        bench.num_samples += 1;
+        for (total, stage) in bench.stage_totals.iter_mut().zip(timings.stages()) {
+            *total += stage;
+        }
+    }
+
+    let num_steps = step - start_step;
+    if num_steps < MEASURED_STEPS {
+        return;
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    let num_particles = physics.num_particles();
+    println!(
+        "{num_particles} particles, {} substeps: {:.2} steps/s, {:.3e} particle-substeps/s",
+        app_state.num_substeps,
+        num_steps as f64 / elapsed,
+        (num_particles * app_state.num_substeps) as f64 * num_steps as f64 / elapsed,
+    );
+
+    if bench.num_samples == 0 {
+        println!("No GPU timings (timestamp queries unsupported by this device).");
+    } else {
+        println!(
+            "Average GPU time per step over {} steps:",
+            bench.num_samples
+        );
+        for (name, total) in Timestamps::STAGE_NAMES.iter().zip(bench.stage_totals) {
+            println!("  {name}: {:.3}ms", total / bench.num_samples as f64);
+        }
+        println!(
+            "  total: {:.3}ms",
+            bench.stage_totals.iter().sum::<f64>() / bench.num_samples as f64
+        );
+    }
+
+    exit.send(AppExit::Success);
+}
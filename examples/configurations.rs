@@ -8,7 +8,10 @@ use bevy_rapier3d::plugin::ReadRapierContext;
 use bevy_rapier3d::prelude::{Collider, RigidBody};
 use bevy_rapier3d::render::RapierDebugRenderPlugin;
 use bevy_rich_text3d::{Text3d, Text3dBounds, Text3dPlugin, Text3dStyling, TextAtlas};
+use bevy_wgsparkl::WgSparklSet;
 use bevy_wgsparkl::components::MpmCouplingEnabled;
+use bevy_wgsparkl::events::ResetSimulation;
+use bevy_wgsparkl::generation::recommended_spacing;
 use bevy_wgsparkl::resources::{AppState, PhysicsContext};
 use nalgebra::{Vector3, vector};
 use wgrapier3d::dynamics::body::{BodyCoupling, BodyCouplingEntry};
@@ -16,7 +19,6 @@ use wgsparkl3d::models::DruckerPrager;
 use wgsparkl3d::solver::ParticlePhase;
 use wgsparkl3d::{
     models::ElasticCoefficients,
-    pipeline::MpmData,
     solver::{Particle, ParticleMassProps, SimulationParams},
 };
 
@@ -25,12 +27,15 @@ pub fn main() {
         .add_plugins((DefaultPlugins, DefaultEditorCamPlugins))
         .add_plugins(bevy_rapier3d::plugin::RapierPhysicsPlugin::<()>::default())
         .add_plugins(RapierDebugRenderPlugin::default())
-        .add_plugins(bevy_wgsparkl::WgSparklPlugin)
+        .add_plugins(bevy_wgsparkl::WgSparklPlugin::default())
         .add_plugins(Text3dPlugin {
             load_system_fonts: true,
             ..Default::default()
         })
-        .add_systems(PostUpdate, setup_mpm_particles)
+        .add_systems(
+            Update,
+            setup_mpm_particles.in_set(WgSparklSet::PrepareCoupling),
+        )
         .add_systems(
             Update,
             reset_scene.run_if(common_conditions::input_just_pressed(KeyCode::KeyR)),
@@ -61,10 +66,8 @@ pub fn setup_scene(mut commands: Commands) {
     ));
 }
 
-pub fn reset_scene(mut commands: Commands, mut app_state: ResMut<AppState>) {
-    app_state.restarting = true;
-    app_state.particles_initialized = false;
-    commands.remove_resource::<PhysicsContext>();
+pub fn reset_scene(mut events: EventWriter<ResetSimulation>) {
+    events.send(ResetSimulation);
 }
 
 pub fn setup_mpm_particles(
@@ -89,17 +92,23 @@ pub fn setup_mpm_particles(
         return; // Already initialized.
     }
 
+    let cell_width = 1f32;
+    let spacing = recommended_spacing(cell_width);
+
     let grid_size_x = 10;
     let grid_size_y = 10;
     let grid_size_z = 10;
-    let num_particles = grid_size_x * grid_size_y * grid_size_z;
+    let samples_x = (grid_size_x as f32 / spacing) as usize;
+    let samples_y = (grid_size_y as f32 / spacing) as usize;
+    let samples_z = (grid_size_z as f32 / spacing) as usize;
+    let num_particles = samples_x * samples_y * samples_z;
 
     let particle_positions = (0..num_particles)
         .map(|i| {
-            let x = i % grid_size_x;
-            let y = (i / grid_size_x) % grid_size_y;
-            let z = (i / (grid_size_x * grid_size_y)) % grid_size_z;
-            Vector3::new(x as f32, y as f32 + 1f32, z as f32)
+            let x = i % samples_x;
+            let y = (i / samples_x) % samples_y;
+            let z = (i / (samples_x * samples_y)) % samples_z;
+            Vector3::new(x as f32, y as f32, z as f32) * spacing + Vector3::y()
         })
         .collect::<Vec<_>>();
 
@@ -143,7 +152,6 @@ pub fn setup_mpm_particles(
         dt: (1.0 / 60.0) / (app_state.num_substeps as f32),
     };
 
-    let cell_width = 1f32;
     let mut particles = vec![];
     let mut configurations = vec![];
     let get_position_for_line = |z: f32| -> bevy::math::Vec3 {
@@ -313,7 +321,7 @@ pub fn setup_mpm_particles(
         for particle in &particle_positions {
             let position = vector![particle.x, particle.y, particle.z];
 
-            let particle_size = vector![1.0, 1.0, 1.0];
+            let particle_size = vector![spacing, spacing, spacing];
             let volume = particle_size.x * particle_size.y * particle_size.z;
             let density = c.density;
             particles.push(Particle {
@@ -340,17 +348,16 @@ pub fn setup_mpm_particles(
 
     println!("Coupled: {}", coupling.len());
 
-    let data = MpmData::with_select_coupling(
+    commands.insert_resource(PhysicsContext::with_select_coupling(
         device,
         params,
-        &particles,
+        particles,
         &rapier.rigidbody_set.bodies,
         &rapier.colliders.colliders,
         coupling,
         cell_width,
         60_000,
-    );
-    commands.insert_resource(PhysicsContext { data, particles });
+    ));
 }
 
 #[derive(Debug)]
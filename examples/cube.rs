@@ -6,24 +6,18 @@ use bevy_rapier3d::geometry::RapierColliderHandle;
 use bevy_rapier3d::plugin::ReadRapierContext;
 use bevy_rapier3d::prelude::{Collider, RigidBody};
 use bevy_rapier3d::render::RapierDebugRenderPlugin;
-use bevy_wgsparkl::components::MpmCouplingEnabled;
-use bevy_wgsparkl::resources::{AppState, PhysicsContext};
-use nalgebra::{Vector3, vector};
-use wgrapier3d::dynamics::body::{BodyCoupling, BodyCouplingEntry};
-use wgsparkl3d::models::DruckerPrager;
-use wgsparkl3d::{
-    models::ElasticCoefficients,
-    pipeline::MpmData,
-    solver::{Particle, ParticleMassProps, SimulationParams},
-};
+use bevy_wgsparkl::prelude::*;
 
 pub fn main() {
     App::new()
         .add_plugins((DefaultPlugins, DefaultEditorCamPlugins))
         .add_plugins(bevy_rapier3d::plugin::RapierPhysicsPlugin::<()>::default())
         .add_plugins(RapierDebugRenderPlugin::default())
-        .add_plugins(bevy_wgsparkl::WgSparklPlugin)
-        .add_systems(PostUpdate, setup_mpm_particles)
+        .add_plugins(WgSparklPlugin::default())
+        .add_systems(
+            Update,
+            setup_mpm_particles.in_set(WgSparklSet::PrepareCoupling),
+        )
         .add_systems(Startup, setup_scene)
         .run();
 }
@@ -71,11 +65,6 @@ pub fn setup_mpm_particles(
         return; // Already initialized.
     }
 
-    let grid_size_x = 25;
-    let grid_size_y = 25;
-    let grid_size_z = 25;
-    let num_particles = grid_size_x * grid_size_y * grid_size_z;
-
     app_state.particles_initialized = true;
 
     let coupling: Vec<_> = coupling
@@ -109,51 +98,39 @@ pub fn setup_mpm_particles(
     };
 
     let cell_width = 1.0;
-    let mut particles = vec![];
 
     let density = 2700.0;
-    let rock_size = vector![1.0, 1.0, 1.0];
-    let volume = rock_size.x * rock_size.y * rock_size.z;
-    let mass_props = ParticleMassProps::new(density * volume, volume.cbrt() / 2.0);
     let modulus = 10_000_000.0;
     let poisson = 0.2;
     let model = ElasticCoefficients::from_young_modulus(modulus, poisson);
-    let plasticity = Some(DruckerPrager {
+    let plasticity = DruckerPrager {
         h0: 45.0f32.to_radians(),
         h1: 50.0f32.to_radians(),
         h2: 0.4,
         h3: 15.0f32.to_radians(),
         ..DruckerPrager::new(modulus, poisson)
-    });
-
-    for i in 0..num_particles {
-        let x = i % grid_size_x;
-        let y = (i / grid_size_x) % grid_size_y;
-        let z = (i / (grid_size_x * grid_size_y)) % grid_size_z;
-        let position = vector![x, y, z];
-        particles.push(Particle {
-            position: vector![position.x as f32, position.y as f32, position.z as f32],
-            velocity: Vector3::zeros(),
-            volume: mass_props,
-            model,
-            plasticity,
-            phase: None,
-        });
-    }
+    };
+
+    let material = ParticleMaterial::new(density, model).with_plasticity(plasticity);
+    let particles = particles_in_box_local(
+        &Transform::from_xyz(12.0, 12.0, 12.0),
+        Vec3::splat(12.5),
+        recommended_spacing(cell_width),
+        &material,
+    );
 
     println!("Number of simulated particles: {}", particles.len());
 
     println!("Coupled: {}", coupling.len());
 
-    let data = MpmData::with_select_coupling(
+    commands.insert_resource(PhysicsContext::with_select_coupling(
         device,
         params,
-        &particles,
+        particles,
         &rapier.rigidbody_set.bodies,
         &rapier.colliders.colliders,
         coupling,
         cell_width,
         60_000,
-    );
-    commands.insert_resource(PhysicsContext { data, particles });
+    ));
 }
@@ -0,0 +1,266 @@
+//! A light box floating on a pool of low-friction granular material, with two-way coupling.
+//!
+//! The solver integrates the two-way coupled box on the GPU, taking the forces applied by the
+//! particles into account. Its pose is read back after each step and turned into a velocity for
+//! Rapier, which moves the box until the next step. The readback waits for the GPU to finish the
+//! step: its cost is displayed next to the GPU timings of the solver stages.
+//!
+//! A heavy ball coupled one way falls into the pool too: it pushes the particles aside but isn’t
+//! slowed down by them, and sinks until it rests on the floor of the pool.
+
+use bevy::prelude::*;
+use bevy::render::renderer::RenderDevice;
+use bevy::utils::Instant;
+use bevy_editor_cam::DefaultEditorCamPlugins;
+use bevy_editor_cam::prelude::EditorCam;
+use bevy_rapier3d::geometry::{ColliderMassProperties, RapierColliderHandle};
+use bevy_rapier3d::plugin::{ReadRapierContext, WriteRapierContext};
+use bevy_rapier3d::prelude::{Collider, RigidBody};
+use bevy_rapier3d::render::RapierDebugRenderPlugin;
+use bevy_wgsparkl::debug::TimingOverlay;
+use bevy_wgsparkl::prelude::*;
+use wgsparkl3d::rapier::dynamics::RigidBodyPosition;
+
+pub fn main() {
+    App::new()
+        .add_plugins((DefaultPlugins, DefaultEditorCamPlugins))
+        .add_plugins(bevy_rapier3d::plugin::RapierPhysicsPlugin::<()>::default())
+        .add_plugins(RapierDebugRenderPlugin::default())
+        .add_plugins(WgSparklPlugin::default())
+        .init_resource::<BodyReadbackTime>()
+        .add_systems(Startup, setup_scene)
+        .add_systems(
+            Update,
+            setup_mpm_particles.in_set(WgSparklSet::PrepareCoupling),
+        )
+        .add_systems(
+            Update,
+            (apply_gpu_body_motion, update_readback_text)
+                .chain()
+                .after(WgSparklSet::Step),
+        )
+        .run();
+}
+
+/// Marks the bodies coupled both ways with the particles.
+#[derive(Component)]
+struct Floating;
+
+#[derive(Component)]
+struct ReadbackText;
+
+/// The time spent waiting for the body poses of the latest step, in milliseconds.
+#[derive(Resource, Default)]
+struct BodyReadbackTime(f64);
+
+const POOL_HALF_WIDTH: f32 = 12.0;
+const FLUID_DEPTH: f32 = 8.0;
+
+fn setup_scene(mut commands: Commands, mut overlay: ResMut<TimingOverlay>) {
+    overlay.enabled = true;
+
+    commands.spawn((
+        Camera3d::default(),
+        EditorCam {
+            last_anchor_depth: 60f64,
+            ..Default::default()
+        },
+        Transform::from_xyz(-25.0, 30.0, 45.0).looking_at(Vec3::new(0.0, 5.0, 0.0), Vec3::Y),
+    ));
+    commands.spawn((
+        ReadbackText,
+        Text::default(),
+        Node {
+            position_type: PositionType::Absolute,
+            left: Val::Px(10.0),
+            top: Val::Px(10.0),
+            ..default()
+        },
+    ));
+
+    /*
+     * Pool
+     */
+    let thickness = 1.0;
+    let wall_height = FLUID_DEPTH * 2.0;
+    let offset = POOL_HALF_WIDTH + thickness;
+    let walls = [
+        (
+            Vec3::new(0.0, -thickness, 0.0),
+            Vec3::new(offset, thickness, offset),
+        ),
+        (
+            Vec3::new(offset, wall_height, 0.0),
+            Vec3::new(thickness, wall_height, offset),
+        ),
+        (
+            Vec3::new(-offset, wall_height, 0.0),
+            Vec3::new(thickness, wall_height, offset),
+        ),
+        (
+            Vec3::new(0.0, wall_height, offset),
+            Vec3::new(offset, wall_height, thickness),
+        ),
+        (
+            Vec3::new(0.0, wall_height, -offset),
+            Vec3::new(offset, wall_height, thickness),
+        ),
+    ];
+    for (position, half_extents) in walls {
+        commands.spawn((
+            Transform::from_translation(position),
+            Collider::cuboid(half_extents.x, half_extents.y, half_extents.z),
+            RigidBody::Fixed,
+            MpmCouplingEnabled,
+        ));
+    }
+
+    /*
+     * Ball coupled one way, only moved by Rapier.
+     */
+    commands.spawn((
+        Transform::from_xyz(-6.0, FLUID_DEPTH + 6.0, -6.0),
+        Collider::ball(1.5),
+        ColliderMassProperties::Density(3000.0),
+        RigidBody::Dynamic,
+        MpmCouplingEnabled,
+    ));
+
+    /*
+     * Floating box, about a third as dense as the material of the pool.
+     */
+    commands.spawn((
+        Transform::from_xyz(0.0, FLUID_DEPTH + 4.0, 0.0),
+        Collider::cuboid(3.0, 1.5, 3.0),
+        ColliderMassProperties::Density(300.0),
+        RigidBody::Dynamic,
+        MpmCouplingEnabled,
+        Floating,
+    ));
+}
+
+fn setup_mpm_particles(
+    mut commands: Commands,
+    device: Res<RenderDevice>,
+    mut app_state: ResMut<AppState>,
+    rapier: ReadRapierContext,
+    coupling: Query<(&RapierColliderHandle, Has<Floating>), With<MpmCouplingEnabled>>,
+) {
+    if rapier.rapier_context.get_single().is_err() {
+        return; // Rapier isn’t initialized yet.
+    }
+
+    let rapier = rapier.single();
+
+    if rapier.colliders.colliders.is_empty() || app_state.particles_initialized {
+        return;
+    }
+
+    app_state.particles_initialized = true;
+    app_state.num_substeps = 16;
+
+    let coupling: Vec<_> = coupling
+        .iter()
+        .map(|(co_handle, floating)| BodyCouplingEntry {
+            body: rapier.colliders.colliders[co_handle.0].parent().unwrap(),
+            collider: co_handle.0,
+            mode: if floating {
+                BodyCoupling::TwoWays
+            } else {
+                BodyCoupling::OneWay
+            },
+        })
+        .collect();
+
+    let params = SimulationParams {
+        gravity: vector![0.0, -9.81, 0.0],
+        dt: (1.0 / 60.0) / (app_state.num_substeps as f32),
+    };
+    let cell_width = 1.0;
+    let (modulus, poisson) = (2_000_000.0, 0.3);
+    let model = ElasticCoefficients::from_young_modulus(modulus, poisson);
+    // A very low friction angle makes the material flow almost like a liquid.
+    let plasticity = DruckerPrager::from_friction(modulus, poisson, 5.0f32.to_radians(), 0.0, 0.4);
+    let material = ParticleMaterial::new(1000.0, model).with_plasticity(plasticity);
+    let particles = particles_in_box_local(
+        &Transform::from_xyz(0.0, FLUID_DEPTH / 2.0, 0.0),
+        Vec3::new(POOL_HALF_WIDTH, FLUID_DEPTH / 2.0, POOL_HALF_WIDTH),
+        recommended_spacing(cell_width),
+        &material,
+    );
+
+    commands.insert_resource(PhysicsContext::from_particles(
+        device.wgpu_device(),
+        params,
+        particles,
+        &rapier.rigidbody_set.bodies,
+        &rapier.colliders.colliders,
+        coupling,
+        cell_width,
+    ));
+}
+
+/// Reads the body poses integrated on the GPU back, and moves the two-way coupled bodies towards
+/// them during the next Rapier step.
+fn apply_gpu_body_motion(
+    device: Res<RenderDevice>,
+    app_state: Res<AppState>,
+    physics: Option<Res<PhysicsContext>>,
+    mut rapier: WriteRapierContext<With<MpmRapierContext>>,
+    mut readback_time: ResMut<BodyReadbackTime>,
+    mut last_step: Local<u64>,
+) {
+    let Some(physics) = physics else {
+        return;
+    };
+
+    if app_state.num_steps == *last_step || rapier.rapier_context.is_empty() {
+        return;
+    }
+    *last_step = app_state.num_steps;
+
+    let start = Instant::now();
+    // This waits for the GPU to complete the step that was just submitted.
+    let Some(poses) =
+        futures::executor::block_on(physics.read_body_poses_async(device.wgpu_device()))
+    else {
+        return;
+    };
+    readback_time.0 = start.elapsed().as_secs_f64() * 1000.0;
+
+    let step_dt = physics.substep_dt() * app_state.num_substeps as f32;
+    let mut rapier = rapier.single_mut();
+    for (coupling, pose) in physics.data.coupling().iter().zip(poses) {
+        if !matches!(coupling.mode, BodyCoupling::TwoWays) {
+            continue;
+        }
+
+        let Some(rb) = rapier.rigidbody_set.bodies.get_mut(coupling.body) else {
+            continue;
+        };
+        // The poses are the collider poses, which match the body poses here since the colliders
+        // are attached at the body origin.
+        let interpolator = RigidBodyPosition {
+            position: *rb.position(),
+            next_position: pose.isometry,
+        };
+        let vel = interpolator
+            .interpolate_velocity(1.0 / step_dt, &rb.mass_properties().local_mprops.local_com);
+        rb.set_linvel(vel.linvel, true);
+        rb.set_angvel(vel.angvel, true);
+    }
+}
+
+fn update_readback_text(
+    readback_time: Res<BodyReadbackTime>,
+    timings: Res<Timestamps>,
+    mut text: Query<&mut Text, With<ReadbackText>>,
+) {
+    for mut text in &mut text {
+        **text = format!(
+            "Body readback: {:.3}ms\nGPU step: {:.3}ms",
+            readback_time.0,
+            timings.total_time()
+        );
+    }
+}
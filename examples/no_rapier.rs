@@ -0,0 +1,86 @@
+//! A pile of sand falling on a floor, without any Rapier collider.
+//!
+//! The particles aren’t coupled with any rigid-body: the floor is a [`SimulationBounds`]
+//! registered as a substep hook.
+
+use bevy::prelude::*;
+use bevy::render::renderer::RenderDevice;
+use bevy_editor_cam::DefaultEditorCamPlugins;
+use bevy_editor_cam::prelude::EditorCam;
+use bevy_wgsparkl::prelude::*;
+use bevy_wgsparkl::step::SubstepHooks;
+use wgsparkl3d::rapier::dynamics::RigidBodySet;
+use wgsparkl3d::rapier::geometry::ColliderSet;
+
+pub fn main() {
+    App::new()
+        .add_plugins((DefaultPlugins, DefaultEditorCamPlugins))
+        .add_plugins(WgSparklPlugin::default())
+        .add_systems(Startup, setup_scene)
+        .add_systems(
+            Update,
+            setup_mpm_particles.in_set(WgSparklSet::PrepareCoupling),
+        )
+        .run();
+}
+
+fn setup_scene(mut commands: Commands) {
+    commands.spawn((
+        Camera3d::default(),
+        EditorCam {
+            last_anchor_depth: 110f64,
+            ..Default::default()
+        },
+        Transform::from_xyz(-30.0, 30.0, 100.0).looking_at(Vec3::new(0.0, 10.0, 0.0), Vec3::Y),
+    ));
+}
+
+fn setup_mpm_particles(
+    mut commands: Commands,
+    device: Res<RenderDevice>,
+    mut app_state: ResMut<AppState>,
+) {
+    if app_state.particles_initialized {
+        return; // Already initialized.
+    }
+
+    app_state.particles_initialized = true;
+    app_state.num_substeps = 8;
+
+    let params = SimulationParams {
+        gravity: vector![0.0, -9.81, 0.0],
+        dt: (1.0 / 60.0) / (app_state.num_substeps as f32),
+    };
+
+    let cell_width = 1.0;
+    let (modulus, poisson) = (10_000_000.0, 0.2);
+    let model = ElasticCoefficients::from_young_modulus(modulus, poisson);
+    let plasticity = DruckerPrager::from_friction(
+        modulus,
+        poisson,
+        35.0f32.to_radians(),
+        10.0f32.to_radians(),
+        0.4,
+    );
+    let material = ParticleMaterial::new(2700.0, model).with_plasticity(plasticity);
+    let particles = particles_in_box_local(
+        &Transform::from_xyz(0.0, 15.0, 0.0),
+        Vec3::splat(8.0),
+        recommended_spacing(cell_width),
+        &material,
+    );
+
+    commands.insert_resource(SubstepHooks(vec![Box::new(SimulationBounds::floor(
+        &device, 0.0,
+    ))]));
+    // No coupled body, so empty Rapier sets are enough.
+    commands.insert_resource(PhysicsContext::from_particles(
+        device.wgpu_device(),
+        params,
+        particles,
+        &RigidBodySet::new(),
+        &ColliderSet::new(),
+        vec![],
+        cell_width,
+    ));
+}
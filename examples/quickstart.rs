@@ -0,0 +1,10 @@
+//! The smallest app simulating particles: a block of sand falling on the ground.
+
+use bevy::prelude::*;
+use bevy_wgsparkl::quickstart::QuickstartPlugin;
+
+pub fn main() {
+    App::new()
+        .add_plugins((DefaultPlugins, QuickstartPlugin))
+        .run();
+}
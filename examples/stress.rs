@@ -0,0 +1,246 @@
+//! Ramps the particle count up to find where the simulation stops scaling on this hardware.
+//!
+//! Each entry of `PARTICLE_COUNTS` is simulated for `MEASURED_FRAMES` frames (after
+//! `WARMUP_FRAMES` frames), then the achieved FPS and the average GPU time of each stage are
+//! printed. A count is reported as broken if it exceeds the device limits, if non-finite particles
+//! show up (e.g. once the grid capacity is too small for the particles), or if the device is lost.
+//!
+//! The grid capacity defaults to `60_000` and can be given as the first argument:
+//! `cargo run --release --example stress -- 200000`.
+
+use bevy::prelude::*;
+use bevy::render::renderer::RenderDevice;
+use bevy_editor_cam::DefaultEditorCamPlugins;
+use bevy_editor_cam::prelude::EditorCam;
+use bevy_rapier3d::geometry::RapierColliderHandle;
+use bevy_rapier3d::plugin::ReadRapierContext;
+use bevy_rapier3d::prelude::{Collider, RigidBody};
+use bevy_wgsparkl::events::DeviceLost;
+use bevy_wgsparkl::instancing3d::InstanceMaterialData;
+use bevy_wgsparkl::nan_check::NanCheck;
+use bevy_wgsparkl::prelude::*;
+use bevy_wgsparkl::resources::SimulationDiagnostics;
+use bevy_wgsparkl::startup::max_rendered_particles;
+
+const PARTICLE_COUNTS: [usize; 3] = [10_000, 100_000, 500_000];
+const DEFAULT_GRID_CAPACITY: u32 = 60_000;
+const WARMUP_FRAMES: u32 = 60;
+const MEASURED_FRAMES: u32 = 300;
+
+pub fn main() {
+    let grid_capacity = std::env::args()
+        .nth(1)
+        .map(|arg| arg.parse().expect("the grid capacity must be an integer"))
+        .unwrap_or(DEFAULT_GRID_CAPACITY);
+
+    App::new()
+        .add_plugins((DefaultPlugins, DefaultEditorCamPlugins))
+        .add_plugins(bevy_rapier3d::plugin::RapierPhysicsPlugin::<()>::default())
+        .add_plugins(WgSparklPlugin::default())
+        .insert_resource(Stress {
+            grid_capacity,
+            ..Default::default()
+        })
+        .add_systems(Startup, setup_scene)
+        .add_systems(
+            Update,
+            setup_mpm_particles.in_set(WgSparklSet::PrepareCoupling),
+        )
+        .add_systems(Update, run_stress.after(WgSparklSet::Step))
+        .run();
+}
+
+#[derive(Resource, Default)]
+struct Stress {
+    grid_capacity: u32,
+    /// The index of the current entry of `PARTICLE_COUNTS`.
+    level: usize,
+    num_frames: u32,
+    measured_time: f64,
+    start_step: u64,
+    stage_totals: [f64; 9],
+    num_samples: usize,
+    last_sample_step: u64,
+}
+
+fn setup_scene(mut commands: Commands, mut nan_check: ResMut<NanCheck>) {
+    nan_check.enabled = true;
+    nan_check.pause = false;
+
+    commands.spawn((
+        Camera3d::default(),
+        EditorCam {
+            last_anchor_depth: 110f64,
+            ..Default::default()
+        },
+        Transform::from_xyz(-60.0, 60.0, 160.0).looking_at(Vec3::new(0.0, 10.0, 0.0), Vec3::Y),
+    ));
+    commands.spawn((
+        Transform::from_xyz(0.0, -2.0, 0.0),
+        Collider::cuboid(200.0, 2.0, 200.0),
+        RigidBody::Fixed,
+        MpmCouplingEnabled,
+    ));
+}
+
+fn setup_mpm_particles(
+    mut commands: Commands,
+    device: Res<RenderDevice>,
+    mut app_state: ResMut<AppState>,
+    stress: Res<Stress>,
+    rapier: ReadRapierContext,
+    coupling: Query<&RapierColliderHandle, With<MpmCouplingEnabled>>,
+) {
+    if rapier.rapier_context.get_single().is_err() {
+        return; // Rapier isn’t initialized yet.
+    }
+
+    let rapier = rapier.single();
+
+    if rapier.colliders.colliders.is_empty() || app_state.particles_initialized {
+        return;
+    }
+
+    app_state.particles_initialized = true;
+    app_state.num_substeps = 8;
+    app_state.render_coupled_colliders = true;
+
+    let coupling: Vec<_> = coupling
+        .iter()
+        .map(|co_handle| BodyCouplingEntry {
+            body: rapier.colliders.colliders[co_handle.0].parent().unwrap(),
+            collider: co_handle.0,
+            mode: BodyCoupling::OneWay,
+        })
+        .collect();
+
+    let params = SimulationParams {
+        gravity: vector![0.0, -9.81, 0.0],
+        dt: (1.0 / 60.0) / (app_state.num_substeps as f32),
+    };
+    let cell_width = 1.0;
+    let spacing = recommended_spacing(cell_width);
+    let model = ElasticCoefficients::from_young_modulus(10_000_000.0, 0.2);
+    let material =
+        ParticleMaterial::new(2700.0, model).with_plasticity(DruckerPrager::new(10_000_000.0, 0.2));
+
+    // A cube with roughly the target number of particles.
+    let target = PARTICLE_COUNTS[stress.level];
+    let half_width = (target as f32).cbrt() * spacing / 2.0;
+    let particles = particles_in_box_local(
+        &Transform::from_xyz(0.0, half_width + 1.0, 0.0),
+        Vec3::splat(half_width),
+        spacing,
+        &material,
+    );
+
+    println!(
+        "Simulating {} particles (grid capacity: {})…",
+        particles.len(),
+        stress.grid_capacity
+    );
+    let max_particles = max_rendered_particles(&device.limits());
+    if particles.len() > max_particles {
+        println!("  broken: the device can only render {max_particles} particles.");
+    }
+
+    commands.insert_resource(PhysicsContext::with_select_coupling(
+        device.wgpu_device(),
+        params,
+        particles,
+        &rapier.rigidbody_set.bodies,
+        &rapier.colliders.colliders,
+        coupling,
+        cell_width,
+        stress.grid_capacity,
+    ));
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_stress(
+    mut commands: Commands,
+    mut stress: ResMut<Stress>,
+    mut app_state: ResMut<AppState>,
+    time: Res<Time<Real>>,
+    timings: Res<Timestamps>,
+    physics: Option<Res<PhysicsContext>>,
+    mut diagnostics: ResMut<SimulationDiagnostics>,
+    mut device_lost: EventReader<DeviceLost>,
+    instances: Query<Entity, With<InstanceMaterialData>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    if let Some(lost) = device_lost.read().last() {
+        println!("  broken: the device was lost ({}).", lost.message);
+        exit.send(AppExit::error());
+        return;
+    }
+
+    let Some(physics) = physics else {
+        return;
+    };
+
+    stress.num_frames += 1;
+    if stress.num_frames <= WARMUP_FRAMES {
+        stress.start_step = app_state.num_steps;
+        return;
+    }
+
+    stress.measured_time += time.delta_secs_f64();
+    if timings.last_updated_step > stress.start_step
+        && timings.last_updated_step > stress.last_sample_step
+    {
+        stress.last_sample_step = timings.last_updated_step;
+        stress.num_samples += 1;
+        for (total, stage) in stress.stage_totals.iter_mut().zip(timings.stages()) {
+            *total += stage;
+        }
+    }
+
+    let unstable = diagnostics.num_non_finite > 0;
+    if stress.num_frames < WARMUP_FRAMES + MEASURED_FRAMES && !unstable {
+        return;
+    }
+
+    let measured_frames = stress.num_frames - WARMUP_FRAMES;
+    println!(
+        "  {:.1} FPS over {measured_frames} frames, {} substeps per frame",
+        measured_frames as f64 / stress.measured_time,
+        app_state.num_substeps
+    );
+    if stress.num_samples == 0 {
+        println!("  No GPU timings (timestamp queries unsupported by this device).");
+    } else {
+        for (name, total) in Timestamps::STAGE_NAMES.iter().zip(stress.stage_totals) {
+            println!("  {name}: {:.3}ms", total / stress.num_samples as f64);
+        }
+        println!(
+            "  total: {:.3}ms",
+            stress.stage_totals.iter().sum::<f64>() / stress.num_samples as f64
+        );
+    }
+    if unstable {
+        println!(
+            "  broken: {} of the {} particles became non-finite (is the grid capacity too small?).",
+            diagnostics.num_non_finite,
+            physics.num_particles()
+        );
+    }
+
+    // Move on to the next particle count.
+    *stress = Stress {
+        grid_capacity: stress.grid_capacity,
+        level: stress.level + 1,
+        ..Default::default()
+    };
+    if stress.level == PARTICLE_COUNTS.len() {
+        exit.send(AppExit::Success);
+        return;
+    }
+
+    diagnostics.num_non_finite = 0;
+    app_state.particles_initialized = false;
+    commands.remove_resource::<PhysicsContext>();
+    for entity in &instances {
+        commands.entity(entity).despawn();
+    }
+}
@@ -0,0 +1,130 @@
+//! Resetting the simulation automatically, e.g. for looping demos.
+
+use crate::events::ResetSimulation;
+use crate::readback::ParticleReadback;
+use crate::region::ParticleRegion;
+use crate::resources::{AppState, PhysicsContext, RunState};
+use bevy::prelude::*;
+use std::time::Duration;
+
+/// The condition triggering an [`AutoReset`].
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum AutoResetCondition {
+    /// The speed of every particle is below `max_speed` (in m/s).
+    Settled { max_speed: f32 },
+    /// No particle is inside of `domain` anymore.
+    LeftDomain { domain: ParticleRegion },
+}
+
+impl AutoResetCondition {
+    /// Whether the condition holds for the given particle samples.
+    pub fn holds(&self, readback: &ParticleReadback) -> bool {
+        match self {
+            Self::Settled { max_speed } => readback
+                .samples
+                .iter()
+                .all(|s| Vec3::from(s.velocity).length_squared() <= max_speed * max_speed),
+            Self::LeftDomain { domain } => !readback
+                .samples
+                .iter()
+                .any(|s| domain.contains(Vec3::from(s.position))),
+        }
+    }
+}
+
+/// Opt-in reset of the simulation once `condition` held for `delay`, so showcase setups loop
+/// by themselves.
+///
+/// While enabled, the [`ParticleReadback`] is requested every frame, and the condition is checked against
+/// each new readback. The reset goes through the [`ResetSimulation`] event, so the systems
+/// setting the simulation up run again. The delay is measured in real time, and doesn’t advance
+/// while the simulation is paused.
+#[derive(Resource, Clone, Debug)]
+pub struct AutoReset {
+    pub enabled: bool,
+    pub condition: AutoResetCondition,
+    /// How long the condition must hold before resetting.
+    pub delay: Duration,
+    holds: bool,
+    elapsed: Duration,
+}
+
+impl Default for AutoReset {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            condition: AutoResetCondition::Settled { max_speed: 0.01 },
+            delay: Duration::from_secs(3),
+            holds: false,
+            elapsed: Duration::ZERO,
+        }
+    }
+}
+
+impl AutoReset {
+    pub fn new(condition: AutoResetCondition, delay: Duration) -> Self {
+        Self {
+            enabled: true,
+            condition,
+            delay,
+            ..Default::default()
+        }
+    }
+
+    /// How long the condition has held so far.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+}
+
+pub fn check_auto_reset(
+    time: Res<Time>,
+    app_state: Res<AppState>,
+    physics: Option<Res<PhysicsContext>>,
+    mut auto_reset: ResMut<AutoReset>,
+    mut readback: ResMut<ParticleReadback>,
+    mut resets: EventWriter<ResetSimulation>,
+    mut last_readback: Local<u64>,
+) {
+    if !auto_reset.enabled {
+        return;
+    }
+
+    readback.request();
+
+    let Some(physics) = physics else {
+        return;
+    };
+
+    // Don’t let the readbacks of the previous simulation trigger another reset.
+    if physics.is_added() {
+        auto_reset.holds = false;
+        auto_reset.elapsed = Duration::ZERO;
+        *last_readback = readback.num_readbacks();
+        return;
+    }
+
+    if readback.num_readbacks() != *last_readback {
+        *last_readback = readback.num_readbacks();
+        let holds = !readback.samples.is_empty() && auto_reset.condition.holds(&readback);
+        auto_reset.holds = holds;
+        if !holds {
+            auto_reset.elapsed = Duration::ZERO;
+        }
+    }
+
+    if !auto_reset.holds || app_state.run_state == RunState::Paused {
+        return;
+    }
+
+    auto_reset.elapsed += time.delta();
+    if auto_reset.elapsed >= auto_reset.delay {
+        debug!(
+            "The auto-reset condition held for {:?}, resetting.",
+            auto_reset.delay
+        );
+        auto_reset.holds = false;
+        auto_reset.elapsed = Duration::ZERO;
+        resets.send(ResetSimulation);
+    }
+}
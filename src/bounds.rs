@@ -0,0 +1,109 @@
+//! Axis-aligned domain boundaries, for simulations without coupled colliders.
+
+use crate::resources::PhysicsContext;
+use crate::step::SubstepHook;
+use bevy::render::renderer::RenderDevice;
+use nalgebra::Vector3;
+use wgcore::Shader;
+use wgcore::kernel::{KernelInvocationBuilder, KernelInvocationQueue};
+use wgcore::tensor::GpuScalar;
+use wgebra::WgSvd2;
+use wgebra::WgSvd3;
+use wgpu::{BufferUsages, CommandEncoder, ComputePipeline, Queue};
+use wgsparkl3d::grid::grid::WgGrid;
+use wgsparkl3d::solver::{GpuParticles, WgParticle};
+
+/// The GPU representation of the box of [`SimulationBounds`].
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Copy, Clone, PartialEq, Debug, Default)]
+#[repr(C)]
+pub struct GpuBounds {
+    mins: [f32; 4],
+    maxs: [f32; 4],
+}
+
+impl GpuBounds {
+    fn new(mins: Vector3<f32>, maxs: Vector3<f32>) -> Self {
+        Self {
+            mins: [mins.x, mins.y, mins.z, 0.0],
+            maxs: [maxs.x, maxs.y, maxs.z, 0.0],
+        }
+    }
+}
+
+#[derive(Shader)]
+#[shader(
+    src = "bounds3d.wgsl",
+    derive(WgParticle, WgGrid, WgSvd2, WgSvd3),
+    composable = false
+)]
+pub struct WgBounds {
+    main: ComputePipeline,
+}
+
+impl WgBounds {
+    pub fn queue<'a>(
+        &'a self,
+        queue: &mut KernelInvocationQueue<'a>,
+        particles: &GpuParticles,
+        bounds: &GpuScalar<GpuBounds>,
+    ) {
+        KernelInvocationBuilder::new(queue, &self.main)
+            .bind0([
+                particles.positions.buffer(),
+                particles.velocities.buffer(),
+                bounds.buffer(),
+            ])
+            .queue(particles.positions.len().div_ceil(64) as u32);
+    }
+}
+
+/// Keeps the particles inside of an axis-aligned box, e.g., as the floor of a simulation
+/// without any coupled collider.
+///
+/// After each substep, the particles outside of the box are projected back onto it, and their
+/// velocity components pointing outside are cancelled (so the walls are sticky along their
+/// normal, and frictionless along their tangent). Register it as a [`SubstepHook`].
+pub struct SimulationBounds {
+    device: RenderDevice,
+    kernel: WgBounds,
+    bounds: GpuScalar<GpuBounds>,
+}
+
+impl SimulationBounds {
+    pub fn new(device: &RenderDevice, mins: Vector3<f32>, maxs: Vector3<f32>) -> Self {
+        let wgpu_device = device.wgpu_device();
+        Self {
+            device: device.clone(),
+            kernel: WgBounds::from_device(wgpu_device).unwrap(),
+            bounds: GpuScalar::init(
+                wgpu_device,
+                GpuBounds::new(mins, maxs),
+                BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            ),
+        }
+    }
+
+    /// An unbounded domain, except for a floor at the height `y`.
+    pub fn floor(device: &RenderDevice, y: f32) -> Self {
+        let inf = f32::MAX;
+        Self::new(device, Vector3::new(-inf, y, -inf), Vector3::repeat(inf))
+    }
+
+    /// Changes the box, taking effect at the next step.
+    pub fn set_bounds(&self, queue: &Queue, mins: Vector3<f32>, maxs: Vector3<f32>) {
+        queue.write_buffer(
+            self.bounds.buffer(),
+            0,
+            bytemuck::bytes_of(&GpuBounds::new(mins, maxs)),
+        );
+    }
+}
+
+impl SubstepHook for SimulationBounds {
+    fn on_substep(&mut self, ctx: &mut PhysicsContext, encoder: &mut CommandEncoder, _: usize) {
+        let mut queue = KernelInvocationQueue::new(self.device.wgpu_device());
+        self.kernel
+            .queue(&mut queue, &ctx.data.particles, &self.bounds);
+        queue.encode(encoder, None);
+    }
+}
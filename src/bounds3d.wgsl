@@ -0,0 +1,35 @@
+#define_import_path wgsparkl::examples::bounds
+
+#import wgsparkl::solver::particle as Particle;
+
+@group(0) @binding(0)
+var<storage, read_write> particles_pos: array<Particle::Position>;
+@group(0) @binding(1)
+var<storage, read_write> particles_vel: array<Particle::Velocity>;
+@group(0) @binding(2)
+var<storage, read> bounds: Bounds;
+
+struct Bounds {
+    mins: vec3<f32>,
+    maxs: vec3<f32>,
+}
+
+@compute @workgroup_size(64, 1, 1)
+fn main(
+    @builtin(global_invocation_id) tid: vec3<u32>,
+) {
+    let particle_id = tid.x;
+
+    if particle_id < arrayLength(&particles_pos) {
+        let pt = particles_pos[particle_id].pt;
+        let clamped = clamp(pt, bounds.mins, bounds.maxs);
+        var vel = particles_vel[particle_id].v;
+
+        // Cancel the velocity components moving the particle further outside.
+        vel = select(vel, max(vel, vec3(0.0)), pt < bounds.mins);
+        vel = select(vel, min(vel, vec3(0.0)), pt > bounds.maxs);
+
+        particles_pos[particle_id].pt = clamped;
+        particles_vel[particle_id].v = vel;
+    }
+}
@@ -0,0 +1,53 @@
+//! Camera helpers for presenting the particles.
+
+use bevy::prelude::*;
+use bevy::render::camera::ScalingMode;
+
+/// The axis-aligned bounding box of `points`, or `None` if there are no points.
+///
+/// The points can come from the initial particles, or from a
+/// [`ParticleReadback`](crate::readback::ParticleReadback) to frame the current state.
+pub fn points_aabb(points: impl IntoIterator<Item = Vec3>) -> Option<(Vec3, Vec3)> {
+    points.into_iter().fold(None, |aabb, pt| match aabb {
+        None => Some((pt, pt)),
+        Some((mins, maxs)) => Some((mins.min(pt), maxs.max(pt))),
+    })
+}
+
+/// An orthographic 3D camera looking along `view_dir`, framing the box from `mins` to `maxs`.
+///
+/// Useful for side views of slab-like scenes (dam breaks, piles, etc.). `margin` is added
+/// around the box, in world units. The camera’s up direction is `+Y`, or `-Z` when looking
+/// along the Y axis.
+pub fn orthographic_camera_framing(
+    mins: Vec3,
+    maxs: Vec3,
+    view_dir: Dir3,
+    margin: f32,
+) -> (Camera3d, Projection, Transform) {
+    let up = if view_dir.dot(Vec3::Y).abs() > 0.99 {
+        Vec3::NEG_Z
+    } else {
+        Vec3::Y
+    };
+    let center = (mins + maxs) / 2.0;
+    let extents = maxs - mins;
+    let right = view_dir.cross(up).normalize();
+    let up = right.cross(*view_dir);
+
+    // Place the camera outside of the box so nothing gets clipped by the near plane.
+    let depth = extents.dot(view_dir.abs());
+    let transform = Transform::from_translation(center - *view_dir * (depth / 2.0 + margin + 1.0))
+        .looking_to(view_dir, up);
+
+    let projection = Projection::Orthographic(OrthographicProjection {
+        scaling_mode: ScalingMode::AutoMin {
+            min_width: extents.dot(right.abs()) + margin * 2.0,
+            min_height: extents.dot(up.abs()) + margin * 2.0,
+        },
+        far: depth + margin * 2.0 + 2.0,
+        ..OrthographicProjection::default_3d()
+    });
+
+    (Camera3d::default(), projection, transform)
+}
@@ -0,0 +1,74 @@
+//! Solid rendering of the coupled colliders, without Rapier’s debug renderer.
+
+use crate::components::MpmCouplingEnabled;
+use crate::resources::AppState;
+use bevy::prelude::*;
+use bevy_rapier3d::geometry::{Collider, ColliderView};
+
+/// Marks the coupled colliders given a mesh by [`update_coupled_collider_meshes`].
+#[derive(Component, Copy, Clone, Debug, Default)]
+pub struct MpmColliderMesh;
+
+/// The mesh representing a collider shape, in the local space of the collider.
+///
+/// Only cuboids, balls, capsules and cylinders are supported.
+pub fn collider_mesh(collider: &Collider) -> Option<Mesh> {
+    match collider.as_typed_shape() {
+        ColliderView::Cuboid(cuboid) => Some(Cuboid::from_size(cuboid.half_extents() * 2.0).into()),
+        ColliderView::Ball(ball) => Some(Sphere::new(ball.radius()).into()),
+        ColliderView::Capsule(capsule) => {
+            let (a, b) = (capsule.segment().a(), capsule.segment().b());
+            let mesh: Mesh = Capsule3d::new(capsule.radius(), a.distance(b) / 2.0).into();
+            // The capsule mesh is aligned with the Y axis.
+            let rotation = Quat::from_rotation_arc(Vec3::Y, (b - a).normalize_or(Vec3::Y));
+            Some(
+                mesh.transformed_by(
+                    Transform::from_translation((a + b) / 2.0).with_rotation(rotation),
+                ),
+            )
+        }
+        ColliderView::Cylinder(cylinder) => {
+            Some(Cylinder::new(cylinder.radius(), cylinder.half_height() * 2.0).into())
+        }
+        _ => None,
+    }
+}
+
+/// Gives a mesh to the colliders with [`MpmCouplingEnabled`] while
+/// [`AppState::render_coupled_colliders`] is enabled, and removes them once it is disabled.
+///
+/// The mesh is added to the collider entity itself, so it follows its pose. Entities that already
+/// have a [`Mesh3d`] are left untouched, as well as unsupported shapes (see [`collider_mesh`]).
+pub fn update_coupled_collider_meshes(
+    mut commands: Commands,
+    app_state: Res<AppState>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    colliders: Query<(Entity, &Collider), (With<MpmCouplingEnabled>, Without<Mesh3d>)>,
+    rendered: Query<Entity, With<MpmColliderMesh>>,
+    mut material: Local<Option<Handle<StandardMaterial>>>,
+) {
+    if !app_state.render_coupled_colliders {
+        for entity in &rendered {
+            commands
+                .entity(entity)
+                .remove::<(MpmColliderMesh, Mesh3d, MeshMaterial3d<StandardMaterial>)>();
+        }
+        return;
+    }
+
+    let material = material
+        .get_or_insert_with(|| materials.add(Color::srgb(0.6, 0.6, 0.65)))
+        .clone();
+
+    for (entity, collider) in &colliders {
+        let Some(mesh) = collider_mesh(collider) else {
+            continue;
+        };
+        commands.entity(entity).insert((
+            MpmColliderMesh,
+            Mesh3d(meshes.add(mesh)),
+            MeshMaterial3d(material.clone()),
+        ));
+    }
+}
@@ -0,0 +1,109 @@
+//! Custom particle coloring evaluated on the CPU.
+
+use crate::instancing3d::InstanceMaterialData;
+use crate::prep_vertex_buffer::RenderMode;
+use crate::readback::{ParticleReadback, ParticleSample};
+use crate::resources::{AppState, RunState};
+use bevy::math::Vec3;
+use bevy::prelude::*;
+use bevy::render::renderer::RenderQueue;
+use std::sync::Arc;
+
+/// The particle data available to a [`CustomColoring`] callback.
+#[derive(Copy, Clone, Debug)]
+pub struct ParticleView {
+    pub index: usize,
+    pub position: Vec3,
+    pub velocity: Vec3,
+    /// The base color assigned to the particle when its graphics were created (linear RGBA).
+    pub base_color: [f32; 4],
+}
+
+impl ParticleView {
+    pub fn from_sample(index: usize, sample: &ParticleSample, base_color: [f32; 4]) -> Self {
+        Self {
+            index,
+            position: Vec3::from(sample.position),
+            velocity: Vec3::from(sample.velocity),
+            base_color,
+        }
+    }
+}
+
+/// A user callback computing the (linear RGBA) color of each particle in [`RenderMode::Custom`].
+///
+/// The callback runs on the CPU on the latest [`ParticleReadback`] samples, so the colors are
+/// at least one frame behind the simulation. Each refresh costs a full particle readback plus a
+/// full upload of the instance buffer, which is much more expensive than the GPU-side render
+/// modes; prefer these for large simulations.
+#[derive(Clone)]
+pub struct CustomColoring(pub Arc<dyn Fn(&ParticleView) -> [f32; 4] + Send + Sync>);
+
+impl CustomColoring {
+    pub fn new(f: impl Fn(&ParticleView) -> [f32; 4] + Send + Sync + 'static) -> Self {
+        Self(Arc::new(f))
+    }
+}
+
+/// Recomputes the particle colors with [`AppState::custom_coloring`] whenever a new readback
+/// is available.
+///
+/// This requests the [`ParticleReadback`] automatically while the custom coloring is in use.
+///
+/// The whole instance buffer is uploaded from its CPU copy, so this runs before the step: the
+/// vertex preparation pass then overwrites the stale positions, deformations and velocities.
+/// While that pass doesn’t run (paused without [`AppState::render_while_paused`]), the colors
+/// aren’t refreshed.
+pub fn apply_custom_coloring(
+    render_queue: Res<RenderQueue>,
+    app_state: Res<AppState>,
+    mut readback: ResMut<ParticleReadback>,
+    mut instances: Query<&mut InstanceMaterialData>,
+    mut last_readback: Local<u64>,
+) {
+    let Some(coloring) = &app_state.custom_coloring else {
+        return;
+    };
+
+    if app_state.render_config.colors_frozen() {
+        return;
+    }
+
+    if RenderMode::from_u32(app_state.render_config.mode) != RenderMode::Custom {
+        return;
+    }
+
+    readback.request();
+
+    if app_state.run_state == RunState::Paused && !app_state.render_while_paused {
+        return;
+    }
+
+    if readback.num_readbacks() == *last_readback {
+        return; // No new data since the last update.
+    }
+    *last_readback = readback.num_readbacks();
+
+    let Ok(mut instances) = instances.get_single_mut() else {
+        return;
+    };
+
+    let render_stride = instances.render_stride;
+    for (i, (instance, sample)) in instances
+        .data
+        .iter_mut()
+        .zip(readback.samples.iter().step_by(render_stride))
+        .enumerate()
+    {
+        instance.color = coloring.0(&ParticleView::from_sample(
+            i * render_stride,
+            sample,
+            instance.base_color,
+        ));
+    }
+    render_queue.write_buffer(
+        &instances.buffer.buffer,
+        0,
+        bytemuck::cast_slice(&instances.data),
+    );
+}
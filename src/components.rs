@@ -1,4 +1,38 @@
 use bevy::prelude::Component;
 
+/// Couples the collider on the same entity with the particles.
+///
+/// NOTE: there is no per-collider friction. The coupling entries only hold the body, the collider
+/// and the [`BodyCoupling`](wgrapier3d::dynamics::body::BodyCoupling) mode, and the solver
+/// doesn’t read the friction coefficient of the Rapier colliders: the friction between the
+/// particles and the colliders is fixed by the solver. The friction of granular materials
+/// piling against a collider can only be changed through their own plasticity (see
+/// [`DruckerPrager`](wgsparkl3d::models::DruckerPrager)).
 #[derive(Component)]
 pub struct MpmCouplingEnabled;
+
+/// Marks the Rapier context the particles are coupled with.
+///
+/// This is added automatically to the context selected by
+/// [`WgSparklPlugin::rapier_context`](crate::WgSparklPlugin::rapier_context), or to the default
+/// Rapier context.
+#[derive(Component)]
+pub struct MpmRapierContext;
+
+/// Detects the particles overlapping this sensor collider.
+///
+/// While the particle readback completes, the number of particles inside the collider is
+/// updated, and [`ParticleSensorEvent`](crate::events::ParticleSensorEvent)s are sent when the
+/// first particle enters it, or the last one leaves it. Intended for Rapier sensors, which aren’t
+/// coupled with the particles.
+#[derive(Component, Default, Clone, Debug)]
+pub struct MpmParticleSensor {
+    pub(crate) num_particles: usize,
+}
+
+impl MpmParticleSensor {
+    /// The number of particles inside the collider, as of the latest particle readback.
+    pub fn num_particles(&self) -> usize {
+        self.num_particles
+    }
+}
@@ -0,0 +1,209 @@
+use crate::components::MpmRapierContext;
+use bevy::prelude::*;
+use bevy_rapier3d::plugin::{DefaultRapierContext, RapierConfiguration};
+use std::collections::HashSet;
+use wgrapier3d::dynamics::body::BodyCouplingEntry;
+use wgsparkl3d::rapier::geometry::{ColliderHandle, ColliderSet, Shape, SharedShape};
+
+/// The Rapier context selected through [`WgSparklPlugin::rapier_context`](crate::WgSparklPlugin::rapier_context).
+#[derive(Resource, Copy, Clone, Default, Debug)]
+pub struct RapierContextSelection(pub Option<Entity>);
+
+/// Adds the [`MpmRapierContext`] marker to the Rapier context the particles are coupled with.
+///
+/// If no context is marked already, this picks the one from [`RapierContextSelection`], or the
+/// default Rapier context otherwise.
+pub fn select_rapier_context(
+    mut commands: Commands,
+    selection: Res<RapierContextSelection>,
+    selected: Query<(), With<MpmRapierContext>>,
+    contexts: Query<(Entity, Has<DefaultRapierContext>), With<RapierConfiguration>>,
+    mut warned: Local<bool>,
+) {
+    if !selected.is_empty() || contexts.is_empty() {
+        return;
+    }
+
+    if let Some(entity) = selection.0 {
+        if contexts.contains(entity) {
+            commands.entity(entity).insert(MpmRapierContext);
+        } else if !*warned {
+            warn!(
+                "The entity {:?} selected as the particles’ Rapier context doesn’t have a Rapier \
+                 context (yet?). The simulation will not step until it does.",
+                entity
+            );
+            *warned = true;
+        }
+        return;
+    }
+
+    let default_context = contexts
+        .iter()
+        .find_map(|(entity, is_default)| is_default.then_some(entity));
+
+    if contexts.iter().len() > 1 && !*warned {
+        warn!(
+            "Multiple Rapier contexts exist but none was selected for coupling with the particles. \
+             Set `WgSparklPlugin::rapier_context` or add the `MpmRapierContext` component to the \
+             context to use.{}",
+            if default_context.is_some() {
+                " Falling back to the default context."
+            } else {
+                " The simulation will not step."
+            }
+        );
+        *warned = true;
+    }
+
+    if let Some(entity) = default_context {
+        commands.entity(entity).insert(MpmRapierContext);
+    }
+}
+
+/// Removes the sensor colliders from `coupling`, logging a warning for each of them.
+///
+/// Sensors are trigger volumes, so the particles must flow through them instead of treating them
+/// as solid walls. Use [`MpmParticleSensor`](crate::components::MpmParticleSensor) to detect
+/// particles entering a sensor instead.
+pub fn skip_sensor_colliders(
+    colliders: &ColliderSet,
+    mut coupling: Vec<BodyCouplingEntry>,
+) -> Vec<BodyCouplingEntry> {
+    coupling.retain(|entry| {
+        let is_sensor = colliders
+            .get(entry.collider)
+            .is_some_and(|collider| collider.is_sensor());
+        if is_sensor {
+            warn_skipped_sensor(entry.collider);
+        }
+        !is_sensor
+    });
+    coupling
+}
+
+/// Removes the compound colliders from `coupling`, logging an error for each of them.
+///
+/// Each coupling entry is uploaded to the GPU as a single shape with a single pose, so compound
+/// shapes can’t be coupled. Attach each part as its own collider instead (e.g., one child entity
+/// with a [`Collider`](bevy_rapier3d::geometry::Collider) per part, all under the same
+/// rigid-body), and couple each of them.
+pub fn skip_compound_colliders(
+    colliders: &ColliderSet,
+    mut coupling: Vec<BodyCouplingEntry>,
+) -> Vec<BodyCouplingEntry> {
+    coupling.retain(|entry| {
+        let is_compound = colliders
+            .get(entry.collider)
+            .is_some_and(|collider| collider.shape().as_compound().is_some());
+        if is_compound {
+            error_skipped_compound(entry.collider);
+        }
+        !is_compound
+    });
+    coupling
+}
+
+/// Removes the sensor and compound colliders from `coupling`, like [`skip_sensor_colliders`] and
+/// [`skip_compound_colliders`].
+///
+/// Only the colliders missing from `reported` are logged, then added to it, so this can run
+/// every frame without flooding the log.
+pub fn skip_unsupported_colliders(
+    colliders: &ColliderSet,
+    mut coupling: Vec<BodyCouplingEntry>,
+    reported: &mut HashSet<ColliderHandle>,
+) -> Vec<BodyCouplingEntry> {
+    coupling.retain(|entry| {
+        let Some(collider) = colliders.get(entry.collider) else {
+            return true;
+        };
+        let is_sensor = collider.is_sensor();
+        let is_compound = collider.shape().as_compound().is_some();
+        if is_sensor && reported.insert(entry.collider) {
+            warn_skipped_sensor(entry.collider);
+        } else if is_compound && reported.insert(entry.collider) {
+            error_skipped_compound(entry.collider);
+        }
+        !is_sensor && !is_compound
+    });
+    coupling
+}
+
+fn warn_skipped_sensor(collider: ColliderHandle) {
+    warn!(
+        "The sensor collider {:?} is in the coupling set. Sensors aren’t coupled with the \
+         particles, skipping it.",
+        collider
+    );
+}
+
+fn error_skipped_compound(collider: ColliderHandle) {
+    error!(
+        "The compound collider {:?} is in the coupling set. Compound shapes aren’t supported, \
+         skipping it: couple each of its parts as a separate collider instead.",
+        collider
+    );
+}
+
+/// A copy of `colliders` where the shapes of the colliders listed in `coupling` are inflated by
+/// `margin` (or deflated if `margin` is negative).
+///
+/// This is what [`PhysicsContext::set_coupling_margin`](crate::resources::PhysicsContext::set_coupling_margin)
+/// applies at each rebuild of the coupling buffers, prefer it so the margin isn’t lost when the
+/// coupling changes. The particles collide with colliders at their centers, so they visibly sink
+/// into colliders by up to their radius
+/// ([`ParticleMassProps::init_radius`](wgsparkl3d::solver::ParticleMassProps::init_radius)):
+/// a margin equal to that radius makes the particle surfaces rest on the collider surfaces.
+///
+/// Only balls, cuboids, capsules, cylinders, and cones can be inflated. Other shapes are kept
+/// as-is, with a warning.
+pub fn inflate_coupled_colliders(
+    colliders: &ColliderSet,
+    coupling: &[BodyCouplingEntry],
+    margin: f32,
+) -> ColliderSet {
+    let mut result = colliders.clone();
+
+    for entry in coupling {
+        let Some(collider) = result.get_mut(entry.collider) else {
+            continue;
+        };
+
+        match inflated_shape(collider.shape(), margin) {
+            Some(shape) => collider.set_shape(shape),
+            None => warn!(
+                "The coupling margin isn’t supported for {:?} shapes, ignoring it.",
+                collider.shape().shape_type()
+            ),
+        }
+    }
+
+    result
+}
+
+fn inflated_shape(shape: &dyn Shape, margin: f32) -> Option<SharedShape> {
+    let inflate = |x: f32| (x + margin).max(0.0);
+
+    if let Some(ball) = shape.as_ball() {
+        Some(SharedShape::ball(inflate(ball.radius)))
+    } else if let Some(cuboid) = shape.as_cuboid() {
+        let he = cuboid.half_extents.map(inflate);
+        Some(SharedShape::cuboid(he.x, he.y, he.z))
+    } else if let Some(capsule) = shape.as_capsule() {
+        Some(SharedShape::capsule(
+            capsule.segment.a,
+            capsule.segment.b,
+            inflate(capsule.radius),
+        ))
+    } else if let Some(cylinder) = shape.as_cylinder() {
+        Some(SharedShape::cylinder(
+            inflate(cylinder.half_height),
+            inflate(cylinder.radius),
+        ))
+    } else {
+        shape
+            .as_cone()
+            .map(|cone| SharedShape::cone(inflate(cone.half_height), inflate(cone.radius)))
+    }
+}
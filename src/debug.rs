@@ -0,0 +1,343 @@
+//! Debug visualizations of the simulation.
+
+use crate::components::MpmRapierContext;
+use crate::events::{DeterminismReport, ToggleTimingOverlay};
+use crate::readback::ParticleReadback;
+use crate::resources::{AppState, PhysicsContext, RunState, Timestamps};
+use crate::step::coupled_body_velocity;
+use bevy::color::palettes::css;
+use bevy::prelude::*;
+use bevy::render::renderer::RenderDevice;
+use bevy_rapier3d::plugin::ReadRapierContext;
+
+/// Draws the velocity uploaded to the GPU for each coupled body, when
+/// [`AppState::debug_coupling_velocity`] is enabled.
+///
+/// The linear velocity is drawn in yellow, and the angular velocity (along the rotation axis) in
+/// magenta, both starting from the body’s center of mass.
+pub fn draw_coupling_velocities(
+    mut gizmos: Gizmos,
+    app_state: Res<AppState>,
+    physics: Option<Res<PhysicsContext>>,
+    rapier: ReadRapierContext<With<MpmRapierContext>>,
+) {
+    if !app_state.debug_coupling_velocity || rapier.rapier_context.is_empty() {
+        return;
+    }
+
+    let Some(physics) = physics else {
+        return;
+    };
+
+    let rapier = rapier.single();
+    let dt = rapier.simulation.integration_parameters.dt;
+
+    for coupling in physics.data.coupling() {
+        let Some(rb) = rapier.rigidbody_set.bodies.get(coupling.body) else {
+            continue;
+        };
+        let vel = coupled_body_velocity(rb, dt, app_state.num_substeps);
+        let com = rb.center_of_mass();
+        let start = Vec3::new(com.x, com.y, com.z);
+        let linear = Vec3::new(vel.linear.x, vel.linear.y, vel.linear.z);
+        let angular = Vec3::new(vel.angular.x, vel.angular.y, vel.angular.z);
+
+        gizmos.arrow(start, start + linear, css::YELLOW);
+        gizmos.arrow(start, start + angular, css::MAGENTA);
+    }
+}
+
+/// Runs the simulation twice from its initial state, and compares the particle positions after
+/// `num_steps` steps of each run, sending a [`DeterminismReport`] with the result.
+///
+/// This surfaces the non-determinism of the GPU solver (see [`AppState::deterministic`]). The
+/// initial state is the CPU-side copy of the particles, so the [`PhysicsContext`] must be built
+/// with [`PhysicsContext::with_cpu_particles`]. The rigid-bodies aren’t reset between the runs,
+/// so the scene should only contain fixed coupled colliders.
+///
+/// While enabled, the simulation is driven by this check: the particles are reset at the start
+/// of each run, and the simulation is paused at its end until a new readback arrives. Both runs
+/// step with [`AppState::deterministic`] set, so they simulate the same fixed `dt`; its previous
+/// value is restored when the check ends. The simulation is left paused and the check is disabled
+/// once the report is sent.
+#[derive(Resource, Clone, Debug)]
+pub struct DeterminismCheck {
+    pub enabled: bool,
+    /// The number of steps of each run.
+    pub num_steps: u64,
+    phase: DeterminismPhase,
+    first_run: Vec<Vec3>,
+    /// The [`AppState::deterministic`] setting to restore at the end of the check.
+    prev_deterministic: bool,
+}
+
+impl Default for DeterminismCheck {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            num_steps: 60,
+            phase: DeterminismPhase::Idle,
+            first_run: vec![],
+            prev_deterministic: false,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum DeterminismPhase {
+    Idle,
+    /// The run is stepping until `end_step`.
+    Running {
+        run: usize,
+        end_step: u64,
+    },
+    /// The run ended, waiting for a readback of its final state.
+    Reading {
+        run: usize,
+        readback: u64,
+    },
+}
+
+pub fn check_determinism(
+    device: Res<RenderDevice>,
+    mut check: ResMut<DeterminismCheck>,
+    mut app_state: ResMut<AppState>,
+    physics: Option<ResMut<PhysicsContext>>,
+    mut readback: ResMut<ParticleReadback>,
+    mut reports: EventWriter<DeterminismReport>,
+) {
+    if !check.enabled {
+        if check.phase != DeterminismPhase::Idle {
+            // Disabled during the check.
+            app_state.deterministic = check.prev_deterministic;
+            check.phase = DeterminismPhase::Idle;
+        }
+        return;
+    }
+
+    let Some(mut physics) = physics else {
+        return;
+    };
+
+    readback.request();
+
+    let num_steps = check.num_steps;
+    let start_run = |run: usize, app_state: &mut AppState, physics: &mut PhysicsContext| {
+        let particles = physics.particles()?.to_vec();
+        physics.reset_particles(device.wgpu_device(), &particles);
+        app_state.run_state = RunState::Running;
+        Some(DeterminismPhase::Running {
+            run,
+            end_step: app_state.num_steps + num_steps,
+        })
+    };
+
+    match check.phase {
+        DeterminismPhase::Idle => {
+            let Some(phase) = start_run(0, &mut app_state, &mut physics) else {
+                warn!("The determinism check needs the CPU-side particles, disabling it.");
+                check.enabled = false;
+                return;
+            };
+            check.prev_deterministic = app_state.deterministic;
+            app_state.deterministic = true;
+            check.phase = phase;
+        }
+        DeterminismPhase::Running { run, end_step } => {
+            if app_state.num_steps >= end_step {
+                app_state.run_state = RunState::Paused;
+                // The readback in flight may have started before the end of the run, so wait
+                // for the next one.
+                check.phase = DeterminismPhase::Reading {
+                    run,
+                    readback: readback.num_readbacks() + 2,
+                };
+            }
+        }
+        DeterminismPhase::Reading { run, readback: num } => {
+            if readback.num_readbacks() < num {
+                return;
+            }
+
+            let positions: Vec<Vec3> = readback
+                .samples
+                .iter()
+                .map(|sample| Vec3::from(sample.position))
+                .collect();
+
+            if run == 0 {
+                check.first_run = positions;
+                if let Some(phase) = start_run(1, &mut app_state, &mut physics) {
+                    check.phase = phase;
+                }
+                return;
+            }
+
+            let differences: Vec<f32> = check
+                .first_run
+                .iter()
+                .zip(&positions)
+                .map(|(a, b)| a.distance(*b))
+                .collect();
+            let report = DeterminismReport {
+                num_steps,
+                max_position_difference: differences.iter().copied().fold(0.0, f32::max),
+                num_different: differences.iter().filter(|d| **d != 0.0).count(),
+            };
+            info!("{report:?}");
+            reports.send(report);
+
+            app_state.deterministic = check.prev_deterministic;
+            check.enabled = false;
+            check.phase = DeterminismPhase::Idle;
+            check.first_run = vec![];
+        }
+    }
+}
+
+/// An overlay drawing the GPU time of each stage of the latest step as a stacked bar, with a
+/// legend.
+///
+/// It is hidden by default; show or hide it with [`ToggleTimingOverlay`] events, e.g., sent from
+/// a UI button or a key binding of the app. Nothing is drawn until the timings of a step were
+/// measured, so never without timestamp queries support.
+#[derive(Resource, Clone, Debug)]
+pub struct TimingOverlay {
+    pub enabled: bool,
+    /// The width of the bar segments, in pixels per millisecond.
+    pub pixels_per_ms: f32,
+}
+
+impl Default for TimingOverlay {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            pixels_per_ms: 40.0,
+        }
+    }
+}
+
+/// The color of each stage in the [`TimingOverlay`], in the order of [`Timestamps::STAGE_NAMES`].
+pub const STAGE_COLORS: [Color; 9] = [
+    Color::Srgba(css::TOMATO),
+    Color::Srgba(css::ORANGE),
+    Color::Srgba(css::GOLD),
+    Color::Srgba(css::YELLOW_GREEN),
+    Color::Srgba(css::MEDIUM_SEA_GREEN),
+    Color::Srgba(css::TURQUOISE),
+    Color::Srgba(css::DODGER_BLUE),
+    Color::Srgba(css::MEDIUM_PURPLE),
+    Color::Srgba(css::HOT_PINK),
+];
+
+/// The root UI node of the [`TimingOverlay`], e.g., for moving it around.
+#[derive(Component)]
+pub struct TimingOverlayRoot;
+
+#[derive(Component)]
+struct TimingOverlaySegment(usize);
+
+#[derive(Component)]
+struct TimingOverlayLabel(Option<usize>);
+
+pub fn toggle_timing_overlay(
+    mut events: EventReader<ToggleTimingOverlay>,
+    mut overlay: ResMut<TimingOverlay>,
+) {
+    for _ in events.read() {
+        overlay.enabled = !overlay.enabled;
+    }
+}
+
+pub fn update_timing_overlay(
+    mut commands: Commands,
+    overlay: Res<TimingOverlay>,
+    timestamps: Res<Timestamps>,
+    root: Query<Entity, With<TimingOverlayRoot>>,
+    mut segments: Query<(&TimingOverlaySegment, &mut Node)>,
+    mut labels: Query<(&TimingOverlayLabel, &mut Text)>,
+) {
+    // `Timestamps::timestamps` is taken while the timings of a step are processed, so check
+    // whether any timing was measured instead.
+    if !overlay.enabled || timestamps.last_updated_step == 0 {
+        for entity in &root {
+            commands.entity(entity).despawn_recursive();
+        }
+        return;
+    }
+
+    if root.is_empty() {
+        spawn_timing_overlay(&mut commands);
+        return;
+    }
+
+    let stages = timestamps.stages();
+    for (segment, mut node) in &mut segments {
+        node.width = Val::Px(stages[segment.0] as f32 * overlay.pixels_per_ms);
+    }
+    for (label, mut text) in &mut labels {
+        **text = match label.0 {
+            Some(i) => format!("{}: {:.3}ms", Timestamps::STAGE_NAMES[i], stages[i]),
+            None => format!("total: {:.3}ms", timestamps.total_time()),
+        };
+    }
+}
+
+fn spawn_timing_overlay(commands: &mut Commands) {
+    let font = TextFont {
+        font_size: 12.0,
+        ..default()
+    };
+
+    commands
+        .spawn((
+            TimingOverlayRoot,
+            Node {
+                position_type: PositionType::Absolute,
+                left: Val::Px(10.0),
+                bottom: Val::Px(10.0),
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(2.0),
+                ..default()
+            },
+        ))
+        .with_children(|root| {
+            root.spawn(Node {
+                height: Val::Px(16.0),
+                margin: UiRect::bottom(Val::Px(4.0)),
+                ..default()
+            })
+            .with_children(|bar| {
+                for (i, color) in STAGE_COLORS.into_iter().enumerate() {
+                    bar.spawn((
+                        TimingOverlaySegment(i),
+                        Node {
+                            height: Val::Percent(100.0),
+                            ..default()
+                        },
+                        BackgroundColor(color),
+                    ));
+                }
+            });
+
+            for (i, color) in STAGE_COLORS.into_iter().enumerate() {
+                root.spawn(Node {
+                    column_gap: Val::Px(4.0),
+                    align_items: AlignItems::Center,
+                    ..default()
+                })
+                .with_children(|row| {
+                    row.spawn((
+                        Node {
+                            width: Val::Px(10.0),
+                            height: Val::Px(10.0),
+                            ..default()
+                        },
+                        BackgroundColor(color),
+                    ));
+                    row.spawn((TimingOverlayLabel(Some(i)), Text::default(), font.clone()));
+                });
+            }
+            root.spawn((TimingOverlayLabel(None), Text::default(), font));
+        });
+}
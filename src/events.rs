@@ -0,0 +1,73 @@
+use bevy::prelude::{Entity, Event};
+
+/// Sent when the GPU device running the simulation is lost (driver reset, sleep/wake, etc.)
+///
+/// The simulation is paused and stops submitting work to the GPU. Since the pipelines and
+/// buffers are bound to the lost device, recovering requires removing the
+/// [`PhysicsContext`](crate::resources::PhysicsContext) and the [`AppState`](crate::resources::AppState),
+/// and setting them up again once a new device is available.
+#[derive(Event, Clone, Debug)]
+pub struct DeviceLost {
+    pub message: String,
+}
+
+/// Send this to restart the simulation from scratch.
+///
+/// The [`PhysicsContext`](crate::resources::PhysicsContext) and the particle graphics are removed
+/// before [`WgSparklSet::PrepareCoupling`](crate::WgSparklSet::PrepareCoupling), where user
+/// systems set the simulation up again since [`AppState::particles_initialized`](crate::resources::AppState::particles_initialized)
+/// is reset. Only the entities spawned by this crate for the particles are despawned: cameras,
+/// lights, UI and colliders are left untouched.
+#[derive(Event, Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct ResetSimulation;
+
+/// Sent by the [`NanCheck`](crate::nan_check::NanCheck) when the simulation starts diverging.
+#[derive(Event, Copy, Clone, PartialEq, Debug)]
+pub struct SimulationDiverged {
+    /// The number of particles with a non-finite position or velocity.
+    pub num_non_finite: usize,
+    /// The speed of the fastest particle with a finite state, in m/s.
+    pub max_speed: f32,
+    /// The value of [`AppState::num_steps`](crate::resources::AppState::num_steps) when the
+    /// simulation was last found stable.
+    pub last_stable_step: u64,
+}
+
+/// Sent when the simulation reaches its [`StepLimit`](crate::resources::StepLimit), after
+/// pausing it.
+#[derive(Event, Copy, Clone, PartialEq, Eq, Debug)]
+pub struct SimulationFinished {
+    pub num_steps: u64,
+}
+
+/// The number of particles inside of each region of the
+/// [`ParticleRegionWatcher`](crate::region::ParticleRegionWatcher), in the same order.
+#[derive(Event, Clone, PartialEq, Eq, Debug)]
+pub struct ParticleRegionCounts {
+    pub counts: Vec<usize>,
+}
+
+/// The result of a [`DeterminismCheck`](crate::debug::DeterminismCheck).
+#[derive(Event, Copy, Clone, PartialEq, Debug)]
+pub struct DeterminismReport {
+    /// The number of steps of each run.
+    pub num_steps: u64,
+    /// The largest distance between the final positions of a particle in both runs.
+    pub max_position_difference: f32,
+    /// The number of particles whose final positions differ between both runs.
+    pub num_different: usize,
+}
+
+/// Send this to show or hide the [`TimingOverlay`](crate::debug::TimingOverlay).
+#[derive(Event, Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct ToggleTimingOverlay;
+
+/// Sent when particles start or stop overlapping a collider with an
+/// [`MpmParticleSensor`](crate::components::MpmParticleSensor).
+#[derive(Event, Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ParticleSensorEvent {
+    /// The first particle entered the sensor.
+    Started { sensor: Entity },
+    /// The last particle left the sensor.
+    Stopped { sensor: Entity },
+}
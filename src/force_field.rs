@@ -0,0 +1,295 @@
+//! Spatially varying forces applied to the particles.
+
+use crate::readback::ParticleReadback;
+use crate::resources::{AppState, PhysicsContext};
+use bevy::prelude::*;
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+use nalgebra::Vector3;
+use wgcore::Shader;
+use wgcore::kernel::{KernelInvocationBuilder, KernelInvocationQueue};
+use wgcore::tensor::{GpuScalar, GpuVector};
+use wgebra::WgSvd2;
+use wgebra::WgSvd3;
+use wgpu::{BufferUsages, ComputePipeline, Device, Queue};
+use wgsparkl3d::grid::grid::WgGrid;
+use wgsparkl3d::solver::{GpuParticles, GpuSimulationParams, WgParticle};
+
+/// A field of accelerations applied to the particles.
+pub trait ForceField: Send + Sync {
+    /// The acceleration applied to a particle at `pos`.
+    fn sample(&self, pos: Vector3<f32>) -> Vector3<f32>;
+}
+
+/// A vortex spinning the particles around an axis.
+#[derive(Copy, Clone, Debug)]
+pub struct VortexField {
+    pub center: Vector3<f32>,
+    /// The (unit) rotation axis. Particles spin counterclockwise around it.
+    pub axis: Vector3<f32>,
+    /// The tangential acceleration close to the axis.
+    pub strength: f32,
+    /// The distance from the axis at which the acceleration is halved.
+    pub radius: f32,
+}
+
+impl ForceField for VortexField {
+    fn sample(&self, pos: Vector3<f32>) -> Vector3<f32> {
+        let rel = pos - self.center;
+        let radial = rel - self.axis * self.axis.dot(&rel);
+        let dist = radial.norm();
+
+        if dist < 1.0e-6 {
+            return Vector3::zeros();
+        }
+
+        self.axis.cross(&radial) / dist * self.strength / (1.0 + dist / self.radius)
+    }
+}
+
+/// Accelerations sampled on a regular 3D grid, like a texture, and interpolated trilinearly.
+///
+/// This lets force fields be painted or baked offline. Particles outside of the grid aren’t
+/// affected.
+#[derive(Clone, Debug)]
+pub struct GridForceField {
+    /// The position of the first sample.
+    pub origin: Vector3<f32>,
+    /// The distance between two consecutive samples along each axis.
+    pub cell_width: f32,
+    /// The number of samples along each axis.
+    pub dims: [usize; 3],
+    /// The samples, with the X index varying fastest, then Y, then Z.
+    pub values: Vec<Vector3<f32>>,
+}
+
+impl ForceField for GridForceField {
+    fn sample(&self, pos: Vector3<f32>) -> Vector3<f32> {
+        let local = (pos - self.origin) / self.cell_width;
+        let max = self.dims.map(|d| d.saturating_sub(1) as f32);
+
+        if self.values.is_empty() || (0..3).any(|k| !(0.0..=max[k]).contains(&local[k])) {
+            return Vector3::zeros();
+        }
+
+        // Keep the last cell in bounds for samples exactly on the upper boundary.
+        let base: [usize; 3] =
+            std::array::from_fn(|k| local[k].floor().min((max[k] - 1.0).max(0.0)) as usize);
+        let frac: [f32; 3] = std::array::from_fn(|k| local[k] - base[k] as f32);
+
+        let mut result = Vector3::zeros();
+        for corner in 0..8 {
+            let id: [usize; 3] =
+                std::array::from_fn(|k| (base[k] + ((corner >> k) & 1)).min(self.dims[k] - 1));
+            let weight: f32 = (0..3)
+                .map(|k| {
+                    if (corner >> k) & 1 == 1 {
+                        frac[k]
+                    } else {
+                        1.0 - frac[k]
+                    }
+                })
+                .product();
+
+            result += self.values
+                [id[0] + id[1] * self.dims[0] + id[2] * self.dims[0] * self.dims[1]]
+                * weight;
+        }
+        result
+    }
+}
+
+#[derive(Shader)]
+#[shader(
+    src = "force_field3d.wgsl",
+    derive(WgParticle, WgGrid, WgSvd2, WgSvd3),
+    composable = false
+)]
+pub struct WgForceField {
+    main: ComputePipeline,
+}
+
+impl WgForceField {
+    pub fn queue<'a>(
+        &'a self,
+        queue: &mut KernelInvocationQueue<'a>,
+        particles: &GpuParticles,
+        params: &GpuSimulationParams,
+        accelerations: &GpuVector<[f32; 4]>,
+        num_substeps: &GpuScalar<f32>,
+    ) {
+        KernelInvocationBuilder::new(queue, &self.main)
+            .bind0([
+                particles.velocities.buffer(),
+                accelerations.buffer(),
+                params.params.buffer(),
+                num_substeps.buffer(),
+            ])
+            .queue(accelerations.len().div_ceil(64) as u32);
+    }
+}
+
+/// Integrates per-particle accelerations into the particle velocities after each step.
+///
+/// This is the building block of [`ForceFields`] and of the per-group gravity (see
+/// [`MpmParticleBlock`](crate::groups::MpmParticleBlock)).
+pub struct AccelerationPass {
+    kernel: WgForceField,
+    accelerations: Option<GpuVector<[f32; 4]>>,
+    num_substeps: GpuScalar<f32>,
+    last_step: u64,
+}
+
+impl AccelerationPass {
+    pub fn new(device: &Device) -> Self {
+        Self {
+            kernel: WgForceField::from_device(device).unwrap(),
+            accelerations: None,
+            num_substeps: GpuScalar::init(
+                device,
+                1.0,
+                BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            ),
+            last_step: 0,
+        }
+    }
+
+    /// Replaces the acceleration of each particle (the `w` components are ignored).
+    pub fn upload(&mut self, device: &Device, queue: &Queue, accelerations: &[[f32; 4]]) {
+        match &self.accelerations {
+            Some(buffer) if buffer.len() as usize == accelerations.len() => {
+                queue.write_buffer(buffer.buffer(), 0, bytemuck::cast_slice(accelerations))
+            }
+            _ => {
+                self.accelerations = Some(GpuVector::init(
+                    device,
+                    accelerations,
+                    BufferUsages::STORAGE | BufferUsages::COPY_DST,
+                ))
+            }
+        }
+    }
+
+    /// Clears the accelerations, so nothing is applied until the next upload.
+    pub fn clear(&mut self) {
+        self.accelerations = None;
+    }
+
+    /// Applies the accelerations over the steps run since the last call, if any.
+    pub fn apply(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        app_state: &AppState,
+        physics: &PhysicsContext,
+    ) {
+        // The step counter restarts from zero when the simulation is reset.
+        let num_steps = app_state
+            .num_steps
+            .checked_sub(self.last_step)
+            .unwrap_or(app_state.num_steps);
+        self.last_step = app_state.num_steps;
+
+        let Some(accelerations) = &self.accelerations else {
+            return;
+        };
+
+        if num_steps == 0 || app_state.device_lost {
+            return;
+        }
+
+        queue.write_buffer(
+            self.num_substeps.buffer(),
+            0,
+            bytemuck::bytes_of(&((num_steps as usize * app_state.num_substeps) as f32)),
+        );
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        let mut kernels = KernelInvocationQueue::new(device);
+        self.kernel.queue(
+            &mut kernels,
+            &physics.data.particles,
+            &physics.data.sim_params,
+            accelerations,
+            &self.num_substeps,
+        );
+        kernels.encode(&mut encoder, None);
+        queue.submit(Some(encoder.finish()));
+    }
+}
+
+/// The force fields applied to the particles after each step.
+///
+/// The fields are sampled on the CPU at the particle positions from the [`ParticleReadback`]
+/// (which gets requested automatically), and the resulting accelerations are uploaded to the GPU
+/// and integrated into the particle velocities. This makes any Rust closure or data structure
+/// usable as a field, but the accelerations lag at least one frame behind the particles and
+/// each refresh costs a full readback plus an upload of one `vec4` per particle. Sampling the
+/// fields directly in a compute shader would avoid both, at the cost of writing each field in
+/// WGSL.
+#[derive(Resource)]
+pub struct ForceFields {
+    pub fields: Vec<Box<dyn ForceField>>,
+    pass: AccelerationPass,
+    last_readback: u64,
+}
+
+impl ForceFields {
+    pub fn new(device: &Device) -> Self {
+        Self {
+            fields: vec![],
+            pass: AccelerationPass::new(device),
+            last_readback: 0,
+        }
+    }
+
+    pub fn with_field(mut self, field: impl ForceField + 'static) -> Self {
+        self.fields.push(Box::new(field));
+        self
+    }
+
+    /// The sum of the accelerations of all the fields at `pos`.
+    pub fn sample(&self, pos: Vector3<f32>) -> Vector3<f32> {
+        self.fields.iter().map(|field| field.sample(pos)).sum()
+    }
+}
+
+/// Integrates the [`ForceFields`] into the particle velocities for the steps run this frame.
+pub fn apply_force_fields(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    app_state: Res<AppState>,
+    physics: Option<Res<PhysicsContext>>,
+    mut readback: ResMut<ParticleReadback>,
+    force_fields: Option<ResMut<ForceFields>>,
+) {
+    let (Some(physics), Some(mut force_fields)) = (physics, force_fields) else {
+        return;
+    };
+
+    if force_fields.fields.is_empty() {
+        return;
+    }
+
+    readback.request();
+    let device = render_device.wgpu_device();
+    let force_fields = &mut *force_fields;
+
+    if readback.num_readbacks() != force_fields.last_readback {
+        force_fields.last_readback = readback.num_readbacks();
+        let accelerations: Vec<[f32; 4]> = readback
+            .samples
+            .iter()
+            .map(|sample| {
+                let acc = force_fields.sample(Vector3::from(sample.position));
+                [acc.x, acc.y, acc.z, 0.0]
+            })
+            .collect();
+        force_fields
+            .pass
+            .upload(device, &render_queue, &accelerations);
+    }
+
+    force_fields
+        .pass
+        .apply(device, &render_queue, &app_state, &physics);
+}
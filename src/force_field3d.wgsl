@@ -0,0 +1,24 @@
+#define_import_path wgsparkl::examples::force_field
+
+#import wgsparkl::solver::particle as Particle;
+#import wgsparkl::solver::params as Params;
+
+@group(0) @binding(0)
+var<storage, read_write> particles_vel: array<Particle::Velocity>;
+@group(0) @binding(1)
+var<storage, read> accelerations: array<vec4<f32>>;
+@group(0) @binding(2)
+var<uniform> params: Params::SimulationParams;
+@group(0) @binding(3)
+var<storage, read> num_substeps: f32;
+
+@compute @workgroup_size(64, 1, 1)
+fn main(
+    @builtin(global_invocation_id) tid: vec3<u32>,
+) {
+    let particle_id = tid.x;
+
+    if particle_id < arrayLength(&accelerations) {
+        particles_vel[particle_id].v += accelerations[particle_id].xyz * params.dt * num_substeps;
+    }
+}
@@ -0,0 +1,518 @@
+//! Helpers for generating sets of particles.
+
+use crate::rng::MpmRng;
+use bevy::math::{UVec3, Vec3};
+use bevy::prelude::Transform;
+use bevy::tasks::{ComputeTaskPool, TaskPool};
+use nalgebra::{Vector3, vector};
+use std::f32::consts::PI;
+use std::fmt;
+use std::ops::Range;
+use wgsparkl3d::models::{DruckerPrager, ElasticCoefficients};
+use wgsparkl3d::solver::{Particle, ParticleMassProps, ParticlePhase};
+
+/// The recommended spacing between particles for a simulation grid with cells of `cell_width`.
+///
+/// MPM is stable and accurate with about 2 particles per cell along each axis (i.e. 8 particles
+/// per cell in 3D). Larger spacings under-sample the grid and cause visible artifacts.
+pub fn recommended_spacing(cell_width: f32) -> f32 {
+    cell_width / 2.0
+}
+
+/// Constructors of [`DruckerPrager`] plasticity from geotechnical parameters.
+pub trait DruckerPragerExt {
+    /// Drucker-Prager plasticity for sand-like materials, from intuitive granular parameters.
+    ///
+    /// The solver follows Klár et al. 2016, "Drucker-Prager Elastoplasticity for Sand
+    /// Animation", where the friction angle evolves with the hardening state `q` as
+    /// `φ(q) = h0 + (h1 q - h3) exp(-h2 q)`. The parameters are mapped as follows (angles in
+    /// radians):
+    /// - `h0 = friction_angle`, the critical-state friction angle reached after large plastic
+    ///   deformations.
+    /// - `h3 = 0`, so the material starts at its critical-state friction angle.
+    /// - `h2 = hardening`, the rate at which the friction angle peaks, then softens back.
+    /// - `h1 = 0.8 * dilatancy_angle * hardening * e`, so that the peak friction angle (reached at
+    ///   `q = 1 / h2`) is `friction_angle + 0.8 * dilatancy_angle`, following Bolton 1986, "The
+    ///   strength and dilatancy of sands".
+    ///
+    /// The model is cohesionless, so there is no cohesion parameter. Like
+    /// [`DruckerPrager::new`], the elastic part is given by the Young modulus and Poisson ratio
+    /// of the material, which should match its [`ElasticCoefficients`].
+    fn from_friction(
+        young_modulus: f32,
+        poisson_ratio: f32,
+        friction_angle: f32,
+        dilatancy_angle: f32,
+        hardening: f32,
+    ) -> Self;
+}
+
+impl DruckerPragerExt for DruckerPrager {
+    fn from_friction(
+        young_modulus: f32,
+        poisson_ratio: f32,
+        friction_angle: f32,
+        dilatancy_angle: f32,
+        hardening: f32,
+    ) -> Self {
+        DruckerPrager {
+            h0: friction_angle,
+            h1: 0.8 * dilatancy_angle * hardening * std::f32::consts::E,
+            h2: hardening,
+            h3: 0.0,
+            ..DruckerPrager::new(young_modulus, poisson_ratio)
+        }
+    }
+}
+
+/// The material properties shared by a set of generated particles.
+#[derive(Copy, Clone, Debug)]
+pub struct ParticleMaterial {
+    pub density: f32,
+    /// The elastic model of the particles.
+    ///
+    /// NOTE: the solver only supports isotropic elasticity (the Lamé coefficients of
+    /// [`ElasticCoefficients`]), so fibered materials with directional stiffness (muscles, wood,
+    /// etc.) can’t be simulated. Their stiffness along the fibers can only be approximated by an
+    /// isotropic Young modulus.
+    pub model: ElasticCoefficients,
+    pub plasticity: Option<DruckerPrager>,
+    pub phase: Option<ParticlePhase>,
+    /// Multiplies the mass of the generated particles, without changing their volume.
+    ///
+    /// This is the same as scaling `density`, as a separate knob for parameter sweeps. Heavier
+    /// particles lower the elastic wave speed `sqrt(E / density)`, so the simulation stays stable
+    /// with longer substeps, while lighter particles need shorter ones. Free-fall is unaffected,
+    /// but the material sags more under its own weight as the mass grows relative to its
+    /// stiffness, and pushes two-way coupled bodies harder.
+    pub mass_scale: f32,
+}
+
+impl ParticleMaterial {
+    pub fn new(density: f32, model: ElasticCoefficients) -> Self {
+        Self {
+            density,
+            model,
+            plasticity: None,
+            phase: None,
+            mass_scale: 1.0,
+        }
+    }
+
+    pub fn with_plasticity(mut self, plasticity: DruckerPrager) -> Self {
+        self.plasticity = Some(plasticity);
+        self
+    }
+
+    pub fn with_phase(mut self, phase: ParticlePhase) -> Self {
+        self.phase = Some(phase);
+        self
+    }
+
+    pub fn with_mass_scale(mut self, mass_scale: f32) -> Self {
+        self.mass_scale = mass_scale;
+        self
+    }
+
+    /// A motionless particle of this material at `position`, sampling a cube of width `spacing`.
+    pub fn particle(&self, position: Vector3<f32>, spacing: f32) -> Particle {
+        self.particle_with_spacing(position, Vector3::repeat(spacing))
+    }
+
+    /// A motionless particle of this material at `position`, sampling a box with the given
+    /// `spacing` along each axis.
+    pub fn particle_with_spacing(&self, position: Vector3<f32>, spacing: Vector3<f32>) -> Particle {
+        let volume = spacing.product();
+        Particle {
+            position,
+            velocity: Vector3::zeros(),
+            volume: ParticleMassProps::new(
+                self.density * self.mass_scale * volume,
+                volume.cbrt() / 2.0,
+            ),
+            model: self.model,
+            plasticity: self.plasticity,
+            phase: self.phase,
+        }
+    }
+}
+
+/// Particles filling a box centered on `transform`’s origin, in world-space.
+///
+/// The box has the given `half_extents` along the local axes of `transform`. Particles are laid
+/// out on a regular grid with `spacing` between neighbors, and at least one particle is generated
+/// along each axis.
+pub fn particles_in_box_local(
+    transform: &Transform,
+    half_extents: Vec3,
+    spacing: f32,
+    material: &ParticleMaterial,
+) -> Vec<Particle> {
+    particles_in_box_local_with_spacing(transform, half_extents, Vec3::splat(spacing), material)
+}
+
+/// Same as [`particles_in_box_local`], with a different spacing along each local axis, e.g., for
+/// sampling thin sheets or columns.
+///
+/// # Panics
+///
+/// Panics if a component of `spacing` isn’t positive.
+pub fn particles_in_box_local_with_spacing(
+    transform: &Transform,
+    half_extents: Vec3,
+    spacing: Vec3,
+    material: &ParticleMaterial,
+) -> Vec<Particle> {
+    assert!(
+        spacing.cmpgt(Vec3::ZERO).all(),
+        "the particle spacing must be positive along each axis, got {spacing}"
+    );
+
+    let counts = (half_extents * 2.0 / spacing)
+        .floor()
+        .as_uvec3()
+        .max(UVec3::ONE);
+    // Center the grid inside of the box.
+    let origin = -(counts.as_vec3() - Vec3::ONE) * spacing / 2.0;
+    let mut particles = Vec::with_capacity((counts.x * counts.y * counts.z) as usize);
+
+    for z in 0..counts.z {
+        for y in 0..counts.y {
+            for x in 0..counts.x {
+                let local = origin + UVec3::new(x, y, z).as_vec3() * spacing;
+                let world = transform.transform_point(local);
+                particles.push(material.particle_with_spacing(
+                    vector![world.x, world.y, world.z],
+                    vector![spacing.x, spacing.y, spacing.z],
+                ));
+            }
+        }
+    }
+
+    particles
+}
+
+/// Particles filling a ball of the given `radius` centered on `center`.
+///
+/// See [`particles_in_ellipsoid`] for details.
+pub fn particles_in_sphere(
+    center: Vec3,
+    radius: f32,
+    spacing: f32,
+    material: &ParticleMaterial,
+) -> Vec<Particle> {
+    particles_in_ellipsoid(
+        &Transform::from_translation(center),
+        Vec3::splat(radius),
+        spacing,
+        material,
+    )
+}
+
+/// Particles filling an ellipsoid centered on `transform`’s origin, in world-space.
+///
+/// The ellipsoid has the given `radii` along the local axes of `transform`. Particles are laid
+/// out on the same regular grid as [`particles_in_box_local`], keeping the ones inside of the
+/// ellipsoid. A single particle is generated at the center if the ellipsoid is too small to
+/// contain any grid point, e.g., if its radii are smaller than `spacing`.
+pub fn particles_in_ellipsoid(
+    transform: &Transform,
+    radii: Vec3,
+    spacing: f32,
+    material: &ParticleMaterial,
+) -> Vec<Particle> {
+    let mut particles: Vec<_> =
+        particles_in_box_local(&Transform::IDENTITY, radii, spacing, material)
+            .into_iter()
+            .filter(|particle| {
+                let local = Vec3::new(
+                    particle.position.x,
+                    particle.position.y,
+                    particle.position.z,
+                );
+                (local / radii).length_squared() <= 1.0
+            })
+            .collect();
+
+    if particles.is_empty() {
+        particles.push(material.particle(Vector3::zeros(), spacing));
+    }
+
+    for particle in &mut particles {
+        let world = transform.transform_point(Vec3::new(
+            particle.position.x,
+            particle.position.y,
+            particle.position.z,
+        ));
+        particle.position = vector![world.x, world.y, world.z];
+    }
+
+    particles
+}
+
+/// Offsets each particle by a random vector in `[-amplitude, amplitude]³`.
+///
+/// This breaks the regularity of the grids generated by e.g. [`particles_in_box_local`], which
+/// otherwise shows up as artificial patterns in granular flows. An `amplitude` of about a quarter
+/// of the spacing keeps the particles well distributed.
+pub fn jitter_particles(particles: &mut [Particle], amplitude: f32, rng: &mut MpmRng) {
+    for particle in particles {
+        particle.position += rng.vector_in_cube(amplitude);
+    }
+}
+
+/// Initial particle velocities spread in a cone, e.g., for fountains or jets.
+///
+/// Each sampled velocity points in a direction drawn uniformly in the cone of half-angle
+/// `spread_angle` around `dir`, with a speed drawn uniformly in `speed_range`.
+///
+/// NOTE: there are no particle emitters yet, so this is applied to generated particles with
+/// [`Self::apply`].
+#[derive(Clone, PartialEq, Debug)]
+pub struct EmitterVelocity {
+    dir: Vec3,
+    speed_range: Range<f32>,
+    spread_angle: f32,
+}
+
+impl EmitterVelocity {
+    /// # Panics
+    ///
+    /// Panics if `dir` is zero, or if `spread_angle` isn’t in `[0, π]`.
+    pub fn new(dir: Vec3, speed_range: Range<f32>, spread_angle: f32) -> Self {
+        assert!(
+            (0.0..=PI).contains(&spread_angle),
+            "the spread angle must be in [0, π], got {spread_angle}"
+        );
+        Self {
+            dir: dir
+                .try_normalize()
+                .expect("the emission direction must not be zero"),
+            speed_range,
+            spread_angle,
+        }
+    }
+
+    /// The normalized emission direction.
+    pub fn dir(&self) -> Vec3 {
+        self.dir
+    }
+
+    pub fn speed_range(&self) -> Range<f32> {
+        self.speed_range.clone()
+    }
+
+    /// The half-angle of the emission cone, in radians.
+    pub fn spread_angle(&self) -> f32 {
+        self.spread_angle
+    }
+
+    /// Draws a velocity from `rng`.
+    pub fn sample(&self, rng: &mut MpmRng) -> Vector3<f32> {
+        // Uniform on the spherical cap.
+        let cos_theta = 1.0 - rng.next_f32() * (1.0 - self.spread_angle.cos());
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = rng.range(0.0..2.0 * PI);
+        let (u, v) = self.dir.any_orthonormal_pair();
+        let dir = self.dir * cos_theta + (u * phi.cos() + v * phi.sin()) * sin_theta;
+        let vel = dir * rng.range(self.speed_range.clone());
+        vector![vel.x, vel.y, vel.z]
+    }
+
+    /// Overwrites the velocity of each particle with a sample drawn from `rng`.
+    pub fn apply(&self, particles: &mut [Particle], rng: &mut MpmRng) {
+        for particle in particles {
+            particle.velocity = self.sample(rng);
+        }
+    }
+}
+
+/// Generates `count` particles in parallel on Bevy’s compute task pool.
+///
+/// `particle(i)` returns the `i`-th particle. The result is ordered by index and doesn’t depend
+/// on the number of threads, as long as `particle` only depends on its input: avoid sharing
+/// state (e.g. a random number generator) between calls, derive it from the index instead (see
+/// [`MpmRng::fork`]).
+///
+/// This falls back to a single thread on platforms without threads (e.g. the web).
+pub fn generate_particles_parallel(
+    count: usize,
+    particle: impl Fn(usize) -> Particle + Sync,
+) -> Vec<Particle> {
+    let pool = ComputeTaskPool::get_or_init(TaskPool::default);
+    let chunk_size = count.div_ceil(pool.thread_num().max(1)).max(1);
+    let particle = &particle;
+
+    pool.scope(|scope| {
+        for start in (0..count).step_by(chunk_size) {
+            let end = (start + chunk_size).min(count);
+            scope.spawn(async move { (start..end).map(particle).collect::<Vec<_>>() });
+        }
+    })
+    .concat()
+}
+
+/// Error returned by [`particles_from_heightmap`] when the heights don’t match the grid size.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct HeightmapSizeError {
+    /// The expected number of heights, i.e., `width * depth`.
+    pub expected: usize,
+    /// The actual number of heights.
+    pub actual: usize,
+}
+
+impl fmt::Display for HeightmapSizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "the heightmap has {} heights but its dimensions require {}",
+            self.actual, self.expected
+        )
+    }
+}
+
+impl std::error::Error for HeightmapSizeError {}
+
+/// Particles filling a slab of `thickness` below a sampled height surface, e.g., for erodible
+/// ground.
+///
+/// `heights` is a row-major grid of `width * depth` heights (`heights[x + z * width]`) sampled
+/// `spacing` apart along the X and Z axes, starting at the origin. Each column of particles is
+/// laid out with `spacing` between neighbors, and contains at least one particle.
+pub fn particles_from_heightmap(
+    heights: &[f32],
+    width: usize,
+    depth: usize,
+    spacing: f32,
+    thickness: f32,
+    material: &ParticleMaterial,
+) -> Result<Vec<Particle>, HeightmapSizeError> {
+    particles_from_heightmap_with_spacing(
+        heights,
+        width,
+        depth,
+        Vector3::repeat(spacing),
+        thickness,
+        material,
+    )
+}
+
+/// Same as [`particles_from_heightmap`], with a different spacing along each axis.
+///
+/// The heights are sampled `spacing.x` apart along X and `spacing.z` apart along Z, and the
+/// layers of each column are `spacing.y` apart.
+///
+/// # Panics
+///
+/// Panics if a component of `spacing` isn’t positive.
+pub fn particles_from_heightmap_with_spacing(
+    heights: &[f32],
+    width: usize,
+    depth: usize,
+    spacing: Vector3<f32>,
+    thickness: f32,
+    material: &ParticleMaterial,
+) -> Result<Vec<Particle>, HeightmapSizeError> {
+    assert!(
+        spacing.iter().all(|s| *s > 0.0),
+        "the particle spacing must be positive along each axis, got {spacing:?}"
+    );
+
+    if heights.len() != width * depth {
+        return Err(HeightmapSizeError {
+            expected: width * depth,
+            actual: heights.len(),
+        });
+    }
+
+    let num_layers = ((thickness / spacing.y).floor() as usize).max(1);
+    let mut particles = Vec::with_capacity(heights.len() * num_layers);
+
+    for z in 0..depth {
+        for x in 0..width {
+            let height = heights[x + z * width];
+            for layer in 0..num_layers {
+                // Keep the particles (and not just their centers) below the surface.
+                let y = height - spacing.y / 2.0 - layer as f32 * spacing.y;
+                let position = vector![x as f32 * spacing.x, y, z as f32 * spacing.z];
+                particles.push(material.particle_with_spacing(position, spacing));
+            }
+        }
+    }
+
+    Ok(particles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drucker_prager_from_friction() {
+        let friction_angle = 30.0f32.to_radians();
+        let dilatancy_angle = 10.0f32.to_radians();
+        let hardening = 0.4;
+        let plasticity = DruckerPrager::from_friction(
+            10_000_000.0,
+            0.2,
+            friction_angle,
+            dilatancy_angle,
+            hardening,
+        );
+
+        assert_eq!(plasticity.h0, friction_angle);
+        assert_eq!(plasticity.h2, hardening);
+        assert_eq!(plasticity.h3, 0.0);
+
+        // φ(q) = h0 + (h1 q - h3) exp(-h2 q) starts at the friction angle, and peaks at
+        // `q = 1 / h2` with 80% of the dilatancy angle on top of it.
+        let phi = |q: f32| {
+            plasticity.h0 + (plasticity.h1 * q - plasticity.h3) * (-plasticity.h2 * q).exp()
+        };
+        assert_eq!(phi(0.0), friction_angle);
+        let peak = friction_angle + 0.8 * dilatancy_angle;
+        assert!((phi(1.0 / hardening) - peak).abs() < 1.0e-6);
+        assert!(phi(0.9 / hardening) < phi(1.0 / hardening));
+        assert!(phi(1.1 / hardening) < phi(1.0 / hardening));
+    }
+
+    fn material() -> ParticleMaterial {
+        ParticleMaterial::new(
+            1000.0,
+            ElasticCoefficients::from_young_modulus(1_000_000.0, 0.3),
+        )
+    }
+
+    #[test]
+    fn heightmap_size_mismatch() {
+        let heights = [1.0; 5];
+        let err = particles_from_heightmap(&heights, 2, 3, 0.5, 1.0, &material()).unwrap_err();
+        assert_eq!(
+            err,
+            HeightmapSizeError {
+                expected: 6,
+                actual: 5
+            }
+        );
+    }
+
+    #[test]
+    fn heightmap_columns() {
+        // A 2x2 grid, with a thickness of two layers.
+        let heights = [1.0, 2.0, 3.0, 4.0];
+        let particles = particles_from_heightmap(&heights, 2, 2, 0.5, 1.0, &material()).unwrap();
+        assert_eq!(particles.len(), 4 * 2);
+
+        // The top particle of the column `x = 1, z = 1` sits right below its height.
+        let top = particles
+            .iter()
+            .filter(|p| p.position.x == 0.5 && p.position.z == 0.5)
+            .map(|p| p.position.y)
+            .fold(f32::MIN, f32::max);
+        assert_eq!(top, 4.0 - 0.25);
+
+        // A slab thinner than the spacing still has one particle per column.
+        let particles = particles_from_heightmap(&heights, 2, 2, 0.5, 0.1, &material()).unwrap();
+        assert_eq!(particles.len(), 4);
+    }
+}
@@ -0,0 +1,189 @@
+//! Groups of particles with their own simulation parameters.
+
+use crate::force_field::AccelerationPass;
+use crate::generation::ParticleMaterial;
+use crate::resources::{AppState, PhysicsContext, RunState};
+use bevy::prelude::*;
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+use nalgebra::Vector3;
+use std::ops::Range;
+use wgpu::Device;
+use wgsparkl3d::models::{DruckerPrager, ElasticCoefficients};
+
+/// A block of consecutive particles, identified by their indices in the simulation.
+///
+/// Spawn an entity with this component to give the block its own parameters.
+#[derive(Component, Clone, Debug)]
+pub struct MpmParticleBlock {
+    /// The indices of the particles of this block.
+    pub particles: Range<usize>,
+    /// Overrides the gravity of [`SimulationParams`](wgsparkl3d::solver::SimulationParams) for
+    /// this block, e.g., to make foam buoyant.
+    ///
+    /// The solver only supports a single gravity, so this is emulated by applying the
+    /// difference with the global gravity as a body force after each step.
+    pub gravity: Option<Vector3<f32>>,
+    /// Overrides the base color of the particles of this block, instead of picking it from the
+    /// palette (see [`ColorSource`](crate::prep_vertex_buffer::ColorSource)).
+    ///
+    /// Only applies when the particle graphics are created, so the block must be spawned along
+    /// with the [`PhysicsContext`]. Render modes computing their own colors still override it.
+    pub color: Option<Color>,
+}
+
+/// Blends the material of the [`MpmParticleBlock`] on the same entity from `from` to `to` over
+/// `duration` seconds, e.g., for sand turning into stone. The transition only advances while the
+/// simulation isn’t paused.
+///
+/// The elastic coefficients and plasticity parameters are interpolated linearly, and uploaded
+/// with [`PhysicsContext::set_group_model`] each frame until the transition completes. The
+/// density and phase of the materials are ignored. If `to` has no plasticity, the particles keep
+/// the plasticity they had when the transition started.
+#[derive(Component, Clone, Debug)]
+pub struct MaterialTransition {
+    pub from: ParticleMaterial,
+    pub to: ParticleMaterial,
+    /// The duration of the transition, in seconds.
+    pub duration: f32,
+    /// The time elapsed since the transition started, in seconds.
+    pub elapsed: f32,
+}
+
+impl MaterialTransition {
+    pub fn new(from: ParticleMaterial, to: ParticleMaterial, duration: f32) -> Self {
+        Self {
+            from,
+            to,
+            duration,
+            elapsed: 0.0,
+        }
+    }
+
+    /// Whether the material reached `to`.
+    pub fn is_finished(&self) -> bool {
+        self.elapsed >= self.duration
+    }
+
+    fn blended_model(&self) -> (ElasticCoefficients, Option<DruckerPrager>) {
+        let t = if self.duration > 0.0 {
+            (self.elapsed / self.duration).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+
+        let model = ElasticCoefficients {
+            lambda: lerp(self.from.model.lambda, self.to.model.lambda),
+            mu: lerp(self.from.model.mu, self.to.model.mu),
+        };
+        let plasticity = match (self.from.plasticity, self.to.plasticity) {
+            (Some(from), Some(to)) => Some(DruckerPrager {
+                h0: lerp(from.h0, to.h0),
+                h1: lerp(from.h1, to.h1),
+                h2: lerp(from.h2, to.h2),
+                h3: lerp(from.h3, to.h3),
+                ..to
+            }),
+            (_, to) => to,
+        };
+        (model, plasticity)
+    }
+}
+
+/// Advances the [`MaterialTransition`]s while the simulation runs, and uploads the blended
+/// materials.
+pub fn apply_material_transitions(
+    time: Res<Time>,
+    render_queue: Res<RenderQueue>,
+    app_state: Res<AppState>,
+    physics: Option<ResMut<PhysicsContext>>,
+    mut transitions: Query<(&MpmParticleBlock, &mut MaterialTransition)>,
+) {
+    let Some(mut physics) = physics else {
+        return;
+    };
+
+    if app_state.run_state == RunState::Paused {
+        return;
+    }
+
+    for (block, mut transition) in &mut transitions {
+        if transition.is_finished() && !physics.is_added() {
+            continue;
+        }
+
+        transition.elapsed = (transition.elapsed + time.delta_secs()).min(transition.duration);
+        let (model, plasticity) = transition.blended_model();
+        physics.set_group_model(&render_queue, block.particles.clone(), model, plasticity);
+    }
+}
+
+/// The per-particle body forces emulating the gravity of each [`MpmParticleBlock`].
+#[derive(Resource)]
+pub struct GroupGravity {
+    pass: AccelerationPass,
+    global_gravity: Vector3<f32>,
+    num_particles: usize,
+}
+
+impl GroupGravity {
+    pub fn new(device: &Device) -> Self {
+        Self {
+            pass: AccelerationPass::new(device),
+            global_gravity: Vector3::zeros(),
+            num_particles: 0,
+        }
+    }
+}
+
+/// Applies the gravity overrides of the [`MpmParticleBlock`]s for the steps run this frame.
+pub fn apply_group_gravity(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    app_state: Res<AppState>,
+    physics: Option<Res<PhysicsContext>>,
+    mut group_gravity: ResMut<GroupGravity>,
+    blocks: Query<Ref<MpmParticleBlock>>,
+    mut removed_blocks: RemovedComponents<MpmParticleBlock>,
+) {
+    let Some(physics) = physics else {
+        return;
+    };
+
+    let global_gravity = physics.sim_params().gravity;
+    let num_particles = physics.num_particles();
+    let needs_update = removed_blocks.read().count() > 0
+        || blocks.iter().any(|block| block.is_changed())
+        || physics.is_added()
+        || group_gravity.global_gravity != global_gravity
+        || group_gravity.num_particles != num_particles;
+
+    if needs_update {
+        group_gravity.global_gravity = global_gravity;
+        group_gravity.num_particles = num_particles;
+
+        if blocks.iter().all(|block| block.gravity.is_none()) {
+            group_gravity.pass.clear();
+        } else {
+            let mut accelerations = vec![[0.0; 4]; num_particles];
+            for block in &blocks {
+                if let Some(gravity) = block.gravity {
+                    let extra = gravity - global_gravity;
+                    let range = block.particles.start.min(num_particles)
+                        ..block.particles.end.min(num_particles);
+                    accelerations[range].fill([extra.x, extra.y, extra.z, 0.0]);
+                }
+            }
+            group_gravity
+                .pass
+                .upload(render_device.wgpu_device(), &render_queue, &accelerations);
+        }
+    }
+
+    group_gravity.pass.apply(
+        render_device.wgpu_device(),
+        &render_queue,
+        &app_state,
+        &physics,
+    );
+}
@@ -1,5 +1,7 @@
 //! A shader that renders a mesh multiple times in one draw call.
 
+use crate::prep_vertex_buffer::RenderMode;
+use crate::resources::AppState;
 use bevy::render::sync_world::MainEntity;
 use bevy::{
     core_pipeline::core_3d::Transparent3d,
@@ -30,11 +32,25 @@ use bytemuck::{Pod, Zeroable};
 use std::sync::Arc;
 
 pub const INSTANCING_SHADER_HANDLE: Handle<Shader> = Handle::weak_from_u128(3222377299100772450);
+/// The path of the instancing shader with the `hot-reload` feature, watched for changes.
+#[cfg(feature = "hot-reload")]
+pub const INSTANCING_SHADER_PATH: &str = "embedded://bevy_wgsparkl/instancing3d.wgsl";
 
 #[derive(Component, Clone)]
 pub struct InstanceMaterialData {
     pub data: Vec<InstanceData>,
     pub buffer: InstanceBuffer,
+    /// Whether the particles are shaded with the scene lights (see [`RenderMode::Lit`]).
+    pub lit: bool,
+    /// Whether the particles are rendered as disks facing their velocity (see
+    /// [`RenderMode::FlowDisk`]).
+    pub flow_disk: bool,
+    /// Whether the particles are rendered with alpha blending (see
+    /// [`RenderConfig::is_transparent`](crate::prep_vertex_buffer::RenderConfig::is_transparent)).
+    pub transparent: bool,
+    /// The `i`-th instance renders the particle `i * render_stride` (see
+    /// [`RenderConfig::with_render_stride`](crate::prep_vertex_buffer::RenderConfig::with_render_stride)).
+    pub render_stride: usize,
 }
 
 impl ExtractComponent for InstanceMaterialData {
@@ -63,13 +79,38 @@ impl Plugin for ParticlesMaterialPlugin {
     }
 }
 
+/// Switches the particle shading depending on whether [`RenderMode::Lit`] or
+/// [`RenderMode::FlowDisk`] is selected, and on whether the particles are transparent.
+pub fn update_particle_shading(
+    app_state: Res<AppState>,
+    mut instances: Query<&mut InstanceMaterialData>,
+) {
+    let mode = RenderMode::from_u32(app_state.render_config.mode);
+    let lit = mode == RenderMode::Lit;
+    let flow_disk = mode == RenderMode::FlowDisk;
+    let transparent = app_state.render_config.is_transparent();
+    for mut instances in &mut instances {
+        if instances.lit != lit
+            || instances.flow_disk != flow_disk
+            || instances.transparent != transparent
+        {
+            instances.lit = lit;
+            instances.flow_disk = flow_disk;
+            instances.transparent = transparent;
+        }
+    }
+}
+
 #[derive(Clone, Copy, Pod, Zeroable)]
 #[repr(C)]
 pub struct InstanceData {
     pub deformation: [Vec4; 3],
+    /// The particle position. The `w` component holds the particle age, if it is tracked.
     pub position: Vec4,
     pub base_color: [f32; 4],
     pub color: [f32; 4],
+    /// The particle velocity. The `w` component holds its norm.
+    pub velocity: Vec4,
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -80,7 +121,7 @@ fn queue_custom(
     pipeline_cache: Res<PipelineCache>,
     meshes: Res<RenderAssets<RenderMesh>>,
     render_mesh_instances: Res<RenderMeshInstances>,
-    material_meshes: Query<(Entity, &MainEntity), With<InstanceMaterialData>>,
+    material_meshes: Query<(Entity, &MainEntity, &InstanceMaterialData)>,
     mut transparent_render_phases: ResMut<ViewSortedRenderPhases<Transparent3d>>,
     views: Query<(Entity, &ExtractedView, &Msaa)>,
 ) {
@@ -94,7 +135,7 @@ fn queue_custom(
         let msaa_key = MeshPipelineKey::from_msaa_samples(msaa.samples());
         let view_key = msaa_key | MeshPipelineKey::from_hdr(view.hdr);
         let rangefinder = view.rangefinder3d();
-        for (entity, main_entity) in &material_meshes {
+        for (entity, main_entity, instance_data) in &material_meshes {
             let Some(mesh_instance) = render_mesh_instances.render_mesh_queue_data(*main_entity)
             else {
                 continue;
@@ -102,8 +143,13 @@ fn queue_custom(
             let Some(mesh) = meshes.get(mesh_instance.mesh_asset_id) else {
                 continue;
             };
-            let key =
-                view_key | MeshPipelineKey::from_primitive_topology(mesh.primitive_topology());
+            let key = CustomPipelineKey {
+                mesh_key: view_key
+                    | MeshPipelineKey::from_primitive_topology(mesh.primitive_topology()),
+                lit: instance_data.lit,
+                flow_disk: instance_data.flow_disk,
+                transparent: instance_data.transparent,
+            };
             let pipeline = pipelines
                 .specialize(&pipeline_cache, &custom_pipeline, key, &mesh.layout)
                 .unwrap();
@@ -121,8 +167,37 @@ fn queue_custom(
 
 #[derive(Component, Clone)]
 pub struct InstanceBuffer {
+    /// The buffer rendered this frame.
     pub buffer: Arc<Buffer>,
     pub length: usize,
+    /// The buffer written by the compute passes, if the instance buffer is double-buffered.
+    ///
+    /// See [`AppState::double_buffer_instances`](crate::resources::AppState::double_buffer_instances).
+    pub back_buffer: Option<Arc<Buffer>>,
+}
+
+impl InstanceBuffer {
+    /// Returns the buffer the compute passes of this frame must write to.
+    ///
+    /// Without double-buffering, this is [`Self::buffer`]. Otherwise, the buffers are swapped so
+    /// [`Self::buffer`] holds the results of the previous frame (and is rendered this frame), and
+    /// a copy of it is encoded into the returned back buffer so the data accumulated across
+    /// frames (age, base colors) carries over.
+    pub fn begin_frame(&mut self, encoder: &mut CommandEncoder) -> Arc<Buffer> {
+        let Some(back_buffer) = &mut self.back_buffer else {
+            return self.buffer.clone();
+        };
+
+        std::mem::swap(&mut self.buffer, back_buffer);
+        encoder.copy_buffer_to_buffer(
+            &self.buffer,
+            0,
+            back_buffer,
+            0,
+            (self.length * size_of::<InstanceData>()) as u64,
+        );
+        back_buffer.clone()
+    }
 }
 
 #[derive(Resource)]
@@ -133,24 +208,65 @@ struct CustomPipeline {
 
 impl FromWorld for CustomPipeline {
     fn from_world(world: &mut World) -> Self {
+        #[cfg(not(feature = "hot-reload"))]
+        let shader = INSTANCING_SHADER_HANDLE;
+        #[cfg(feature = "hot-reload")]
+        let shader = world.resource::<AssetServer>().load(INSTANCING_SHADER_PATH);
         let mesh_pipeline = world.resource::<MeshPipeline>();
 
         CustomPipeline {
-            shader: INSTANCING_SHADER_HANDLE,
+            shader,
             mesh_pipeline: mesh_pipeline.clone(),
         }
     }
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+struct CustomPipelineKey {
+    mesh_key: MeshPipelineKey,
+    lit: bool,
+    flow_disk: bool,
+    transparent: bool,
+}
+
 impl SpecializedMeshPipeline for CustomPipeline {
-    type Key = MeshPipelineKey;
+    type Key = CustomPipelineKey;
 
     fn specialize(
         &self,
         key: Self::Key,
         layout: &MeshVertexBufferLayoutRef,
     ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
-        let mut descriptor = self.mesh_pipeline.specialize(key, layout)?;
+        let mut descriptor = self.mesh_pipeline.specialize(key.mesh_key, layout)?;
+
+        if key.lit {
+            descriptor.vertex.shader_defs.push("LIT".into());
+            if let Some(fragment) = &mut descriptor.fragment {
+                fragment.shader_defs.push("LIT".into());
+            }
+        }
+
+        // Only orient the instances by their velocity in this mode, so the others skip the work.
+        if key.flow_disk {
+            descriptor.vertex.shader_defs.push("FLOW_DISK".into());
+            if let Some(fragment) = &mut descriptor.fragment {
+                fragment.shader_defs.push("FLOW_DISK".into());
+            }
+        }
+
+        // Without sorting the instances, blending is only approximate: don’t let the particles
+        // hide each other.
+        if key.transparent {
+            if let Some(fragment) = &mut descriptor.fragment {
+                fragment.shader_defs.push("TRANSPARENT".into());
+                for target in fragment.targets.iter_mut().flatten() {
+                    target.blend = Some(BlendState::ALPHA_BLENDING);
+                }
+            }
+            if let Some(depth_stencil) = &mut descriptor.depth_stencil {
+                depth_stencil.depth_write_enabled = false;
+            }
+        }
 
         descriptor.vertex.shader = self.shader.clone();
         descriptor.vertex.buffers.push(VertexBufferLayout {
@@ -188,6 +304,11 @@ impl SpecializedMeshPipeline for CustomPipeline {
                     offset: VertexFormat::Float32x4.size() * 5,
                     shader_location: 8,
                 },
+                VertexAttribute {
+                    format: VertexFormat::Float32x4,
+                    offset: VertexFormat::Float32x4.size() * 6,
+                    shader_location: 9,
+                },
             ],
         });
         descriptor.fragment.as_mut().unwrap().shader = self.shader.clone();
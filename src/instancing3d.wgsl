@@ -1,4 +1,11 @@
 #import bevy_pbr::mesh_functions::{get_world_from_local, mesh_position_local_to_clip}
+#ifdef LIT
+#import bevy_pbr::{
+    mesh_view_bindings::view,
+    pbr_functions::{apply_pbr_lighting, calculate_view, main_pass_post_lighting_processing},
+    pbr_types::pbr_input_new,
+}
+#endif
 
 struct Vertex {
     @location(0) position: vec3<f32>,
@@ -11,6 +18,8 @@ struct Vertex {
     @location(6) pos: vec3<f32>,
     @location(7) unused: vec4<f32>,
     @location(8) i_color: vec4<f32>,
+    // The particle velocity, and its norm in `w`.
+    @location(9) velocity: vec4<f32>,
 };
 
 struct VertexOutput {
@@ -19,11 +28,40 @@ struct VertexOutput {
     @location(1) normal: vec3<f32>,
     @location(2) pos: vec3<f32>,
     @location(3) uv: vec2<f32>,
+    @location(4) velocity: vec4<f32>,
 };
 
+#ifdef FLOW_DISK
+// The thickness of the disks, relative to their diameter.
+const FLOW_DISK_THICKNESS: f32 = 0.15;
+
+// Maps the cube onto a thin square facing the particle velocity, keeping the size of the
+// particle. The fragment shader cuts the disk out of the square.
+fn flow_disk_deformation(deformation: mat3x3<f32>, velocity: vec4<f32>) -> mat3x3<f32> {
+    // Slow particles have no meaningful direction.
+    if velocity.w < 1.0e-4 {
+        return deformation;
+    }
+
+    let size = (length(deformation[0]) + length(deformation[1]) + length(deformation[2])) / 3.0;
+    let normal = velocity.xyz / velocity.w;
+    let helper = select(vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), abs(normal.x) > 0.9);
+    let tangent = normalize(cross(normal, helper));
+    let bitangent = cross(normal, tangent);
+    return mat3x3(tangent * size, bitangent * size, normal * size * FLOW_DISK_THICKNESS);
+}
+#endif
+
 @vertex
 fn vertex(vertex: Vertex) -> VertexOutput {
+#ifdef FLOW_DISK
+    let deformation = flow_disk_deformation(
+        mat3x3(vertex.def_x, vertex.def_y, vertex.def_z),
+        vertex.velocity
+    );
+#else
     let deformation = mat3x3(vertex.def_x, vertex.def_y, vertex.def_z);
+#endif
     let position = deformation * vertex.position + vertex.pos;
     var out: VertexOutput;
     let identity = mat4x4f(
@@ -40,14 +78,49 @@ fn vertex(vertex: Vertex) -> VertexOutput {
     out.normal = deformation * vertex.normal;
     out.pos = deformation * vertex.position + vertex.pos;
     out.uv = vertex.uv;
+    out.velocity = vertex.velocity;
     return out;
 }
 
+fn color_alpha(in: VertexOutput) -> f32 {
+#ifdef TRANSPARENT
+  return in.color.w;
+#else
+  return 1.0;
+#endif
+}
+
+#ifdef LIT
+// Shades the particle with the scene lights, like a `StandardMaterial`.
+fn lit_color(in: VertexOutput) -> vec4<f32> {
+  var pbr_input = pbr_input_new();
+  pbr_input.material.base_color = vec4(in.color.xyz, color_alpha(in));
+  pbr_input.frag_coord = in.clip_position;
+  pbr_input.world_position = vec4(in.pos, 1.0);
+  pbr_input.world_normal = normalize(in.normal);
+  pbr_input.N = pbr_input.world_normal;
+  pbr_input.is_orthographic = view.clip_from_view[3].w == 1.0;
+  pbr_input.V = calculate_view(pbr_input.world_position, pbr_input.is_orthographic);
+  return main_pass_post_lighting_processing(pbr_input, apply_pbr_lighting(pbr_input));
+}
+#endif
+
 @fragment
 fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+#ifdef FLOW_DISK
+  // Cut the disk out of the faces of the flattened cube, without the dark outline.
+  if length(in.uv - vec2(0.5)) > 0.5 {
+    discard;
+  }
+#else
   if any(abs(in.uv - vec2(0.5)) > vec2(0.48)) {
-    return vec4(0.0, 0.0, 0.0, 1.0);
+    return vec4(0.0, 0.0, 0.0, color_alpha(in));
   }
+#endif
+
+#ifdef LIT
+  return lit_color(in);
+#else
 
   let normal = normalize(in.normal);
   let lightPos = vec3(100.0, 100.0, 100.0);
@@ -65,5 +138,6 @@ fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
     */
 
   let specColor = vec3(0.4);
-  return vec4(in.color.xyz / 2.0 + lambertian * in.color.xyz / 2.0 + specular * specColor / 3.0, 1.0);
+  return vec4(in.color.xyz / 2.0 + lambertian * in.color.xyz / 2.0 + specular * specColor / 3.0, color_alpha(in));
+#endif
 }
\ No newline at end of file
@@ -0,0 +1,72 @@
+//! Access to the GPU particle buffers for external compute shaders.
+//!
+//! Effect crates (dust, splashes, etc.) can bind these buffers directly as read-only storage
+//! buffers in their own compute passes, without reading the particles back on the CPU:
+//!
+//! ```wgsl
+//! @group(0) @binding(0)
+//! var<storage, read> particles: array<vec4<f32>>; // With `stride == 16` and `position_offset == 0`.
+//! ```
+//!
+//! The buffers are overwritten by [`step_simulation`](crate::step::step_simulation), so external
+//! passes should be submitted after it to see the latest positions.
+
+use crate::instancing3d::{InstanceData, InstanceMaterialData};
+use crate::resources::PhysicsContext;
+use wgpu::Buffer;
+
+/// Memory layout of a particle buffer exposed through [`ParticleBufferProvider`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ParticleBufferLayout {
+    /// Size in bytes between two consecutive particles.
+    pub stride: u64,
+    /// Offset in bytes of the particle position (three `f32`) within each element.
+    pub position_offset: u64,
+}
+
+/// A source of per-particle GPU data that can be bound by external shaders.
+pub trait ParticleBufferProvider {
+    /// The GPU buffer containing the particle data.
+    fn particle_buffer(&self) -> &Buffer;
+    /// The layout of each element of [`Self::particle_buffer`].
+    fn particle_buffer_layout(&self) -> ParticleBufferLayout;
+    /// The number of valid elements in [`Self::particle_buffer`].
+    fn live_particle_count(&self) -> u32;
+}
+
+/// Exposes the solver’s particle positions buffer.
+impl ParticleBufferProvider for PhysicsContext {
+    fn particle_buffer(&self) -> &Buffer {
+        self.data.particles.positions.buffer()
+    }
+
+    fn particle_buffer_layout(&self) -> ParticleBufferLayout {
+        // Each position is a `vec3<f32>`, padded to 16 bytes.
+        ParticleBufferLayout {
+            stride: 16,
+            position_offset: 0,
+        }
+    }
+
+    fn live_particle_count(&self) -> u32 {
+        self.data.particles.positions.len() as u32
+    }
+}
+
+/// Exposes the instance buffer used for rendering the particles.
+impl ParticleBufferProvider for InstanceMaterialData {
+    fn particle_buffer(&self) -> &Buffer {
+        &self.buffer.buffer
+    }
+
+    fn particle_buffer_layout(&self) -> ParticleBufferLayout {
+        ParticleBufferLayout {
+            stride: size_of::<InstanceData>() as u64,
+            position_offset: std::mem::offset_of!(InstanceData, position) as u64,
+        }
+    }
+
+    fn live_particle_count(&self) -> u32 {
+        self.buffer.length as u32
+    }
+}
@@ -0,0 +1,236 @@
+//! Import and export of particle sets as CSV files, e.g., to exchange point clouds with other
+//! tools.
+
+use crate::generation::ParticleMaterial;
+use crate::readback::ParticleSample;
+use nalgebra::Vector3;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use wgsparkl3d::solver::Particle;
+
+/// Error returned when reading a particle file fails.
+#[derive(Debug)]
+pub enum ParticleFileError {
+    /// The file couldn’t be read.
+    Io(io::Error),
+    /// The file has no header line.
+    MissingHeader,
+    /// A required column isn’t in the header.
+    MissingColumn(&'static str),
+    /// Only some of the `vx`, `vy`, `vz` columns are in the header.
+    IncompleteVelocity,
+    /// A line doesn’t have as many fields as the header.
+    WrongFieldCount {
+        /// The line number, starting at 1.
+        line: usize,
+        expected: usize,
+        actual: usize,
+    },
+    /// A field isn’t a valid number, or isn’t finite (`NaN` or infinite).
+    InvalidNumber {
+        /// The line number, starting at 1.
+        line: usize,
+        column: String,
+        value: String,
+    },
+}
+
+impl fmt::Display for ParticleFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "failed to read the particle file: {err}"),
+            Self::MissingHeader => write!(f, "the particle file has no header line"),
+            Self::MissingColumn(column) => {
+                write!(f, "the particle file has no `{column}` column")
+            }
+            Self::IncompleteVelocity => write!(
+                f,
+                "the particle file must have all of the `vx`, `vy`, `vz` columns, or none"
+            ),
+            Self::WrongFieldCount {
+                line,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "line {line} of the particle file has {actual} fields but the header has {expected}"
+            ),
+            Self::InvalidNumber {
+                line,
+                column,
+                value,
+            } => write!(
+                f,
+                "line {line} of the particle file has an invalid `{column}` value: {value:?}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParticleFileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ParticleFileError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// Reads particles from a CSV file with `x,y,z` columns, and optional `vx,vy,vz` columns.
+///
+/// See [`read_particles_csv`] for the format.
+pub fn particles_from_csv(
+    path: impl AsRef<Path>,
+    material: &ParticleMaterial,
+    spacing: f32,
+) -> Result<Vec<Particle>, ParticleFileError> {
+    read_particles_csv(BufReader::new(File::open(path)?), material, spacing)
+}
+
+/// Reads particles from CSV data with `x,y,z` columns, and optional `vx,vy,vz` columns.
+///
+/// The first line is the header naming the columns (case-insensitive, in any order). Columns with
+/// other names are ignored, and so are empty lines. Non-finite values (`NaN`, `inf`) are
+/// rejected. Each particle is made of `material`, and samples a cube of width `spacing` (see
+/// [`ParticleMaterial::particle`]).
+pub fn read_particles_csv(
+    reader: impl BufRead,
+    material: &ParticleMaterial,
+    spacing: f32,
+) -> Result<Vec<Particle>, ParticleFileError> {
+    let mut lines = reader
+        .lines()
+        .enumerate()
+        .map(|(i, line)| line.map(|line| (i + 1, line)))
+        .filter(|line| !matches!(line, Ok((_, line)) if line.trim().is_empty()));
+
+    let (_, header) = lines.next().ok_or(ParticleFileError::MissingHeader)??;
+    let columns: Vec<String> = header
+        .split(',')
+        .map(|name| name.trim().to_lowercase())
+        .collect();
+    let column = |name: &str| columns.iter().position(|c| c == name);
+    let required = |name: &'static str| column(name).ok_or(ParticleFileError::MissingColumn(name));
+
+    let position_columns = [required("x")?, required("y")?, required("z")?];
+    let velocity_columns = match [column("vx"), column("vy"), column("vz")] {
+        [Some(vx), Some(vy), Some(vz)] => Some([vx, vy, vz]),
+        [None, None, None] => None,
+        _ => return Err(ParticleFileError::IncompleteVelocity),
+    };
+
+    let mut particles = vec![];
+    for line in lines {
+        let (line_id, line) = line?;
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        if fields.len() != columns.len() {
+            return Err(ParticleFileError::WrongFieldCount {
+                line: line_id,
+                expected: columns.len(),
+                actual: fields.len(),
+            });
+        }
+
+        let parse_vector = |ids: [usize; 3]| -> Result<Vector3<f32>, ParticleFileError> {
+            let mut result = Vector3::zeros();
+            for (k, id) in ids.into_iter().enumerate() {
+                result[k] = fields[id]
+                    .parse()
+                    .ok()
+                    .filter(|value: &f32| value.is_finite())
+                    .ok_or_else(|| ParticleFileError::InvalidNumber {
+                        line: line_id,
+                        column: columns[id].clone(),
+                        value: fields[id].to_string(),
+                    })?;
+            }
+            Ok(result)
+        };
+
+        let mut particle = material.particle(parse_vector(position_columns)?, spacing);
+        if let Some(velocity_columns) = velocity_columns {
+            particle.velocity = parse_vector(velocity_columns)?;
+        }
+        particles.push(particle);
+    }
+
+    Ok(particles)
+}
+
+/// Writes the positions and velocities of the particles as CSV with `x,y,z,vx,vy,vz` columns.
+///
+/// The samples can be obtained from [`ParticleReadback`](crate::readback::ParticleReadback), and
+/// the result read back with [`read_particles_csv`].
+pub fn write_particles_csv(
+    mut writer: impl Write,
+    samples: &[ParticleSample],
+) -> Result<(), io::Error> {
+    writeln!(writer, "x,y,z,vx,vy,vz")?;
+    for sample in samples {
+        let [x, y, z] = sample.position;
+        let [vx, vy, vz] = sample.velocity;
+        writeln!(writer, "{x},{y},{z},{vx},{vy},{vz}")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wgsparkl3d::models::ElasticCoefficients;
+
+    fn material() -> ParticleMaterial {
+        ParticleMaterial::new(
+            1000.0,
+            ElasticCoefficients::from_young_modulus(1_000_000.0, 0.3),
+        )
+    }
+
+    fn sample(position: [f32; 3], velocity: [f32; 3]) -> ParticleSample {
+        let mut sample = ParticleSample::default();
+        sample.position = position;
+        sample.velocity = velocity;
+        sample
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let samples = [
+            sample([1.0, -2.5, 3.25], [0.0, -9.81, 0.5]),
+            sample([0.1, 1.0e-7, -4.0e6], [1.0 / 3.0, 0.0, -0.0]),
+        ];
+        let mut csv = vec![];
+        write_particles_csv(&mut csv, &samples).unwrap();
+
+        let particles = read_particles_csv(csv.as_slice(), &material(), 0.5).unwrap();
+        assert_eq!(particles.len(), samples.len());
+        for (particle, sample) in particles.iter().zip(&samples) {
+            assert_eq!(particle.position, Vector3::from(sample.position));
+            assert_eq!(particle.velocity, Vector3::from(sample.velocity));
+        }
+    }
+
+    #[test]
+    fn csv_rejects_non_finite_values() {
+        for value in ["NaN", "inf", "-inf"] {
+            let csv = format!("x,y,z\n0,1,2\n0,{value},2\n");
+            let err = read_particles_csv(csv.as_bytes(), &material(), 0.5).unwrap_err();
+            assert!(
+                matches!(
+                    &err,
+                    ParticleFileError::InvalidNumber { line: 3, column, value: v }
+                        if column == "y" && v == value
+                ),
+                "unexpected error for {value}: {err}"
+            );
+        }
+    }
+}
@@ -1,26 +1,185 @@
+pub mod auto_reset;
+pub mod bounds;
+pub mod camera;
+pub mod collider_mesh;
+pub mod coloring;
 pub mod components;
+pub mod coupling;
+pub mod debug;
+pub mod events;
+pub mod force_field;
+pub mod generation;
+pub mod groups;
 pub mod instancing3d;
+pub mod interop;
+pub mod io;
+pub mod nan_check;
+pub mod packing;
+pub mod particle_age;
+pub mod prelude;
 pub mod prep_vertex_buffer;
+pub mod quickstart;
+pub mod readback;
+pub mod region;
 pub mod resources;
+pub mod rng;
+pub mod sensor;
+pub mod sleep;
+pub mod speed_limit;
 pub mod startup;
 pub mod step;
+pub mod surface;
+#[cfg(test)]
+mod test_utils;
 
-use bevy::{asset::load_internal_asset, prelude::*};
+#[cfg(not(feature = "hot-reload"))]
+use bevy::asset::load_internal_asset;
+use bevy::prelude::*;
+#[cfg(not(feature = "hot-reload"))]
 use instancing3d::INSTANCING_SHADER_HANDLE;
+use resources::RunState;
+use std::time::Duration;
 
-pub struct WgSparklPlugin;
+#[derive(Default)]
+pub struct WgSparklPlugin {
+    /// The entity of the Rapier context to couple the particles with.
+    ///
+    /// If `None`, the default Rapier context is used.
+    pub rapier_context: Option<Entity>,
+    /// The run state of the simulation when the app starts.
+    pub initial_run_state: RunState,
+    /// If set, the simulation stays paused until this much time elapsed since the app started,
+    /// regardless of `initial_run_state`.
+    ///
+    /// Useful to let the scene and camera settle before the particles start moving.
+    pub auto_start_after: Option<Duration>,
+}
+
+impl WgSparklPlugin {
+    pub fn with_initial_run_state(mut self, run_state: RunState) -> Self {
+        self.initial_run_state = run_state;
+        self
+    }
+
+    pub fn with_auto_start_after(mut self, delay: Duration) -> Self {
+        self.auto_start_after = Some(delay);
+        self
+    }
+}
+
+/// System sets of the systems run by [`WgSparklPlugin`] in the `Update` schedule.
+///
+/// The sets are chained in the order they are declared, with command buffers applied in
+/// between. Each frame:
+/// 1. The Rapier context coupled with the particles is selected (before all the sets).
+/// 2. [`WgSparklSet::PrepareCoupling`]: user systems collect the coupled colliders and insert
+///    the [`PhysicsContext`](resources::PhysicsContext) once the Rapier colliders exist.
+/// 3. [`WgSparklSet::SetupGraphics`]: the particle graphics are created for a new context.
+/// 4. [`WgSparklSet::Step`]: the simulation steps (this does nothing until a context exists).
+/// 5. [`WgSparklSet::Render`]: rendering-related updates and debug visualizations.
+///
+/// So a context inserted in `PrepareCoupling` gets its graphics and its first step in the same
+/// frame. None of the sets run if the device can’t run the simulation (see
+/// [`UnsupportedDevice`](resources::UnsupportedDevice)).
+#[derive(SystemSet, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum WgSparklSet {
+    /// Systems creating or updating the [`PhysicsContext`](resources::PhysicsContext) and its
+    /// coupled colliders. No built-in system runs in this set; it is meant for user systems.
+    PrepareCoupling,
+    /// Creates the particle graphics when a new simulation is set up.
+    SetupGraphics,
+    /// Steps the simulation and exchanges data with the GPU.
+    Step,
+    /// Updates the particle colors and draws debug visualizations.
+    Render,
+}
 
 impl Plugin for WgSparklPlugin {
     fn build(&self, app: &mut App) {
+        #[cfg(not(feature = "hot-reload"))]
         load_internal_asset!(
             app,
             INSTANCING_SHADER_HANDLE,
             "instancing3d.wgsl",
             Shader::from_wgsl
         );
+        #[cfg(feature = "hot-reload")]
+        bevy::asset::embedded_asset!(app, "instancing3d.wgsl");
         app.add_plugins(instancing3d::ParticlesMaterialPlugin);
+        app.configure_sets(
+            Update,
+            (
+                WgSparklSet::PrepareCoupling,
+                WgSparklSet::SetupGraphics,
+                WgSparklSet::Step,
+                WgSparklSet::Render,
+            )
+                .chain()
+                // `AppState` isn’t inserted if the device can’t run the simulation.
+                .run_if(resource_exists::<resources::AppState>),
+        );
+        app.init_resource::<rng::MpmRng>();
+        app.add_event::<events::DeviceLost>();
+        app.add_event::<events::ParticleSensorEvent>();
+        app.add_event::<events::ToggleTimingOverlay>();
+        app.add_event::<events::ParticleRegionCounts>();
+        app.add_event::<events::SimulationFinished>();
+        app.add_event::<events::SimulationDiverged>();
+        app.add_event::<events::ResetSimulation>();
+        app.add_event::<events::DeterminismReport>();
+        app.init_resource::<region::ParticleRegionWatcher>();
+        app.init_resource::<resources::LastStepOutcome>();
+        app.init_resource::<nan_check::NanCheck>();
+        app.init_resource::<debug::TimingOverlay>();
+        app.init_resource::<debug::DeterminismCheck>();
+        app.init_resource::<auto_reset::AutoReset>();
+        app.insert_resource(coupling::RapierContextSelection(self.rapier_context));
+        app.insert_resource(startup::InitialRunState {
+            run_state: self.initial_run_state,
+            auto_start_after: self.auto_start_after,
+        });
         app.add_systems(Startup, startup::setup_app);
-        app.add_systems(Update, step::step_simulation);
-        app.add_systems(Update, startup::setup_graphics);
+        app.add_systems(
+            Update,
+            (coupling::select_rapier_context, startup::reset_simulation)
+                .before(WgSparklSet::PrepareCoupling)
+                .run_if(resource_exists::<resources::AppState>),
+        );
+        app.add_systems(
+            Update,
+            (
+                step::handle_device_lost,
+                readback::receive_particle_readback,
+                nan_check::check_non_finite_particles,
+                sleep::update_particle_sleep,
+                auto_reset::check_auto_reset,
+                sensor::detect_particle_sensor_overlaps,
+                region::count_particles_in_regions,
+                debug::check_determinism,
+                groups::apply_material_transitions,
+                coloring::apply_custom_coloring,
+                step::step_simulation,
+                step::record_timing_history,
+                force_field::apply_force_fields,
+                groups::apply_group_gravity,
+                readback::queue_particle_readback,
+            )
+                .chain()
+                .in_set(WgSparklSet::Step),
+        );
+        app.add_systems(
+            Update,
+            startup::setup_graphics.in_set(WgSparklSet::SetupGraphics),
+        );
+        app.add_systems(
+            Update,
+            (
+                instancing3d::update_particle_shading,
+                debug::draw_coupling_velocities,
+                collider_mesh::update_coupled_collider_meshes,
+                (debug::toggle_timing_overlay, debug::update_timing_overlay).chain(),
+            )
+                .in_set(WgSparklSet::Render),
+        );
     }
 }
@@ -0,0 +1,147 @@
+//! Detection of particles with non-finite (NaN or infinite) states.
+
+use crate::events::SimulationDiverged;
+use crate::instancing3d::{InstanceData, InstanceMaterialData};
+use crate::readback::{ParticleReadback, ParticleSample};
+use crate::resources::{AppState, RunState, SimulationDiagnostics};
+use bevy::prelude::*;
+use bevy::render::renderer::RenderQueue;
+use std::mem::offset_of;
+
+/// Opt-in detection of particles whose position or velocity became non-finite, which usually
+/// means the simulation is unstable (e.g. too stiff for the number of substeps).
+///
+/// While enabled, the [`ParticleReadback`] is requested every frame, and every `interval`-th
+/// readback is scanned. The number of non-finite particles is stored in
+/// [`SimulationDiagnostics::num_non_finite`] and logged as an error.
+///
+/// The simulation is considered diverged if any particle is non-finite, or faster than
+/// `max_speed`. A [`SimulationDiverged`] event is sent when that starts happening, along with
+/// the last step found stable. Since the readback is asynchronous, a few more steps may run
+/// before the simulation is paused.
+#[derive(Resource, Clone, Debug)]
+pub struct NanCheck {
+    pub enabled: bool,
+    /// Scan one readback out of `interval`.
+    pub interval: u64,
+    /// Pause the simulation when it diverges.
+    pub pause: bool,
+    /// Consider the simulation diverged if a particle is faster than this, in m/s.
+    pub max_speed: Option<f32>,
+    /// Set the base color of the non-finite particles to this (linear RGBA) color.
+    pub highlight_color: Option<[f32; 4]>,
+}
+
+impl Default for NanCheck {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval: 10,
+            pause: true,
+            max_speed: None,
+            highlight_color: Some([1.0, 0.0, 1.0, 1.0]),
+        }
+    }
+}
+
+fn is_finite(sample: &ParticleSample) -> bool {
+    sample.position.iter().all(|x| x.is_finite()) && sample.velocity.iter().all(|x| x.is_finite())
+}
+
+pub fn check_non_finite_particles(
+    render_queue: Res<RenderQueue>,
+    nan_check: Res<NanCheck>,
+    mut app_state: ResMut<AppState>,
+    mut readback: ResMut<ParticleReadback>,
+    mut diagnostics: ResMut<SimulationDiagnostics>,
+    instances: Query<&InstanceMaterialData>,
+    mut events: EventWriter<SimulationDiverged>,
+    mut last_readback: Local<u64>,
+    mut diverged: Local<bool>,
+) {
+    if !nan_check.enabled {
+        return;
+    }
+
+    readback.request();
+
+    let num_readbacks = readback.num_readbacks();
+    if num_readbacks == *last_readback || num_readbacks % nan_check.interval.max(1) != 0 {
+        return;
+    }
+    *last_readback = num_readbacks;
+
+    let non_finite: Vec<usize> = readback
+        .samples
+        .iter()
+        .enumerate()
+        .filter(|(_, sample)| !is_finite(sample))
+        .map(|(i, _)| i)
+        .collect();
+    diagnostics.num_non_finite = non_finite.len();
+
+    let max_speed = readback
+        .samples
+        .iter()
+        .filter(|sample| is_finite(sample))
+        .map(|sample| Vec3::from(sample.velocity).length())
+        .fold(0.0, f32::max);
+    let too_fast = nan_check.max_speed.is_some_and(|limit| max_speed > limit);
+
+    if non_finite.is_empty() && !too_fast {
+        *diverged = false;
+        diagnostics.last_stable_step = app_state.num_steps;
+        return;
+    }
+
+    if !*diverged {
+        *diverged = true;
+        let pausing = if nan_check.pause {
+            " Pausing the simulation."
+        } else {
+            ""
+        };
+        if non_finite.is_empty() {
+            error!(
+                "The simulation diverged: a particle reached {max_speed}m/s (last stable step: \
+                 {}).{pausing}",
+                diagnostics.last_stable_step
+            );
+        } else {
+            error!(
+                "{} particles have a non-finite position or velocity (first: #{}, last stable \
+                 step: {}).{pausing}",
+                non_finite.len(),
+                non_finite[0],
+                diagnostics.last_stable_step
+            );
+        }
+
+        events.send(SimulationDiverged {
+            num_non_finite: non_finite.len(),
+            max_speed,
+            last_stable_step: diagnostics.last_stable_step,
+        });
+
+        if nan_check.pause {
+            app_state.run_state = RunState::Paused;
+        }
+    }
+
+    if let (Some(color), Ok(instances)) = (nan_check.highlight_color, instances.get_single()) {
+        let stride = instances.render_stride;
+        for id in non_finite
+            .into_iter()
+            .filter(|id| id % stride == 0)
+            .map(|id| id / stride)
+            .take_while(|id| *id < instances.data.len())
+        {
+            let offset = id * size_of::<InstanceData>() + offset_of!(InstanceData, base_color);
+            render_queue.write_buffer(
+                &instances.buffer.buffer,
+                offset as u64,
+                bytemuck::bytes_of(&color),
+            );
+        }
+    }
+}
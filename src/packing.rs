@@ -0,0 +1,85 @@
+use wgcore::Shader;
+use wgcore::kernel::{KernelInvocationBuilder, KernelInvocationQueue};
+use wgcore::tensor::GpuVector;
+use wgebra::WgSvd2;
+use wgebra::WgSvd3;
+use wgpu::{Buffer, BufferUsages, ComputePipeline, Device};
+use wgsparkl3d::grid::grid::{GpuGrid, WgGrid};
+use wgsparkl3d::solver::{GpuParticles, WgParticle};
+
+#[derive(Shader)]
+#[shader(
+    src = "packing3d.wgsl",
+    derive(WgParticle, WgGrid, WgSvd2, WgSvd3),
+    composable = false
+)]
+pub struct WgPacking {
+    clear: ComputePipeline,
+    count: ComputePipeline,
+}
+
+impl WgPacking {
+    pub fn queue<'a>(
+        &'a self,
+        queue: &mut KernelInvocationQueue<'a>,
+        particles: &GpuParticles,
+        grid: &GpuGrid,
+        counts: &GpuVector<u32>,
+    ) {
+        KernelInvocationBuilder::new(queue, &self.clear)
+            .bind0([counts.buffer()])
+            .queue(counts.len().div_ceil(64) as u32);
+        KernelInvocationBuilder::new(queue, &self.count)
+            .bind0([
+                counts.buffer(),
+                particles.positions.buffer(),
+                grid.meta.buffer(),
+            ])
+            .queue(particles.positions.len().div_ceil(64) as u32);
+    }
+}
+
+/// Counts the particles in each grid cell, for [`RenderMode::Packing`](crate::prep_vertex_buffer::RenderMode::Packing).
+///
+/// The counts are stored in a hash table with one slot per particle, so distant cells colliding
+/// in the table occasionally show a higher count. This costs two extra compute passes over the
+/// particles each frame, only while the packing render mode is active.
+pub struct PackingPass {
+    pub kernel: WgPacking,
+    counts: GpuVector<u32>,
+}
+
+impl PackingPass {
+    pub fn from_device(device: &Device) -> Self {
+        Self {
+            kernel: WgPacking::from_device(device).unwrap(),
+            counts: Self::counts_buffer(device, 1),
+        }
+    }
+
+    fn counts_buffer(device: &Device, len: usize) -> GpuVector<u32> {
+        GpuVector::init(device, &vec![0; len], BufferUsages::STORAGE)
+    }
+
+    /// The per-cell particle counts, indexed by the hash of the cell.
+    pub fn counts(&self) -> &Buffer {
+        self.counts.buffer()
+    }
+
+    /// Resizes the counts table for `num_particles` particles, if needed.
+    pub fn resize(&mut self, device: &Device, num_particles: usize) {
+        let len = num_particles.max(1);
+        if self.counts.len() as usize != len {
+            self.counts = Self::counts_buffer(device, len);
+        }
+    }
+
+    pub fn queue<'a>(
+        &'a self,
+        queue: &mut KernelInvocationQueue<'a>,
+        particles: &GpuParticles,
+        grid: &GpuGrid,
+    ) {
+        self.kernel.queue(queue, particles, grid, &self.counts);
+    }
+}
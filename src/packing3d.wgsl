@@ -0,0 +1,36 @@
+#define_import_path wgsparkl::examples::packing
+
+#import wgsparkl::solver::particle as Particle;
+#import wgsparkl::grid::grid as Grid;
+
+@group(0) @binding(0)
+var<storage, read_write> counts: array<atomic<u32>>;
+@group(0) @binding(1)
+var<storage, read> particles_pos: array<Particle::Position>;
+@group(0) @binding(2)
+var<storage, read_write> grid: Grid::Grid;
+
+// Hashes the cell containing `pt` into the `counts` table.
+fn cell_slot(pt: vec3<f32>) -> u32 {
+    let cell = vec3<i32>(floor(pt / grid.cell_width));
+    let hash = (u32(cell.x) * 73856093u) ^ (u32(cell.y) * 19349663u) ^ (u32(cell.z) * 83492791u);
+    return hash % arrayLength(&counts);
+}
+
+@compute @workgroup_size(64, 1, 1)
+fn clear(
+    @builtin(global_invocation_id) tid: vec3<u32>,
+) {
+    if tid.x < arrayLength(&counts) {
+        atomicStore(&counts[tid.x], 0u);
+    }
+}
+
+@compute @workgroup_size(64, 1, 1)
+fn count(
+    @builtin(global_invocation_id) tid: vec3<u32>,
+) {
+    if tid.x < arrayLength(&particles_pos) {
+        atomicAdd(&counts[cell_slot(particles_pos[tid.x].pt)], 1u);
+    }
+}
@@ -0,0 +1,78 @@
+use wgcore::Shader;
+use wgcore::kernel::{KernelInvocationBuilder, KernelInvocationQueue};
+use wgcore::tensor::GpuScalar;
+use wgebra::WgSvd2;
+use wgebra::WgSvd3;
+use wgpu::{Buffer, BufferUsages, ComputePipeline, Device, Queue};
+use wgsparkl3d::grid::grid::WgGrid;
+use wgsparkl3d::solver::{GpuSimulationParams, WgParticle};
+
+#[derive(Shader)]
+#[shader(
+    src = "particle_age3d.wgsl",
+    derive(WgParticle, WgGrid, WgSvd2, WgSvd3),
+    composable = false
+)]
+pub struct WgParticleAge {
+    main: ComputePipeline,
+}
+
+impl WgParticleAge {
+    pub fn queue<'a>(
+        &'a self,
+        queue: &mut KernelInvocationQueue<'a>,
+        params: &GpuSimulationParams,
+        num_substeps: &GpuScalar<f32>,
+        instances: &Buffer,
+        num_instances: usize,
+    ) {
+        KernelInvocationBuilder::new(queue, &self.main)
+            .bind0([instances, params.params.buffer(), num_substeps.buffer()])
+            .queue(num_instances.div_ceil(64) as u32);
+    }
+}
+
+/// Tracks the age of each particle, stored in the `w` component of the render instances’
+/// position.
+///
+/// The age is the simulated time elapsed since the particle graphics were created.
+///
+/// NOTE: the age can’t drive a particle lifetime yet. The particle count is fixed when the
+/// [`PhysicsContext`](crate::resources::PhysicsContext) is created, and neither this crate nor
+/// the solver have emitters or a path removing (and compacting) particles: a `max_lifetime` needs
+/// both first.
+pub struct ParticleAgePass {
+    pub kernel: WgParticleAge,
+    num_substeps: GpuScalar<f32>,
+}
+
+impl ParticleAgePass {
+    pub fn from_device(device: &Device) -> Self {
+        Self {
+            kernel: WgParticleAge::from_device(device).unwrap(),
+            num_substeps: GpuScalar::init(
+                device,
+                1.0,
+                BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            ),
+        }
+    }
+
+    pub fn queue<'a>(
+        &'a self,
+        compute_queue: &Queue,
+        queue: &mut KernelInvocationQueue<'a>,
+        params: &GpuSimulationParams,
+        num_substeps: usize,
+        instances: &Buffer,
+        num_instances: usize,
+    ) {
+        compute_queue.write_buffer(
+            self.num_substeps.buffer(),
+            0,
+            bytemuck::bytes_of(&(num_substeps as f32)),
+        );
+        self.kernel
+            .queue(queue, params, &self.num_substeps, instances, num_instances);
+    }
+}
@@ -0,0 +1,30 @@
+#define_import_path wgsparkl::examples::particle_age
+
+#import wgsparkl::solver::params as Params;
+
+@group(0) @binding(0)
+var<storage, read_write> instances: array<InstanceData>;
+@group(0) @binding(1)
+var<uniform> params: Params::SimulationParams;
+@group(0) @binding(2)
+var<storage, read> num_substeps: f32;
+
+struct InstanceData {
+    deformation: mat3x3<f32>,
+    position: vec3<f32>,
+    age: f32,
+    base_color: vec4<f32>,
+    color: vec4<f32>,
+    velocity: vec4<f32>,
+}
+
+@compute @workgroup_size(64, 1, 1)
+fn main(
+    @builtin(global_invocation_id) tid: vec3<u32>,
+) {
+    let particle_id = tid.x;
+
+    if particle_id < arrayLength(&instances) {
+        instances[particle_id].age += params.dt * num_substeps;
+    }
+}
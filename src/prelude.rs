@@ -0,0 +1,31 @@
+//! Re-exports of the most commonly used types, including from the underlying physics crates.
+
+pub use crate::auto_reset::{AutoReset, AutoResetCondition};
+pub use crate::bounds::SimulationBounds;
+pub use crate::components::{MpmCouplingEnabled, MpmParticleSensor, MpmRapierContext};
+pub use crate::events::{
+    DeterminismReport, DeviceLost, ParticleRegionCounts, ParticleSensorEvent, ResetSimulation,
+    SimulationDiverged, SimulationFinished, ToggleTimingOverlay,
+};
+pub use crate::generation::{
+    DruckerPragerExt, EmitterVelocity, ParticleMaterial, generate_particles_parallel,
+    jitter_particles, particles_from_heightmap, particles_from_heightmap_with_spacing,
+    particles_in_box_local, particles_in_box_local_with_spacing, particles_in_ellipsoid,
+    particles_in_sphere, recommended_spacing,
+};
+pub use crate::groups::{MaterialTransition, MpmParticleBlock};
+pub use crate::io::particles_from_csv;
+pub use crate::prep_vertex_buffer::{AlphaSource, ColorSource, Colormap, RenderConfig, RenderMode};
+pub use crate::readback::ParticleReadback;
+pub use crate::region::{ParticleRegion, ParticleRegionWatcher};
+pub use crate::resources::{
+    AppState, LastStepOutcome, PhysicsContext, RunState, SimulationDiagnostics, StepLimit,
+    TimeStepMode, Timestamps, UnsupportedDevice,
+};
+pub use crate::rng::MpmRng;
+pub use crate::sleep::ParticleSleep;
+pub use crate::{WgSparklPlugin, WgSparklSet};
+pub use nalgebra::{Vector3, vector};
+pub use wgrapier3d::dynamics::body::{BodyCoupling, BodyCouplingEntry};
+pub use wgsparkl3d::models::{DruckerPrager, ElasticCoefficients};
+pub use wgsparkl3d::solver::{Particle, ParticlePhase, SimulationParams};
@@ -1,3 +1,5 @@
+use crate::instancing3d::InstanceData;
+use bevy::math::Vec3;
 use wgcore::Shader;
 use wgcore::kernel::{KernelInvocationBuilder, KernelInvocationQueue};
 use wgcore::tensor::GpuScalar;
@@ -8,6 +10,7 @@ use wgsparkl3d::grid::grid::{GpuGrid, WgGrid};
 use wgsparkl3d::solver::WgParticle;
 use wgsparkl3d::solver::{GpuParticles, GpuSimulationParams};
 
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum RenderMode {
     Default = 0,
     Volume = 1,
@@ -15,6 +18,34 @@ pub enum RenderMode {
     CdfNormals = 3,
     CdfDistances = 4,
     CdfSigns = 5,
+    /// Lighten recent particles. Requires [`AppState::track_particle_age`](crate::resources::AppState::track_particle_age).
+    Age = 6,
+    /// Tint particles depending on their volume change (red when expanded, blue when compressed).
+    ///
+    /// Combine with [`RenderConfig::deformation_scale`] to exaggerate how particles squash and
+    /// stretch.
+    Deformed = 7,
+    /// Colors computed on the CPU by [`AppState::custom_coloring`](crate::resources::AppState::custom_coloring).
+    Custom = 8,
+    /// Color particles by their height along [`RenderConfig::up_axis`], with a [`Colormap`]
+    /// spanning [`RenderConfig::height_min`] to [`RenderConfig::height_max`].
+    Height = 9,
+    /// Color particles by the number of particles in their grid cell (a proxy for the packing
+    /// density), with a [`Colormap`] spanning `0` to [`RenderConfig::packing_max`].
+    ///
+    /// This reveals compression waves and voids in granular flows. The counts are computed by a
+    /// [`PackingPass`](crate::packing::PackingPass), adding two compute passes per frame.
+    Packing = 10,
+    /// The base colors, shaded with the scene lights like a
+    /// [`StandardMaterial`](bevy::pbr::StandardMaterial), instead of the fixed light of the
+    /// other modes.
+    Lit = 11,
+    /// The base colors, with each particle rendered as a thin disk facing its velocity, which
+    /// makes the flow of fluid-like materials easier to follow than with cubes.
+    ///
+    /// The disks are oriented when rendering, from the velocity of the instance data. Slow
+    /// particles keep the orientation of their deformation.
+    FlowDisk = 12,
 }
 
 impl RenderMode {
@@ -26,6 +57,13 @@ impl RenderMode {
             Self::CdfNormals => "cdf (normals)",
             Self::CdfDistances => "cdf (distances)",
             Self::CdfSigns => "cdf (signs)",
+            Self::Age => "age",
+            Self::Deformed => "deformed",
+            Self::Custom => "custom",
+            Self::Height => "height",
+            Self::Packing => "packing",
+            Self::Lit => "lit",
+            Self::FlowDisk => "flow disk",
         }
     }
 
@@ -37,20 +75,293 @@ impl RenderMode {
             3 => Self::CdfNormals,
             4 => Self::CdfDistances,
             5 => Self::CdfSigns,
+            6 => Self::Age,
+            7 => Self::Deformed,
+            8 => Self::Custom,
+            9 => Self::Height,
+            10 => Self::Packing,
+            11 => Self::Lit,
+            12 => Self::FlowDisk,
             _ => unreachable!(),
         }
     }
 }
 
-#[derive(bytemuck::Pod, bytemuck::Zeroable, Copy, Clone, PartialEq, Debug, Default)]
+/// A gradient mapping scalar values to colors.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Colormap {
+    Viridis = 0,
+    Turbo = 1,
+    Grayscale = 2,
+}
+
+impl Colormap {
+    pub fn text(&self) -> &'static str {
+        match self {
+            Self::Viridis => "viridis",
+            Self::Turbo => "turbo",
+            Self::Grayscale => "grayscale",
+        }
+    }
+
+    pub fn from_u32(val: u32) -> Self {
+        match val {
+            0 => Self::Viridis,
+            1 => Self::Turbo,
+            2 => Self::Grayscale,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// How the opacity of each particle is computed.
+///
+/// Transparent particles are blended without depth writes and without sorting them back to
+/// front, so no particle hides another one and the result only approximates a correct blending.
+/// This is best suited for ghostly or volumetric looks with low opacities.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum AlphaSource {
+    /// All the particles are opaque, regardless of the alpha of their colors.
+    Opaque = 0,
+    /// All the particles have the opacity [`RenderConfig::alpha`].
+    Constant = 1,
+    /// Particles at rest have the opacity [`RenderConfig::alpha`], and become opaque as their
+    /// speed reaches [`RenderConfig::alpha_velocity_max`].
+    Velocity = 2,
+}
+
+impl AlphaSource {
+    pub fn text(&self) -> &'static str {
+        match self {
+            Self::Opaque => "opaque",
+            Self::Constant => "constant",
+            Self::Velocity => "velocity",
+        }
+    }
+
+    pub fn from_u32(val: u32) -> Self {
+        match val {
+            0 => Self::Opaque,
+            1 => Self::Constant,
+            2 => Self::Velocity,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// How the base color of each particle is picked from the palette.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ColorSource {
+    /// Cycle through the palette based on the particle index.
+    Index = 0,
+    /// Use one color per block of consecutive particles sharing the same material.
+    Block = 1,
+    /// Pick a color from a hash of the particle’s initial position.
+    SpatialHash = 2,
+    /// Use a single color for all the particles.
+    Solid = 3,
+}
+
+impl ColorSource {
+    pub fn text(&self) -> &'static str {
+        match self {
+            Self::Index => "index",
+            Self::Block => "block",
+            Self::SpatialHash => "spatial hash",
+            Self::Solid => "solid",
+        }
+    }
+
+    pub fn from_u32(val: u32) -> Self {
+        match val {
+            0 => Self::Index,
+            1 => Self::Block,
+            2 => Self::SpatialHash,
+            3 => Self::Solid,
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Copy, Clone, PartialEq, Debug)]
 #[repr(C)]
 pub struct RenderConfig {
     pub mode: u32,
+    /// The [`ColorSource`] used to assign base colors when the particle graphics are created.
+    pub color_source: u32,
+    /// Offset applied to the palette index (or to the hash for [`ColorSource::SpatialHash`]).
+    pub color_seed: u32,
+    /// Age (in simulated seconds) at which particles reach their base color in [`RenderMode::Age`].
+    pub age_fade_duration: f32,
+    /// Scales the deformation gradient applied to each particle’s shape when rendering.
+    ///
+    /// `1.0` renders the actual deformation computed by the solver, `0.0` renders undeformed
+    /// particles, and greater values exaggerate the deformation.
+    pub deformation_scale: f32,
+    /// Non-zero to highlight the particles in contact with a coupled collider with `contact_color`.
+    pub highlight_contacts: u32,
+    /// Particles closer than this distance to a coupled collider are considered in contact.
+    pub contact_distance: f32,
+    _padding: u32,
+    /// The (linear RGBA) color of the particles in contact with a coupled collider.
+    pub contact_color: [f32; 4],
+    /// The height mapped to the start of the colormap in [`RenderMode::Height`].
+    pub height_min: f32,
+    /// The height mapped to the end of the colormap in [`RenderMode::Height`].
+    pub height_max: f32,
+    /// The [`Colormap`] used by [`RenderMode::Height`] and [`RenderMode::Packing`].
+    pub colormap: u32,
+    /// The index of the up axis (`0` for X, `1` for Y, `2` for Z).
+    pub up_axis: u32,
+    /// The normal (`xyz`) and offset (`w`) of the clip plane, see [`Self::with_clip_plane`].
+    pub clip_plane: [f32; 4],
+    /// Non-zero to hide the particles beyond `clip_plane`.
+    pub clip_enabled: u32,
+    /// The particle count per cell mapped to the end of the colormap in [`RenderMode::Packing`].
+    pub packing_max: f32,
+    /// Non-zero to keep the current particle colors, see [`Self::freeze_colors`].
+    pub freeze_colors: u32,
+    /// Only render every `render_stride`-th particle, see [`Self::with_render_stride`].
+    pub render_stride: u32,
+    /// The [`AlphaSource`] of the particle opacities.
+    pub alpha_source: u32,
+    /// The opacity of the particles, or of the particles at rest with [`AlphaSource::Velocity`].
+    pub alpha: f32,
+    /// The speed at which particles become opaque with [`AlphaSource::Velocity`].
+    pub alpha_velocity_max: f32,
+    _padding3: u32,
+    /// Scales the rendered particle positions and shapes along each axis, see
+    /// [`Self::with_render_scale`].
+    pub render_scale: [f32; 3],
+    _padding4: u32,
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        Self::new(RenderMode::Default)
+    }
 }
 
 impl RenderConfig {
     pub fn new(mode: RenderMode) -> Self {
-        Self { mode: mode as u32 }
+        Self {
+            mode: mode as u32,
+            color_source: ColorSource::Index as u32,
+            color_seed: 0,
+            age_fade_duration: 5.0,
+            deformation_scale: 1.0,
+            highlight_contacts: 0,
+            contact_distance: 0.5,
+            _padding: 0,
+            contact_color: [1.0, 0.6, 0.1, 1.0],
+            height_min: 0.0,
+            height_max: 20.0,
+            colormap: Colormap::Viridis as u32,
+            up_axis: 1,
+            clip_plane: [0.0, 0.0, 1.0, 0.0],
+            clip_enabled: 0,
+            // 2 particles per cell along each axis, as recommended.
+            packing_max: 8.0,
+            freeze_colors: 0,
+            render_stride: 1,
+            alpha_source: AlphaSource::Opaque as u32,
+            alpha: 0.3,
+            alpha_velocity_max: 5.0,
+            _padding3: 0,
+            render_scale: [1.0; 3],
+            _padding4: 0,
+        }
+    }
+
+    pub fn with_color_source(mut self, source: ColorSource, seed: u32) -> Self {
+        self.color_source = source as u32;
+        self.color_seed = seed;
+        self
+    }
+
+    /// Sets the range of heights and the colormap of [`RenderMode::Height`].
+    pub fn with_height_gradient(mut self, min: f32, max: f32, colormap: Colormap) -> Self {
+        self.height_min = min;
+        self.height_max = max;
+        self.colormap = colormap as u32;
+        self
+    }
+
+    /// Only renders the particles with `normal.dot(position) <= offset`, e.g., for a cutaway
+    /// view of the inside of a pile.
+    ///
+    /// This only affects rendering: the hidden particles are still simulated.
+    pub fn with_clip_plane(mut self, normal: Vec3, offset: f32) -> Self {
+        self.clip_plane = [normal.x, normal.y, normal.z, offset];
+        self.clip_enabled = 1;
+        self
+    }
+
+    /// The normal and offset of the clip plane, if clipping is enabled.
+    pub fn clip_plane(&self) -> Option<(Vec3, f32)> {
+        let [x, y, z, offset] = self.clip_plane;
+        (self.clip_enabled != 0).then_some((Vec3::new(x, y, z), offset))
+    }
+
+    /// Only renders every `stride`-th particle, while all of them are still simulated.
+    ///
+    /// This is a render-only approximation for simulations with far more particles than useful
+    /// to render: the instance buffer and the vertex preparation pass shrink accordingly, but the
+    /// rendered particles keep their size, so the material looks sparser. Only applies when the
+    /// particle graphics are created. A stride of `0` is the same as `1`.
+    pub fn with_render_stride(mut self, stride: u32) -> Self {
+        self.render_stride = stride;
+        self
+    }
+
+    /// Scales the rendered particles along each axis, e.g., to exaggerate the height variations
+    /// of a shallow flow.
+    ///
+    /// This is purely cosmetic: the simulation, the contacts with the coupled colliders, and the
+    /// particle readbacks keep the actual coordinates, so the rendered particles don’t line up
+    /// with the colliders anymore. The clip plane still applies to the actual coordinates.
+    pub fn with_render_scale(mut self, scale: Vec3) -> Self {
+        self.render_scale = scale.to_array();
+        self
+    }
+
+    /// Sets how the particle opacities are computed, see [`AlphaSource`].
+    pub fn with_alpha(mut self, source: AlphaSource, alpha: f32) -> Self {
+        self.alpha_source = source as u32;
+        self.alpha = alpha;
+        self
+    }
+
+    /// Whether the particles are rendered with alpha blending.
+    pub fn is_transparent(&self) -> bool {
+        AlphaSource::from_u32(self.alpha_source) != AlphaSource::Opaque
+    }
+
+    /// Keeps the current particle colors until [`Self::unfreeze_colors`] is called, e.g., to
+    /// orbit around a paused scene colored by a velocity snapshot while taking screenshots.
+    ///
+    /// The positions and deformations are still updated, and the render mode can’t change the
+    /// colors until they are unfrozen.
+    pub fn freeze_colors(&mut self) {
+        self.freeze_colors = 1;
+    }
+
+    pub fn unfreeze_colors(&mut self) {
+        self.freeze_colors = 0;
+    }
+
+    pub fn colors_frozen(&self) -> bool {
+        self.freeze_colors != 0
+    }
+
+    /// Highlights the particles closer than `distance` to a coupled collider with `color`.
+    ///
+    /// This applies on top of every render mode except [`RenderMode::Custom`].
+    pub fn with_contact_highlight(mut self, color: [f32; 4], distance: f32) -> Self {
+        self.highlight_contacts = 1;
+        self.contact_distance = distance;
+        self.contact_color = color;
+        self
     }
 }
 
@@ -88,8 +399,10 @@ impl WgPrepVertexBuffer {
         particles: &GpuParticles,
         grid: &GpuGrid,
         params: &GpuSimulationParams,
+        packing_counts: &Buffer,
         vertex_buffer: &Buffer,
     ) {
+        let num_instances = vertex_buffer.size() / size_of::<InstanceData>() as u64;
         KernelInvocationBuilder::new(queue, &self.main)
             .bind0([
                 vertex_buffer,
@@ -100,7 +413,9 @@ impl WgPrepVertexBuffer {
                 grid.meta.buffer(),
                 params.params.buffer(),
                 config.buffer.buffer(),
+                packing_counts,
             ])
-            .queue(particles.positions.len().div_ceil(64) as u32);
+            // One invocation per instance, i.e., per rendered particle.
+            .queue(num_instances.div_ceil(64) as u32);
     }
 }
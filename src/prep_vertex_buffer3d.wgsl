@@ -21,9 +21,34 @@ var<storage, read_write> grid: Grid::Grid;
 var<uniform> params: Params::SimulationParams;
 @group(0) @binding(7)
 var<storage, read> config: RenderConfig;
+@group(0) @binding(8)
+var<storage, read> packing_counts: array<u32>;
 
 struct RenderConfig {
     mode: u32,
+    // Only used CPU-side when initializing the base colors.
+    color_source: u32,
+    color_seed: u32,
+    age_fade_duration: f32,
+    deformation_scale: f32,
+    highlight_contacts: u32,
+    contact_distance: f32,
+    // `contact_color` is aligned to 16 bytes, after 4 bytes of implicit padding.
+    contact_color: vec4<f32>,
+    height_min: f32,
+    height_max: f32,
+    colormap: u32,
+    up_axis: u32,
+    // The plane normal in `xyz`, and its offset in `w`.
+    clip_plane: vec4<f32>,
+    clip_enabled: u32,
+    packing_max: f32,
+    freeze_colors: u32,
+    render_stride: u32,
+    alpha_source: u32,
+    alpha: f32,
+    alpha_velocity_max: f32,
+    render_scale: vec3<f32>,
 }
 
 const DEFAULT: u32 = 0;
@@ -32,65 +57,176 @@ const VELOCITY: u32 = 2;
 const CDF_NORMALS: u32 = 3;
 const CDF_DISTANCES: u32 = 4;
 const CDF_SIGNS: u32 = 5;
+const AGE: u32 = 6;
+const DEFORMED: u32 = 7;
+// The colors of `CUSTOM` are written from the CPU, so they are left untouched here.
+const CUSTOM: u32 = 8;
+const HEIGHT: u32 = 9;
+const PACKING: u32 = 10;
+// Lighting is applied when rendering, the colors are the same as `DEFAULT`.
+const LIT: u32 = 11;
+// The disks are oriented when rendering, the colors are the same as `DEFAULT`.
+const FLOW_DISK: u32 = 12;
 
+const ALPHA_CONSTANT: u32 = 1;
+const ALPHA_VELOCITY: u32 = 2;
+
+const VIRIDIS: u32 = 0;
+const TURBO: u32 = 1;
+const GRAYSCALE: u32 = 2;
+
+// Polynomial fit of matplotlib’s viridis colormap (sRGB).
+fn viridis(t: f32) -> vec3<f32> {
+    let c0 = vec3(0.27772733, 0.00540734, 0.33409981);
+    let c1 = vec3(0.10509304, 1.40461353, 1.38459016);
+    let c2 = vec3(-0.33086183, 0.21484756, 0.09509516);
+    let c3 = vec3(-4.63423050, -5.79910097, -19.33244096);
+    let c4 = vec3(6.22826994, 14.17993337, 56.69055260);
+    let c5 = vec3(4.77638500, -13.74514538, -65.35303263);
+    let c6 = vec3(-5.43545586, 4.64585261, 26.31243525);
+    return c0 + t * (c1 + t * (c2 + t * (c3 + t * (c4 + t * (c5 + t * c6)))));
+}
+
+// Polynomial fit of Google’s turbo colormap (sRGB).
+fn turbo(t: f32) -> vec3<f32> {
+    let v4 = vec4(1.0, t, t * t, t * t * t);
+    let v2 = v4.zw * v4.z;
+    return vec3(
+        dot(v4, vec4(0.13572138, 4.61539260, -42.66032258, 132.13108234)) + dot(v2, vec2(-152.94239396, 59.28637943)),
+        dot(v4, vec4(0.09140261, 2.19418839, 4.84296658, -14.18503333)) + dot(v2, vec2(4.27729857, 2.82956604)),
+        dot(v4, vec4(0.10667330, 12.64194608, -60.58204836, 110.36276771)) + dot(v2, vec2(-89.90310912, 27.34824973)),
+    );
+}
+
+// Maps `t` in [0, 1] to a linear RGB color.
+fn colormap(map: u32, t: f32) -> vec3<f32> {
+    var srgb = vec3(t);
+    if map == VIRIDIS {
+        srgb = viridis(t);
+    } else if map == TURBO {
+        srgb = turbo(t);
+    }
+    return pow(clamp(srgb, vec3(0.0), vec3(1.0)), vec3(2.2));
+}
+
+// The slot of the cell containing `pt` in `packing_counts`. Must match `packing3d.wgsl`.
+fn packing_slot(pt: vec3<f32>) -> u32 {
+    let cell = vec3<i32>(floor(pt / grid.cell_width));
+    let hash = (u32(cell.x) * 73856093u) ^ (u32(cell.y) * 19349663u) ^ (u32(cell.z) * 83492791u);
+    return hash % arrayLength(&packing_counts);
+}
 
 struct InstanceData {
     deformation: mat3x3<f32>,
     position: vec3<f32>,
+    age: f32,
     base_color: vec4<f32>,
     color: vec4<f32>,
+    velocity: vec4<f32>,
 }
 
 @compute @workgroup_size(64, 1, 1)
 fn main(
     @builtin(global_invocation_id) tid: vec3<u32>,
 ) {
-    let particle_id = tid.x;
+    let instance_id = tid.x;
+    let particle_id = instance_id * max(config.render_stride, 1u);
 
-    if particle_id < arrayLength(&instances) {
+    if instance_id < arrayLength(&instances) && particle_id < arrayLength(&particles_pos) {
         let def_grad = Particle::deformation_gradient(particles_vol[particle_id]);
-        instances[particle_id].deformation = def_grad;
-        instances[particle_id].position = particles_pos[particle_id].pt;
+        let identity = mat3x3(1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0);
+        let s = config.render_scale;
+        let render_scale = mat3x3(s.x, 0.0, 0.0, 0.0, s.y, 0.0, 0.0, 0.0, s.z);
+        instances[instance_id].deformation = render_scale * (identity + (def_grad - identity) * config.deformation_scale);
+        instances[instance_id].position = particles_pos[particle_id].pt * s;
+        let velocity = particles_vel[particle_id].v;
+        instances[instance_id].velocity = vec4(velocity, length(velocity));
 
-        let color = instances[particle_id].base_color;
+        let color = instances[instance_id].base_color;
+        let previous_color = instances[instance_id].color;
         let cell_width = grid.cell_width;
         let dt = params.dt;
         let max_vel = cell_width / dt;
 
-        if config.mode == DEFAULT {
-            instances[particle_id].color = color;
+        if config.mode == DEFAULT || config.mode == LIT || config.mode == FLOW_DISK {
+            instances[instance_id].color = color;
         } else if config.mode == VELOCITY {
             let vel = particles_vel[particle_id].v;
-            instances[particle_id].color = vec4(abs(vel) * dt * 100.0 + vec3(0.2), color.w);
+            instances[instance_id].color = vec4(abs(vel) * dt * 100.0 + vec3(0.2), color.w);
         } else if config.mode == VOLUME {
             let svd = Svd3::svd(def_grad);
             let color_xyz = (vec3(1.0) - svd.S) / 0.005 + vec3(0.2);
-            instances[particle_id].color = vec4(color_xyz, color.w);
+            instances[instance_id].color = vec4(color_xyz, color.w);
         } else if config.mode == CDF_NORMALS {
             let particle_normal = particles_cdf[particle_id].normal;
             if all(particle_normal == vec3(0.0)) {
-                instances[particle_id].color = vec4(0.0, 0.0, 0.0, color.w);
+                instances[instance_id].color = vec4(0.0, 0.0, 0.0, color.w);
             } else {
                 let n = (particle_normal + vec3(1.0)) / 2.0;
-                instances[particle_id].color = vec4(n.x, n.y, n.z, color.w);
+                instances[instance_id].color = vec4(n.x, n.y, n.z, color.w);
             }
         } else if config.mode == CDF_DISTANCES {
             let d = particles_cdf[particle_id].signed_distance / (cell_width * 1.5);
             if d > 0.0 {
-                instances[particle_id].color = vec4(0.0, abs(d), 0.0, color.w);
+                instances[instance_id].color = vec4(0.0, abs(d), 0.0, color.w);
             } else {
-                instances[particle_id].color = vec4(abs(d), 0.0, 0.0, color.w);
+                instances[instance_id].color = vec4(abs(d), 0.0, 0.0, color.w);
             }
         } else if config.mode == CDF_SIGNS {
              let d = particles_cdf[particle_id].affinity;
              let a = (d >> 16) & (d & 0x0000ffff);
              if d == 0 {
-                 instances[particle_id].color = vec4(0.0, 0.0, 0.0, color.w);
+                 instances[instance_id].color = vec4(0.0, 0.0, 0.0, color.w);
              } else if a == 0 {
-                 instances[particle_id].color = vec4(0.0, 1.0, 0.0, color.w);
+                 instances[instance_id].color = vec4(0.0, 1.0, 0.0, color.w);
              } else {
-                 instances[particle_id].color = vec4(1.0, 0.0, 0.0, color.w);
+                 instances[instance_id].color = vec4(1.0, 0.0, 0.0, color.w);
              }
+         } else if config.mode == AGE {
+            // Fresh particles are lighter, then fade to their base color.
+            let t = clamp(instances[instance_id].age / config.age_fade_duration, 0.0, 1.0);
+            instances[instance_id].color = vec4(mix(vec3(1.0), color.xyz, 0.4 + 0.6 * t), color.w);
+         } else if config.mode == DEFORMED {
+            // Tint expanded particles in red, and compressed particles in blue.
+            let j = determinant(def_grad);
+            let s = clamp((j - 1.0) / 0.005, -1.0, 1.0);
+            let tint = select(vec3(0.2, 0.4, 1.0), vec3(1.0, 0.3, 0.2), s > 0.0);
+            instances[instance_id].color = vec4(mix(color.xyz, tint, abs(s)), color.w);
+         } else if config.mode == HEIGHT {
+            let height = particles_pos[particle_id].pt[config.up_axis];
+            let t = clamp((height - config.height_min) / (config.height_max - config.height_min), 0.0, 1.0);
+            instances[instance_id].color = vec4(colormap(config.colormap, t), color.w);
+         } else if config.mode == PACKING {
+            let count = f32(packing_counts[packing_slot(particles_pos[particle_id].pt)]);
+            let t = clamp(count / config.packing_max, 0.0, 1.0);
+            instances[instance_id].color = vec4(colormap(config.colormap, t), color.w);
          }
+
+        if config.highlight_contacts != 0 && config.mode != CUSTOM {
+            let cdf = particles_cdf[particle_id];
+            if cdf.affinity != 0 && cdf.signed_distance < config.contact_distance {
+                instances[instance_id].color = config.contact_color;
+            }
+        }
+
+        if config.mode != CUSTOM {
+            if config.alpha_source == ALPHA_CONSTANT {
+                instances[instance_id].color.w = config.alpha;
+            } else if config.alpha_source == ALPHA_VELOCITY {
+                let speed = length(particles_vel[particle_id].v);
+                let t = clamp(speed / config.alpha_velocity_max, 0.0, 1.0);
+                instances[instance_id].color.w = mix(config.alpha, 1.0, t);
+            }
+        }
+
+        if config.freeze_colors != 0 {
+            instances[instance_id].color = previous_color;
+        }
+
+        // Collapse the clipped particles so they don’t cover any pixel.
+        let pos = particles_pos[particle_id].pt;
+        if config.clip_enabled != 0 && dot(config.clip_plane.xyz, pos) > config.clip_plane.w {
+            instances[instance_id].deformation = mat3x3<f32>();
+        }
     }
 }
\ No newline at end of file
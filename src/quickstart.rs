@@ -0,0 +1,111 @@
+//! A ready-made demo scene, for trying the plugin out in a few lines.
+//!
+//! This is meant as an on-ramp, not as a base for real projects: it picks the camera, the
+//! Rapier setup and the material for you. See the `cube` example for the same scene built with
+//! the configurable API.
+
+use crate::components::MpmCouplingEnabled;
+use crate::generation::{ParticleMaterial, particles_in_box_local, recommended_spacing};
+use crate::resources::{AppState, PhysicsContext};
+use crate::{WgSparklPlugin, WgSparklSet};
+use bevy::prelude::*;
+use bevy::render::renderer::RenderDevice;
+use bevy_rapier3d::geometry::RapierColliderHandle;
+use bevy_rapier3d::plugin::{RapierPhysicsPlugin, ReadRapierContext};
+use bevy_rapier3d::prelude::{Collider, RigidBody};
+use nalgebra::vector;
+use wgrapier3d::dynamics::body::{BodyCoupling, BodyCouplingEntry};
+use wgsparkl3d::models::{DruckerPrager, ElasticCoefficients};
+use wgsparkl3d::solver::SimulationParams;
+
+/// Adds Rapier, the [`WgSparklPlugin`], and a block of sand falling on a coupled ground.
+///
+/// Add it along with Bevy’s `DefaultPlugins`, see the `quickstart` example.
+pub struct QuickstartPlugin;
+
+impl Plugin for QuickstartPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(RapierPhysicsPlugin::<()>::default())
+            .add_plugins(WgSparklPlugin::default())
+            .add_systems(Startup, spawn_demo_scene)
+            .add_systems(
+                Update,
+                setup_demo_particles.in_set(WgSparklSet::PrepareCoupling),
+            );
+    }
+}
+
+/// Spawns the camera, the light and the coupled ground of the [`QuickstartPlugin`] scene.
+pub fn spawn_demo_scene(mut commands: Commands) {
+    commands.spawn((
+        Camera3d::default(),
+        Transform::from_xyz(-30.0, 30.0, 100.0).looking_at(Vec3::new(0.0, 10.0, 0.0), Vec3::Y),
+    ));
+    commands.spawn((
+        DirectionalLight::default(),
+        Transform::from_xyz(50.0, 100.0, 50.0).looking_at(Vec3::ZERO, Vec3::Y),
+    ));
+    commands.spawn((
+        Transform::from_xyz(0.0, -2.0, 0.0),
+        Collider::cuboid(50.0, 2.0, 50.0),
+        RigidBody::Fixed,
+        MpmCouplingEnabled,
+    ));
+}
+
+/// Sets up a block of sand above the ground once the Rapier colliders exist.
+pub fn setup_demo_particles(
+    mut commands: Commands,
+    device: Res<RenderDevice>,
+    mut app_state: ResMut<AppState>,
+    rapier: ReadRapierContext,
+    coupling: Query<&RapierColliderHandle, With<MpmCouplingEnabled>>,
+) {
+    if rapier.rapier_context.get_single().is_err() {
+        return; // Rapier isn’t initialized yet.
+    }
+
+    let rapier = rapier.single();
+
+    if rapier.colliders.colliders.is_empty() || app_state.particles_initialized {
+        return;
+    }
+
+    app_state.particles_initialized = true;
+    app_state.num_substeps = 8;
+    app_state.render_coupled_colliders = true;
+
+    let coupling: Vec<_> = coupling
+        .iter()
+        .map(|co_handle| BodyCouplingEntry {
+            body: rapier.colliders.colliders[co_handle.0].parent().unwrap(),
+            collider: co_handle.0,
+            mode: BodyCoupling::OneWay,
+        })
+        .collect();
+
+    let params = SimulationParams {
+        gravity: vector![0.0, -9.81, 0.0],
+        dt: (1.0 / 60.0) / (app_state.num_substeps as f32),
+    };
+    let cell_width = 1.0;
+    let model = ElasticCoefficients::from_young_modulus(10_000_000.0, 0.2);
+    let material =
+        ParticleMaterial::new(2700.0, model).with_plasticity(DruckerPrager::new(10_000_000.0, 0.2));
+    let particles = particles_in_box_local(
+        &Transform::from_xyz(0.0, 15.0, 0.0),
+        Vec3::splat(8.0),
+        recommended_spacing(cell_width),
+        &material,
+    );
+
+    commands.insert_resource(PhysicsContext::from_particles(
+        device.wgpu_device(),
+        params,
+        particles,
+        &rapier.rigidbody_set.bodies,
+        &rapier.colliders.colliders,
+        coupling,
+        cell_width,
+    ));
+}
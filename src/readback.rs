@@ -0,0 +1,333 @@
+use crate::region::ParticleRegion;
+use crate::resources::{AppState, PhysicsContext, SimulationDiagnostics};
+use async_channel::{Receiver, Sender};
+use bevy::prelude::*;
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+use bevy::tasks::ComputeTaskPool;
+use std::ops::Range;
+use wgcore::Shader;
+use wgcore::kernel::{KernelInvocationBuilder, KernelInvocationQueue};
+use wgcore::tensor::{GpuScalar, GpuVector};
+use wgebra::WgSvd2;
+use wgebra::WgSvd3;
+use wgpu::{Buffer, BufferUsages, ComputePipeline, Device};
+use wgsparkl3d::grid::grid::WgGrid;
+use wgsparkl3d::solver::{GpuParticles, WgParticle};
+
+/// Per-particle data gathered from the solver buffers for CPU-side analysis.
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Copy, Clone, PartialEq, Debug, Default)]
+#[repr(C)]
+pub struct ParticleSample {
+    pub position: [f32; 3],
+    /// Signed distance to the closest coupled collider, as computed by the solver’s CDF stages.
+    pub signed_distance: f32,
+    pub velocity: [f32; 3],
+    /// Collider affinity bits from the CDF stages. Zero if the particle isn’t near any collider.
+    pub affinity: u32,
+    /// The current density of the particle, i.e., its mass divided by its current volume.
+    ///
+    /// The current volume is the rest volume scaled by the determinant of the deformation
+    /// gradient, so this equals the material density while the particle isn’t compressed.
+    pub density: f32,
+    pub mass: f32,
+    /// The index of the particle in the simulation.
+    pub index: u32,
+    _padding: f32,
+}
+
+impl ParticleSample {
+    /// How deep this particle is inside a coupled collider, or zero if it isn’t penetrating.
+    pub fn penetration(&self) -> f32 {
+        if self.affinity == 0 {
+            0.0
+        } else {
+            (-self.signed_distance).max(0.0)
+        }
+    }
+}
+
+/// Counts `values` into `num_bins` bins of equal width spanning `range`.
+///
+/// Values outside of `range` are counted in the first or last bin. Useful for quick validation
+/// of per-particle quantities, e.g., the densities from [`ParticleReadback::densities`].
+pub fn histogram(values: &[f32], range: Range<f32>, num_bins: usize) -> Vec<usize> {
+    let mut bins = vec![0; num_bins];
+    if num_bins == 0 {
+        return bins;
+    }
+
+    let width = (range.end - range.start) / num_bins as f32;
+    for value in values {
+        let bin = ((value - range.start) / width).floor().max(0.0) as usize;
+        bins[bin.min(num_bins - 1)] += 1;
+    }
+    bins
+}
+
+#[derive(Shader)]
+#[shader(
+    src = "readback3d.wgsl",
+    derive(WgParticle, WgGrid, WgSvd2, WgSvd3),
+    composable = false
+)]
+pub struct WgParticleReadback {
+    main: ComputePipeline,
+    gather_region: ComputePipeline,
+}
+
+/// The GPU representation of a [`ParticleRegion`].
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Copy, Clone, PartialEq, Debug, Default)]
+#[repr(C)]
+pub struct GpuRegion {
+    mins: [f32; 4],
+    maxs: [f32; 4],
+}
+
+impl From<ParticleRegion> for GpuRegion {
+    fn from(region: ParticleRegion) -> Self {
+        Self {
+            mins: region.mins.extend(0.0).to_array(),
+            maxs: region.maxs.extend(0.0).to_array(),
+        }
+    }
+}
+
+impl WgParticleReadback {
+    pub fn queue<'a>(
+        &'a self,
+        queue: &mut KernelInvocationQueue<'a>,
+        particles: &GpuParticles,
+        samples: &GpuVector<ParticleSample>,
+    ) {
+        KernelInvocationBuilder::new(queue, &self.main)
+            .bind0([
+                samples.buffer(),
+                particles.positions.buffer(),
+                particles.velocities.buffer(),
+                particles.cdf.buffer(),
+                particles.volumes.buffer(),
+            ])
+            .queue(particles.positions.len().div_ceil(64) as u32);
+    }
+
+    /// Appends the samples of the particles inside of `region` to `samples`, and counts them
+    /// into `num_samples` (which must be zero initially).
+    pub fn queue_region<'a>(
+        &'a self,
+        queue: &mut KernelInvocationQueue<'a>,
+        particles: &GpuParticles,
+        region: &GpuScalar<GpuRegion>,
+        num_samples: &GpuVector<u32>,
+        samples: &Buffer,
+    ) {
+        KernelInvocationBuilder::new(queue, &self.gather_region)
+            .bind0([
+                samples,
+                particles.positions.buffer(),
+                particles.velocities.buffer(),
+                particles.cdf.buffer(),
+                particles.volumes.buffer(),
+                region.buffer(),
+                num_samples.buffer(),
+            ])
+            .queue(particles.positions.len().div_ceil(64) as u32);
+    }
+}
+
+struct ReadbackBuffers {
+    samples: GpuVector<ParticleSample>,
+    staging: GpuVector<ParticleSample>,
+    len: usize,
+}
+
+impl ReadbackBuffers {
+    fn new(device: &Device, len: usize) -> Self {
+        let zeros = vec![ParticleSample::default(); len];
+        Self {
+            samples: GpuVector::init(
+                device,
+                &zeros,
+                BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+            ),
+            staging: GpuVector::init(
+                device,
+                &zeros,
+                BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            ),
+            len,
+        }
+    }
+}
+
+/// Asynchronous readback of the particle state from the GPU.
+///
+/// When `enabled`, or when [`Self::request`] was called since the previous step, the particle
+/// state is gathered and read back after the step. Results arrive at least one frame late, and
+/// a new readback is only started once the previous one completed.
+#[derive(Resource)]
+pub struct ParticleReadback {
+    /// Reads the particles back after every step, regardless of the requests.
+    pub enabled: bool,
+    /// The latest particle samples read back from the GPU.
+    pub samples: Vec<ParticleSample>,
+    pipeline: WgParticleReadback,
+    buffers: Option<ReadbackBuffers>,
+    in_flight: bool,
+    requested: bool,
+    num_readbacks: u64,
+    snd: Sender<(ReadbackBuffers, Option<Vec<ParticleSample>>)>,
+    rcv: Receiver<(ReadbackBuffers, Option<Vec<ParticleSample>>)>,
+}
+
+impl ParticleReadback {
+    pub fn new(device: &Device) -> Self {
+        let (snd, rcv) = async_channel::unbounded();
+        Self {
+            enabled: false,
+            samples: vec![],
+            pipeline: WgParticleReadback::from_device(device).unwrap(),
+            buffers: None,
+            in_flight: false,
+            requested: false,
+            num_readbacks: 0,
+            snd,
+            rcv,
+        }
+    }
+
+    /// Requests a readback after the next step.
+    ///
+    /// Features relying on the samples call this every frame while they are in use, so the
+    /// readbacks stop as soon as nothing needs them anymore.
+    pub fn request(&mut self) {
+        self.requested = true;
+    }
+
+    /// Whether the particles are read back after the next step.
+    pub fn is_requested(&self) -> bool {
+        self.enabled || self.requested
+    }
+
+    /// The number of readbacks completed so far. This changes whenever `samples` is updated.
+    pub fn num_readbacks(&self) -> u64 {
+        self.num_readbacks
+    }
+
+    /// The density of each particle from the latest readback, aligned with the particle indices.
+    pub fn densities(&self) -> Vec<f32> {
+        self.samples.iter().map(|s| s.density).collect()
+    }
+
+    /// The total mass of the particles from the latest readback.
+    pub fn total_mass(&self) -> f32 {
+        self.samples.iter().map(|s| s.mass).sum()
+    }
+
+    /// The total linear momentum of the particles from the latest readback.
+    pub fn total_momentum(&self) -> Vec3 {
+        self.samples
+            .iter()
+            .map(|s| Vec3::from(s.velocity) * s.mass)
+            .sum()
+    }
+
+    /// The center of mass of the particles from the latest readback, or `None` if there is no
+    /// sample.
+    pub fn center_of_mass(&self) -> Option<Vec3> {
+        let total_mass = self.total_mass();
+        (total_mass > 0.0).then(|| {
+            self.samples
+                .iter()
+                .map(|s| Vec3::from(s.position) * s.mass)
+                .sum::<Vec3>()
+                / total_mass
+        })
+    }
+}
+
+pub fn receive_particle_readback(
+    mut readback: ResMut<ParticleReadback>,
+    mut diagnostics: ResMut<SimulationDiagnostics>,
+) {
+    while let Ok((buffers, samples)) = readback.rcv.try_recv() {
+        readback.buffers = Some(buffers);
+        readback.in_flight = false;
+
+        if let Some(samples) = samples {
+            diagnostics.update_penetration(&samples);
+            readback.samples = samples;
+            readback.num_readbacks += 1;
+        }
+    }
+}
+
+pub fn queue_particle_readback(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    app_state: Res<AppState>,
+    physics: Option<Res<PhysicsContext>>,
+    mut readback: ResMut<ParticleReadback>,
+) {
+    let requested = readback.is_requested();
+    // The features needing the samples request them again for the next step.
+    readback.requested = false;
+
+    let Some(physics) = physics else {
+        return;
+    };
+
+    if !requested || readback.in_flight || app_state.device_lost {
+        return;
+    }
+
+    let device = render_device.wgpu_device();
+    let num_particles = physics.data.particles.positions.len() as usize;
+    let buffers = match readback.buffers.take() {
+        Some(buffers) if buffers.len == num_particles => buffers,
+        _ => ReadbackBuffers::new(device, num_particles),
+    };
+
+    let mut encoder = device.create_command_encoder(&Default::default());
+    {
+        let mut queue = KernelInvocationQueue::new(device);
+        readback
+            .pipeline
+            .queue(&mut queue, &physics.data.particles, &buffers.samples);
+        queue.encode(&mut encoder, None);
+    }
+    buffers.staging.copy_from(&mut encoder, &buffers.samples);
+    render_queue.0.submit(Some(encoder.finish()));
+
+    readback.in_flight = true;
+    let readback_snd = readback.snd.clone();
+    let render_device = RenderDevice::clone(&render_device);
+    let readback_future = async move {
+        let samples = buffers.staging.read(render_device.wgpu_device()).await.ok();
+        readback_snd.send((buffers, samples)).await.unwrap();
+    };
+
+    ComputeTaskPool::get().spawn(readback_future).detach();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_without_bins() {
+        assert!(histogram(&[0.0, 1.0], 0.0..1.0, 0).is_empty());
+    }
+
+    #[test]
+    fn histogram_bin_edges() {
+        // Each bin includes its lower edge, and the end of the range falls in the last bin.
+        let values = [0.0, 0.24, 0.25, 0.5, 0.99, 1.0];
+        assert_eq!(histogram(&values, 0.0..1.0, 4), vec![2, 1, 1, 2]);
+    }
+
+    #[test]
+    fn histogram_clamps_out_of_range_values() {
+        let values = [-10.0, -0.1, 1.5, 100.0];
+        assert_eq!(histogram(&values, 0.0..1.0, 2), vec![2, 2]);
+    }
+}
@@ -0,0 +1,76 @@
+#define_import_path wgsparkl::examples::readback
+
+#import wgsparkl::solver::particle as Particle;
+
+@group(0) @binding(0)
+var<storage, read_write> samples: array<ParticleSample>;
+@group(0) @binding(1)
+var<storage, read> particles_pos: array<Particle::Position>;
+@group(0) @binding(2)
+var<storage, read> particles_vel: array<Particle::Velocity>;
+@group(0) @binding(3)
+var<storage, read> particles_cdf: array<Particle::Cdf>;
+@group(0) @binding(4)
+var<storage, read> particles_vol: array<Particle::Volume>;
+@group(0) @binding(5)
+var<storage, read> region: Region;
+@group(0) @binding(6)
+var<storage, read_write> num_samples: atomic<u32>;
+
+struct ParticleSample {
+    position: vec3<f32>,
+    signed_distance: f32,
+    velocity: vec3<f32>,
+    affinity: u32,
+    density: f32,
+    mass: f32,
+    index: u32,
+}
+
+struct Region {
+    mins: vec3<f32>,
+    maxs: vec3<f32>,
+}
+
+fn sample(particle_id: u32) -> ParticleSample {
+    let cdf = particles_cdf[particle_id];
+    let vol = particles_vol[particle_id];
+    let def_grad = Particle::deformation_gradient(vol);
+    let density = vol.mass / (vol.init_volume * determinant(def_grad));
+    return ParticleSample(
+        particles_pos[particle_id].pt,
+        cdf.signed_distance,
+        particles_vel[particle_id].v,
+        cdf.affinity,
+        density,
+        vol.mass,
+        particle_id,
+    );
+}
+
+@compute @workgroup_size(64, 1, 1)
+fn main(
+    @builtin(global_invocation_id) tid: vec3<u32>,
+) {
+    let particle_id = tid.x;
+
+    if particle_id < arrayLength(&samples) {
+        samples[particle_id] = sample(particle_id);
+    }
+}
+
+// Appends the particles inside of the region to `samples`, in an arbitrary order.
+@compute @workgroup_size(64, 1, 1)
+fn gather_region(
+    @builtin(global_invocation_id) tid: vec3<u32>,
+) {
+    let particle_id = tid.x;
+
+    if particle_id < arrayLength(&particles_pos) {
+        let pt = particles_pos[particle_id].pt;
+        if all(pt >= region.mins) && all(pt <= region.maxs) {
+            let id = atomicAdd(&num_samples, 1u);
+            samples[id] = sample(particle_id);
+        }
+    }
+}
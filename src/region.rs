@@ -0,0 +1,82 @@
+//! Counting the particles inside of user-defined regions.
+
+use crate::events::ParticleRegionCounts;
+use crate::readback::ParticleReadback;
+use bevy::prelude::*;
+
+/// An axis-aligned box watched by the [`ParticleRegionWatcher`].
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ParticleRegion {
+    pub mins: Vec3,
+    pub maxs: Vec3,
+}
+
+impl ParticleRegion {
+    pub fn new(mins: Vec3, maxs: Vec3) -> Self {
+        Self { mins, maxs }
+    }
+
+    /// The region centered on `center`, with the given half-extents.
+    pub fn from_center(center: Vec3, half_extents: Vec3) -> Self {
+        Self::new(center - half_extents, center + half_extents)
+    }
+
+    pub fn contains(&self, point: Vec3) -> bool {
+        point.cmpge(self.mins).all() && point.cmple(self.maxs).all()
+    }
+}
+
+/// Counts the particles inside of each of the `regions`, sending a [`ParticleRegionCounts`]
+/// event with the results.
+///
+/// While any region is registered, the [`ParticleReadback`] is requested, and one readback out of
+/// `interval` is scanned. The counts are thus at least one frame behind the simulation, and
+/// `interval` readbacks apart. Scanning is done on the CPU and costs
+/// `O(num_particles * num_regions)`, on top of the readback itself which grows linearly with the
+/// number of particles: raise `interval` for large simulations.
+#[derive(Resource, Clone, Debug)]
+pub struct ParticleRegionWatcher {
+    pub regions: Vec<ParticleRegion>,
+    /// Scan one readback out of `interval`.
+    pub interval: u64,
+}
+
+impl Default for ParticleRegionWatcher {
+    fn default() -> Self {
+        Self {
+            regions: vec![],
+            interval: 1,
+        }
+    }
+}
+
+pub fn count_particles_in_regions(
+    watcher: Res<ParticleRegionWatcher>,
+    mut readback: ResMut<ParticleReadback>,
+    mut events: EventWriter<ParticleRegionCounts>,
+    mut last_readback: Local<u64>,
+) {
+    if watcher.regions.is_empty() {
+        return;
+    }
+
+    readback.request();
+
+    let num_readbacks = readback.num_readbacks();
+    if num_readbacks == *last_readback || num_readbacks % watcher.interval.max(1) != 0 {
+        return;
+    }
+    *last_readback = num_readbacks;
+
+    let mut counts = vec![0; watcher.regions.len()];
+    for sample in &readback.samples {
+        let point = Vec3::from(sample.position);
+        for (region, count) in watcher.regions.iter().zip(counts.iter_mut()) {
+            if region.contains(point) {
+                *count += 1;
+            }
+        }
+    }
+
+    events.send(ParticleRegionCounts { counts });
+}
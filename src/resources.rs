@@ -1,15 +1,46 @@
+use crate::coloring::CustomColoring;
+use crate::coupling::{inflate_coupled_colliders, skip_unsupported_colliders};
+use crate::generation::recommended_spacing;
+use crate::packing::PackingPass;
+use crate::particle_age::ParticleAgePass;
 use crate::prep_vertex_buffer::{GpuRenderConfig, RenderConfig, WgPrepVertexBuffer};
-use bevy::prelude::Resource;
+use crate::readback::{GpuRegion, ParticleSample, WgParticleReadback};
+use crate::region::ParticleRegion;
+use crate::speed_limit::SpeedLimitPass;
+use bevy::prelude::{Color, Mesh, Resource, warn};
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+use nalgebra::Vector3;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ops::Range;
+use std::sync::OnceLock;
+use std::time::Duration;
+use wgcore::Shader;
 use wgcore::hot_reloading::HotReloadState;
+use wgcore::kernel::KernelInvocationQueue;
+use wgcore::re_exports::encase::StorageBuffer;
+use wgcore::tensor::{GpuScalar, GpuVector};
 use wgcore::timestamps::GpuTimestamps;
+use wgpu::{BufferDescriptor, BufferUsages, CommandEncoder, Device, Queue};
+use wgrapier3d::dynamics::body::{BodyCoupling, BodyCouplingEntry};
+use wgsparkl3d::models::{DruckerPrager, ElasticCoefficients};
 use wgsparkl3d::pipeline::{MpmData, MpmPipeline};
-use wgsparkl3d::solver::Particle;
+use wgsparkl3d::rapier::dynamics::RigidBodySet;
+use wgsparkl3d::rapier::geometry::{ColliderHandle, ColliderSet};
+use wgsparkl3d::solver::{GpuParticles, Particle, SimulationParams};
+use wgsparkl3d::wgparry::math::GpuSim;
+use wgsparkl3d::wgrapier::dynamics::GpuVelocity;
 
 #[derive(Resource)]
 pub struct AppState {
     pub run_state: RunState,
     pub render_config: RenderConfig,
     pub gpu_render_config: GpuRenderConfig,
+    /// The compute kernels of the solver.
+    ///
+    /// NOTE: the solver doesn’t expose any workgroup size or dispatch tunable: its kernels, like
+    /// the ones of this crate, use a workgroup size of 64 fixed in their WGSL sources. Use the
+    /// per-stage [`Timestamps`] to compare the performance of different particle counts, grid
+    /// capacities, or numbers of substeps instead.
     pub pipeline: MpmPipeline,
     pub prep_vertex_buffer: WgPrepVertexBuffer,
     pub num_substeps: usize,
@@ -18,12 +49,788 @@ pub struct AppState {
     pub selected_scene: usize,
     pub hot_reload: HotReloadState,
     pub particles_initialized: bool,
+    /// Keep refreshing the particle instance buffer while the simulation is paused.
+    pub render_while_paused: bool,
+    pub timestamp_processing: TimestampProcessing,
+    /// Track the simulated age of each particle (see [`ParticleAgePass`]).
+    pub track_particle_age: bool,
+    pub particle_age: ParticleAgePass,
+    pub packing: PackingPass,
+    /// Set when the GPU device was lost. No more work is submitted to the GPU after that.
+    pub device_lost: bool,
+    /// The callback computing the particle colors in [`RenderMode::Custom`](crate::prep_vertex_buffer::RenderMode::Custom).
+    pub custom_coloring: Option<CustomColoring>,
+    /// Keeps the simulation paused until this much time elapsed since the app started.
+    ///
+    /// Reset to `None` once the simulation starts, or if `run_state` is changed before that.
+    pub auto_start_after: Option<Duration>,
+    /// Draw the velocities uploaded to the GPU for each coupled body (see [`draw_coupling_velocities`](crate::debug::draw_coupling_velocities)).
+    pub debug_coupling_velocity: bool,
+    /// Render the colliders with [`MpmCouplingEnabled`](crate::components::MpmCouplingEnabled)
+    /// (see [`update_coupled_collider_meshes`](crate::collider_mesh::update_coupled_collider_meshes)).
+    pub render_coupled_colliders: bool,
+    /// The number of simulation steps run so far.
+    pub num_steps: u64,
+    /// The palette the particle base colors are picked from (see [`ColorSource`](crate::prep_vertex_buffer::ColorSource)).
+    ///
+    /// Falls back to [`DEFAULT_PALETTE`](crate::startup::DEFAULT_PALETTE) if `None` or empty.
+    /// Only applies when the particle graphics are created.
+    pub palette: Option<Vec<Color>>,
+    /// Whether the particle instance buffer is double-buffered.
+    ///
+    /// If `true`, the particles are rendered from the buffer written during the previous frame
+    /// while the compute passes write the current frame’s, so rendering never waits on the
+    /// simulation. This adds one frame of latency to the rendered particles and a copy of the
+    /// instance buffer per frame, so measure the frame time on the target hardware before
+    /// enabling it. Only applies when the particle graphics are created.
+    pub double_buffer_instances: bool,
+    /// The index of the next substep to run within the current step.
+    ///
+    /// This is only non-zero after running substeps individually with [`RunState::StepSubstep`].
+    /// The next [`RunState::Running`] or [`RunState::Step`] frame runs the remaining substeps of
+    /// the step.
+    pub next_substep: usize,
+    /// Whether the coupled bodies are uploaded from the Rapier context at the start of each
+    /// step.
+    ///
+    /// Disable this to drive them from another source with
+    /// [`PhysicsContext::upload_body_poses`].
+    pub sync_rapier_bodies: bool,
+    /// How the length of the simulation steps is chosen.
+    pub time_step_mode: TimeStepMode,
+    /// If set, the gravity of a new simulation is scaled from zero to its full value over this
+    /// much simulated time, so dense blocks aren’t shocked by the full gravity at the first step.
+    ///
+    /// See [`PhysicsContext::set_gravity_scale`].
+    pub gravity_ramp: Option<Duration>,
+    /// Removes the sources of non-determinism controlled by this crate: the steps always have the
+    /// fixed `dt` of the [`SimulationParams`], even with [`TimeStepMode::Realtime`].
+    ///
+    /// This doesn’t make the GPU results bit-identical across runs. The solver has no
+    /// deterministic mode (nor any seed: it doesn’t use random numbers, and the random particle
+    /// generation is seeded by [`MpmRng`](crate::rng::MpmRng)), and its grid sort relies on
+    /// atomics, so the order in which the contributions of the particles are summed may change
+    /// between runs, and floating-point sums depend on their order. Results also differ across
+    /// GPUs, drivers and backends. Runs with the same setup are statistically equivalent, but
+    /// diverge bit-wise after a few steps, which is amplified by chaotic scenes (e.g. granular
+    /// flows). Regression tests should compare aggregate quantities with tolerances instead.
+    pub deterministic: bool,
+    /// If set, the speed of the particles is clamped to this value (in m/s) after each substep.
+    ///
+    /// This is a band-aid for difficult scenes: it keeps a few fast particles from exploding the
+    /// simulation without increasing the number of substeps. It isn’t physical though: the
+    /// clamped particles lose kinetic energy and momentum (e.g. free falls stop accelerating at
+    /// this speed, and impacts are damped), so pick a value well above the speeds expected in the
+    /// scene. The particles already moved during the substep that exceeded the limit, only the
+    /// next substeps see the clamped velocity.
+    pub max_particle_speed: Option<f32>,
+    pub speed_limit: SpeedLimitPass,
+}
+
+/// Inserted instead of the [`AppState`] when the GPU device can’t run the simulation (see
+/// [`missing_device_requirements`](crate::startup::missing_device_requirements)).
+///
+/// No kernel is compiled in that case, and the systems of the [`WgSparklSet`](crate::WgSparklSet)s
+/// don’t run, so the app keeps running without the particles.
+#[derive(Resource, Clone, Debug)]
+pub struct UnsupportedDevice {
+    /// The unmet requirements.
+    pub missing: Vec<String>,
 }
 
 #[derive(Resource)]
 pub struct PhysicsContext {
     pub data: MpmData,
-    pub particles: Vec<Particle>,
+    particles: Option<Vec<Particle>>,
+    params: SimulationParams,
+    gravity_scale: f32,
+    realtime_dt: Option<f32>,
+    cell_width: f32,
+    grid_capacity: u32,
+    particle_radius: f32,
+    num_particles: usize,
+    region_readback: OnceLock<WgParticleReadback>,
+    /// The unsupported coupled colliders already logged about.
+    reported_colliders: HashSet<ColliderHandle>,
+    coupling_margin: f32,
+}
+
+impl PhysicsContext {
+    /// Initializes the simulation of `particles`, coupled with the rigid-bodies listed in `coupling`.
+    ///
+    /// Sensor and compound colliders are removed from `coupling` (see
+    /// [`skip_unsupported_colliders`]).
+    ///
+    /// The particles are dropped once uploaded to the GPU, only their count and radius are kept
+    /// for rendering. Use [`Self::with_cpu_particles`] to keep a CPU-side copy.
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_select_coupling(
+        device: &Device,
+        params: SimulationParams,
+        particles: Vec<Particle>,
+        bodies: &RigidBodySet,
+        colliders: &ColliderSet,
+        coupling: Vec<BodyCouplingEntry>,
+        cell_width: f32,
+        grid_capacity: u32,
+    ) -> Self {
+        warn_undersampled(&particles, cell_width);
+        let mut reported_colliders = HashSet::new();
+        let coupling = skip_unsupported_colliders(colliders, coupling, &mut reported_colliders);
+
+        let data = MpmData::with_select_coupling(
+            device,
+            params,
+            &particles,
+            bodies,
+            colliders,
+            coupling,
+            cell_width,
+            grid_capacity,
+        );
+        Self {
+            data,
+            particle_radius: particles
+                .first()
+                .map(|p| p.volume.init_radius())
+                .unwrap_or_default(),
+            num_particles: particles.len(),
+            particles: None,
+            params,
+            gravity_scale: 1.0,
+            realtime_dt: None,
+            cell_width,
+            grid_capacity,
+            region_readback: OnceLock::new(),
+            reported_colliders,
+            coupling_margin: 0.0,
+        }
+    }
+
+    /// Same as [`Self::with_select_coupling`], with a grid capacity fitted to the initial
+    /// particles (see [`Self::recommended_grid_capacity`]).
+    ///
+    /// Use the explicit capacity of [`Self::with_select_coupling`] instead if the particles are
+    /// expected to cover a much larger region later on, e.g., with emitters or long-running flows.
+    pub fn from_particles(
+        device: &Device,
+        params: SimulationParams,
+        particles: Vec<Particle>,
+        bodies: &RigidBodySet,
+        colliders: &ColliderSet,
+        coupling: Vec<BodyCouplingEntry>,
+        cell_width: f32,
+    ) -> Self {
+        let grid_capacity = Self::recommended_grid_capacity(&particles, cell_width);
+        Self::with_select_coupling(
+            device,
+            params,
+            particles,
+            bodies,
+            colliders,
+            coupling,
+            cell_width,
+            grid_capacity,
+        )
+    }
+
+    /// A grid capacity for simulating `particles` with cells of `cell_width`.
+    ///
+    /// This counts the sparse grid blocks touched by the particles and their neighbor blocks,
+    /// doubled so the particles can spread out, and rounded up to a multiple of 1024. A capacity
+    /// too small for the region covered by the particles makes the simulation unstable, while a
+    /// needlessly large one wastes GPU memory.
+    pub fn recommended_grid_capacity(particles: &[Particle], cell_width: f32) -> u32 {
+        let block_width = cell_width * GRID_BLOCK_WIDTH;
+        let mut blocks = HashSet::new();
+        for particle in particles {
+            let block = (particle.position / block_width).map(|x| x.floor() as i32);
+            for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for dz in -1..=1 {
+                        blocks.insert(block + Vector3::new(dx, dy, dz));
+                    }
+                }
+            }
+        }
+
+        ((blocks.len() as u32) * 2).max(1).next_multiple_of(1024)
+    }
+
+    /// The number of grid blocks allocated by the simulation.
+    pub fn grid_capacity(&self) -> u32 {
+        self.grid_capacity
+    }
+
+    /// Initializes the simulation of `num_particles` particles generated on the GPU.
+    ///
+    /// The particle buffers are allocated on the GPU without uploading anything (wgpu zeroes new
+    /// buffers), then `init` records compute passes filling them (e.g., positions sampled from
+    /// procedural noise) before the first step. `init` must write the positions, velocities and
+    /// volumes of every particle. The materials of `template` are given to all the particles, and
+    /// its radius is used for rendering.
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_gpu_initialization(
+        device: &Device,
+        queue: &Queue,
+        params: SimulationParams,
+        template: Particle,
+        num_particles: usize,
+        init: impl FnOnce(&Device, &mut CommandEncoder, &GpuParticles),
+        bodies: &RigidBodySet,
+        colliders: &ColliderSet,
+        coupling: Vec<BodyCouplingEntry>,
+        cell_width: f32,
+        grid_capacity: u32,
+    ) -> Self {
+        let mut result = Self::with_select_coupling(
+            device,
+            params,
+            vec![],
+            bodies,
+            colliders,
+            coupling,
+            cell_width,
+            grid_capacity,
+        );
+        result.particle_radius = template.volume.init_radius();
+        result.num_particles = num_particles;
+
+        let len = num_particles as u32;
+        let usages = BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST;
+        let particles = &mut result.data.particles;
+        particles.positions = GpuVector::uninit(device, len, usages);
+        particles.velocities = GpuVector::uninit(device, len, usages);
+        particles.volumes = GpuVector::uninit(device, len, usages);
+        particles.affines = GpuVector::uninit(device, len, usages);
+        particles.cdf = GpuVector::uninit(device, len, usages);
+        particles.sorted_ids = GpuVector::uninit(device, len, usages);
+        particles.node_linked_lists = GpuVector::uninit(device, len, usages);
+        let models = &mut result.data.models;
+        models.linear_elasticity = GpuVector::uninit(device, len, usages);
+        models.drucker_prager_plasticity = GpuVector::uninit(device, len, usages);
+        result.set_group_model(queue, 0..num_particles, template.model, template.plasticity);
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        init(device, &mut encoder, &result.data.particles);
+        queue.submit(Some(encoder.finish()));
+
+        result
+    }
+
+    /// Keeps `particles` as the CPU-side copy of the initial particle state.
+    ///
+    /// This doubles the memory footprint of the particles, but is needed by the color sources
+    /// based on the particle positions or materials. `particles` must be the particles this context was created with.
+    pub fn with_cpu_particles(mut self, particles: Vec<Particle>) -> Self {
+        assert_eq!(particles.len(), self.num_particles);
+        self.particles = Some(particles);
+        self
+    }
+
+    /// The CPU-side copy of the initial particle state, if kept with [`Self::with_cpu_particles`].
+    pub fn particles(&self) -> Option<&[Particle]> {
+        self.particles.as_deref()
+    }
+
+    /// The simulation parameters (gravity and substep length).
+    pub fn sim_params(&self) -> SimulationParams {
+        self.params
+    }
+
+    /// Changes the simulation parameters, taking effect at the next step.
+    ///
+    /// The parameters are also used for later rebuilds (e.g. [`Self::set_coupling`]).
+    pub fn set_sim_params(&mut self, queue: &Queue, params: SimulationParams) {
+        self.params = params;
+        queue.write_buffer(
+            self.data.sim_params.params.buffer(),
+            0,
+            bytemuck::bytes_of(&self.scaled_sim_params()),
+        );
+    }
+
+    /// The factor applied to the gravity of [`Self::sim_params`] on the GPU.
+    pub fn gravity_scale(&self) -> f32 {
+        self.gravity_scale
+    }
+
+    /// Scales the gravity simulated on the GPU, taking effect at the next step.
+    ///
+    /// [`Self::sim_params`] keeps returning the unscaled gravity. This is driven by
+    /// [`AppState::gravity_ramp`] if it is set.
+    pub fn set_gravity_scale(&mut self, queue: &Queue, scale: f32) {
+        self.gravity_scale = scale;
+        self.set_sim_params(queue, self.params);
+    }
+
+    /// The substep length overriding the `dt` of [`Self::sim_params`] on the GPU, if any.
+    pub fn realtime_dt(&self) -> Option<f32> {
+        self.realtime_dt
+    }
+
+    /// Overrides the substep length simulated on the GPU, taking effect at the next step.
+    ///
+    /// [`Self::sim_params`] keeps returning the base `dt`, which is simulated again once the
+    /// override is removed with `None`. This is driven by [`TimeStepMode::Realtime`].
+    pub fn set_realtime_dt(&mut self, queue: &Queue, dt: Option<f32>) {
+        self.realtime_dt = dt;
+        self.set_sim_params(queue, self.params);
+    }
+
+    /// The substep length currently simulated: the [`Self::realtime_dt`] if set, or the `dt` of
+    /// [`Self::sim_params`].
+    pub fn substep_dt(&self) -> f32 {
+        self.realtime_dt.unwrap_or(self.params.dt)
+    }
+
+    fn scaled_sim_params(&self) -> SimulationParams {
+        SimulationParams {
+            dt: self.substep_dt(),
+            ..scale_gravity(self.params, self.gravity_scale)
+        }
+    }
+
+    /// The radius of the particles, used for rendering.
+    pub fn particle_radius(&self) -> f32 {
+        self.particle_radius
+    }
+
+    /// The number of simulated particles.
+    pub fn num_particles(&self) -> usize {
+        self.num_particles
+    }
+
+    /// The width of the cells of the simulation grid.
+    pub fn cell_width(&self) -> f32 {
+        self.cell_width
+    }
+
+    /// Extracts a triangle mesh of the surface of the particle cloud, sampled on cells of width
+    /// `cell_width`.
+    ///
+    /// `samples` are the particle samples of this simulation, e.g., from
+    /// [`ParticleReadback`](crate::readback::ParticleReadback). See
+    /// [`surface::extract_surface_mesh`](crate::surface::extract_surface_mesh) for the meaning of
+    /// `iso_level`. A `cell_width` around the particle radius gives a smooth surface.
+    pub fn extract_surface_mesh(
+        &self,
+        samples: &[ParticleSample],
+        iso_level: f32,
+        cell_width: f32,
+    ) -> Mesh {
+        crate::surface::extract_surface_mesh(samples, self.particle_radius, iso_level, cell_width)
+    }
+
+    /// Reads back the collider poses integrated on the GPU during the latest step, in the order of
+    /// the coupling set (see [`MpmData::coupling`]).
+    ///
+    /// Only the [`BodyCoupling::TwoWays`] bodies are moved by the particles, the other poses are
+    /// the ones uploaded at the start of the step. Compare them with the Rapier poses to debug
+    /// the two-way coupling, or move the Rapier bodies towards them (see the `floating` example).
+    /// The poses are only copied after steps with two-way coupled bodies, so this returns `None`
+    /// without any (see [`Self::has_two_way_coupling`]), or if reading the buffer failed.
+    ///
+    /// The read waits for the GPU to complete the latest step, and must complete before the next
+    /// step is submitted since that step writes to the same buffer, e.g., by blocking on it in a
+    /// system running after [`WgSparklSet::Step`](crate::WgSparklSet::Step).
+    pub fn read_body_poses_async(
+        &self,
+        device: &Device,
+    ) -> impl Future<Output = Option<Vec<GpuSim>>> + '_ {
+        let has_two_way_coupling = self.has_two_way_coupling();
+        async move {
+            if !has_two_way_coupling {
+                return None;
+            }
+            self.data.poses_staging.read(device).await.ok()
+        }
+    }
+
+    /// Reads back the particles inside of `region`, without reading the whole particle buffer.
+    ///
+    /// A compute pass gathers the particles inside of `region` into a compact buffer, then only
+    /// these samples are copied and mapped. The gather pass tests each particle, which costs
+    /// about as much GPU time as preparing the full [`ParticleReadback`](crate::readback::ParticleReadback),
+    /// but the transfer and the mapping, which dominate the full readback at scale, only grow with
+    /// the number of particles inside of `region`. The gather buffer is still allocated for all
+    /// the particles at each call.
+    ///
+    /// This takes two round-trips to the GPU since the number of gathered particles is read
+    /// first. The samples are in an arbitrary order, see [`ParticleSample::index`]. Returns `None`
+    /// if reading the buffers failed, e.g., if the device was lost.
+    pub fn read_particles_in_region_async(
+        &self,
+        device: &RenderDevice,
+        queue: &RenderQueue,
+        region: ParticleRegion,
+    ) -> impl Future<Output = Option<Vec<ParticleSample>>> + 'static {
+        let wgpu_device = device.wgpu_device();
+        let kernel = self
+            .region_readback
+            .get_or_init(|| WgParticleReadback::from_device(wgpu_device).unwrap());
+        let sample_size = size_of::<ParticleSample>() as u64;
+        let samples = wgpu_device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: self.num_particles.max(1) as u64 * sample_size,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let region = GpuScalar::init(wgpu_device, GpuRegion::from(region), BufferUsages::STORAGE);
+        let num_samples = GpuVector::init(
+            wgpu_device,
+            &[0u32],
+            BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+        );
+        let num_samples_staging = GpuVector::init(
+            wgpu_device,
+            &[0u32],
+            BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+        );
+
+        let mut encoder = wgpu_device.create_command_encoder(&Default::default());
+        {
+            let mut kernels = KernelInvocationQueue::new(wgpu_device);
+            kernel.queue_region(
+                &mut kernels,
+                &self.data.particles,
+                &region,
+                &num_samples,
+                &samples,
+            );
+            kernels.encode(&mut encoder, None);
+        }
+        num_samples_staging.copy_from(&mut encoder, &num_samples);
+        queue.submit(Some(encoder.finish()));
+
+        let device = device.clone();
+        let queue = queue.clone();
+        async move {
+            let wgpu_device = device.wgpu_device();
+            let len = num_samples_staging.read(wgpu_device).await.ok()?[0] as usize;
+            if len == 0 {
+                return Some(vec![]);
+            }
+
+            let staging = GpuVector::init(
+                wgpu_device,
+                &vec![ParticleSample::default(); len],
+                BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            );
+            let mut encoder = wgpu_device.create_command_encoder(&Default::default());
+            encoder.copy_buffer_to_buffer(
+                &samples,
+                0,
+                staging.buffer(),
+                0,
+                len as u64 * sample_size,
+            );
+            queue.submit(Some(encoder.finish()));
+            staging.read(wgpu_device).await.ok()
+        }
+    }
+
+    /// Whether any body is coupled with [`BodyCoupling::TwoWays`].
+    ///
+    /// Only these bodies are integrated on the GPU, so their poses are only copied to
+    /// `data.poses_staging` after each step if this is `true`.
+    pub fn has_two_way_coupling(&self) -> bool {
+        self.data
+            .coupling()
+            .iter()
+            .any(|entry| matches!(entry.mode, BodyCoupling::TwoWays))
+    }
+
+    /// Uploads the pose of the collider and the velocity of the body of each coupled entry, in
+    /// the order of the coupling set (see [`MpmData::coupling`]).
+    ///
+    /// This is done from the Rapier context at each step unless
+    /// [`AppState::sync_rapier_bodies`] is disabled, so this is for driving the coupled bodies
+    /// from another source (hand-authored animations, another physics engine, etc.). The data is
+    /// written at the next queue submission, so calling this before
+    /// [`WgSparklSet::Step`](crate::WgSparklSet::Step) applies it to the next step.
+    ///
+    /// Panics if `bodies` doesn’t have exactly one entry per coupled collider.
+    pub fn upload_body_poses(&self, queue: &Queue, bodies: &[(GpuSim, GpuVelocity)]) {
+        assert_eq!(
+            bodies.len(),
+            self.data.coupling().len(),
+            "one pose and velocity must be given per coupled collider"
+        );
+        let poses: Vec<GpuSim> = bodies.iter().map(|(pose, _)| *pose).collect();
+        queue.write_buffer(
+            self.data.bodies.poses().buffer(),
+            0,
+            bytemuck::cast_slice(&poses),
+        );
+
+        let vels: Vec<GpuVelocity> = bodies.iter().map(|(_, vel)| *vel).collect();
+        let mut vels_bytes = vec![];
+        let mut buffer = StorageBuffer::new(&mut vels_bytes);
+        buffer.write(&vels).unwrap();
+        queue.write_buffer(self.data.bodies.vels().buffer(), 0, &vels_bytes);
+    }
+
+    /// Changes how the rigid-body attached to `collider` is coupled with the particles.
+    ///
+    /// This rebuilds the coupling buffers with [`Self::set_coupling`], the particles keep their
+    /// current state. Returns `false` (and does nothing) if `collider` isn’t coupled.
+    ///
+    /// NOTE: there is no per-collider coupling stiffness. The solver enforces the coupling as a
+    /// boundary condition on the grid velocities, without any stiffness or penalty parameter, so
+    /// all the coupled colliders are equally rigid.
+    pub fn set_coupling_mode(
+        &mut self,
+        device: &Device,
+        bodies: &RigidBodySet,
+        colliders: &ColliderSet,
+        collider: ColliderHandle,
+        mode: BodyCoupling,
+    ) -> bool {
+        let mut coupling = self.data.coupling().to_vec();
+        let Some(entry) = coupling.iter_mut().find(|entry| entry.collider == collider) else {
+            return false;
+        };
+        entry.mode = mode;
+
+        self.set_coupling(device, bodies, colliders, coupling);
+        true
+    }
+
+    /// Replaces the coupled colliders with `coupling`, unless they are the same already.
+    ///
+    /// Call this when coupled colliders are added or removed at runtime, e.g., with the entries
+    /// built from the colliders with [`MpmCouplingEnabled`](crate::components::MpmCouplingEnabled).
+    /// Returns `true` if the coupling changed, see [`Self::set_coupling`] for its cost.
+    ///
+    /// Only the list of entries is compared: changing the shape of a coupled collider isn’t
+    /// detected, call [`Self::set_coupling`] directly in that case. Moving a coupled collider
+    /// needs neither since the poses are uploaded at each step.
+    pub fn sync_coupling(
+        &mut self,
+        device: &Device,
+        bodies: &RigidBodySet,
+        colliders: &ColliderSet,
+        coupling: Vec<BodyCouplingEntry>,
+    ) -> bool {
+        // This runs every frame, so each unsupported collider is only reported once.
+        let coupling =
+            skip_unsupported_colliders(colliders, coupling, &mut self.reported_colliders);
+        let current = self.data.coupling();
+        let unchanged = current.len() == coupling.len()
+            && current
+                .iter()
+                .zip(&coupling)
+                .all(|(a, b)| a.body == b.body && a.collider == b.collider && a.mode == b.mode);
+        if unchanged {
+            return false;
+        }
+
+        self.set_coupling(device, bodies, colliders, coupling);
+        true
+    }
+
+    /// The distance the coupled collider shapes are inflated by, see [`Self::set_coupling_margin`].
+    pub fn coupling_margin(&self) -> f32 {
+        self.coupling_margin
+    }
+
+    /// Inflates the shapes of the coupled colliders by `margin` (or deflates them if `margin` is
+    /// negative), so the particles collide with an offset surface.
+    ///
+    /// The particles collide with colliders at their centers, so they visibly sink into colliders
+    /// by up to their radius: a margin equal to [`Self::particle_radius`] makes the particle
+    /// surfaces rest on the collider surfaces. See [`inflate_coupled_colliders`] for the supported
+    /// shapes.
+    ///
+    /// This rebuilds the coupling buffers with [`Self::set_coupling`], and the margin is kept for
+    /// all the later rebuilds.
+    pub fn set_coupling_margin(
+        &mut self,
+        device: &Device,
+        bodies: &RigidBodySet,
+        colliders: &ColliderSet,
+        margin: f32,
+    ) {
+        self.coupling_margin = margin;
+        let coupling = self.data.coupling().to_vec();
+        self.set_coupling(device, bodies, colliders, coupling);
+    }
+
+    /// Replaces the coupled colliders with `coupling`.
+    ///
+    /// The coupling set is baked into the GPU buffers of [`MpmData`], so this rebuilds them,
+    /// along with the grid buffers. The particle buffers are kept as-is, so the particles
+    /// continue from their current state. Changing the cell width or the grid capacity still
+    /// requires a new [`PhysicsContext`]. The shapes are inflated by the
+    /// [`Self::coupling_margin`].
+    pub fn set_coupling(
+        &mut self,
+        device: &Device,
+        bodies: &RigidBodySet,
+        colliders: &ColliderSet,
+        coupling: Vec<BodyCouplingEntry>,
+    ) {
+        let coupling =
+            skip_unsupported_colliders(colliders, coupling, &mut self.reported_colliders);
+        let inflated;
+        let colliders = if self.coupling_margin != 0.0 {
+            inflated = inflate_coupled_colliders(colliders, &coupling, self.coupling_margin);
+            &inflated
+        } else {
+            colliders
+        };
+        // Build the new buffers without any particle, then move the current particles in.
+        let mut data = MpmData::with_select_coupling(
+            device,
+            self.scaled_sim_params(),
+            &[],
+            bodies,
+            colliders,
+            coupling,
+            self.cell_width,
+            self.grid_capacity,
+        );
+        std::mem::swap(&mut data.particles, &mut self.data.particles);
+        std::mem::swap(&mut data.models, &mut self.data.models);
+        self.data = data;
+    }
+
+    /// Resets the particles to `particles`, keeping the grid and coupling buffers of [`MpmData`].
+    ///
+    /// This is much faster than rebuilding the whole [`PhysicsContext`] when iterating on the
+    /// initial state of a scene. Only the particle and material buffers are recreated, so the
+    /// materials and plasticity of `particles` apply too (and the plastic state starts over), but
+    /// `particles` must contain as many particles as the current simulation. Returns `false` (and
+    /// does nothing) otherwise.
+    pub fn reset_particles(&mut self, device: &Device, particles: &[Particle]) -> bool {
+        if particles.len() != self.num_particles {
+            return false;
+        }
+
+        // The materials live in separate buffers: build both without any grid block or coupled
+        // body to spare, then move them in.
+        let mut data = MpmData::with_select_coupling(
+            device,
+            self.scaled_sim_params(),
+            particles,
+            &RigidBodySet::new(),
+            &ColliderSet::new(),
+            vec![],
+            self.cell_width,
+            1024,
+        );
+        std::mem::swap(&mut data.particles, &mut self.data.particles);
+        std::mem::swap(&mut data.models, &mut self.data.models);
+        if self.particles.is_some() {
+            self.particles = Some(particles.to_vec());
+        }
+        true
+    }
+
+    /// Changes the elastic model of the particles in the index range `particles`, and their
+    /// plasticity if `plasticity` is `Some`.
+    ///
+    /// This is uploaded to the GPU immediately, taking effect at the next step, and also applied
+    /// to the CPU-side copy of the particles, if any. Each call uploads the models of the whole
+    /// range, so calling this every frame on large groups (e.g. through [`MaterialTransition`])
+    /// costs GPU bandwidth.
+    ///
+    /// Removing the plasticity of particles isn’t supported: `None` keeps their current one.
+    ///
+    /// [`MaterialTransition`]: crate::groups::MaterialTransition
+    pub fn set_group_model(
+        &mut self,
+        queue: &Queue,
+        particles: Range<usize>,
+        model: ElasticCoefficients,
+        plasticity: Option<DruckerPrager>,
+    ) {
+        let particles =
+            particles.start.min(self.num_particles)..particles.end.min(self.num_particles);
+        if particles.is_empty() {
+            return;
+        }
+
+        let models = &self.data.models;
+        queue.write_buffer(
+            models.linear_elasticity.buffer(),
+            (particles.start * size_of::<ElasticCoefficients>()) as u64,
+            bytemuck::cast_slice(&vec![model; particles.len()]),
+        );
+        if let Some(plasticity) = plasticity {
+            queue.write_buffer(
+                models.drucker_prager_plasticity.buffer(),
+                (particles.start * size_of::<DruckerPrager>()) as u64,
+                bytemuck::cast_slice(&vec![plasticity; particles.len()]),
+            );
+        }
+
+        if let Some(cpu_particles) = &mut self.particles {
+            for particle in &mut cpu_particles[particles] {
+                particle.model = model;
+                if plasticity.is_some() {
+                    particle.plasticity = plasticity;
+                }
+            }
+        }
+    }
+
+    /// The number of particles in contact with each coupled collider, from the latest samples of
+    /// the [`ParticleReadback`](crate::readback::ParticleReadback).
+    ///
+    /// A particle touches a collider if the collider is in its CDF affinity and the particle is
+    /// closer than `contact_distance` to the closest collider (the same test as
+    /// [`RenderConfig::with_contact_highlight`]). Every coupled collider is in the map, with zero
+    /// if nothing touches it.
+    ///
+    /// The readback must be enabled or requested. Since it is asynchronous, the counts are at least one frame
+    /// behind the simulation.
+    pub fn contacts_per_collider(
+        &self,
+        samples: &[ParticleSample],
+        contact_distance: f32,
+    ) -> HashMap<ColliderHandle, u32> {
+        let coupling = self.data.coupling();
+        let mut counts = vec![0; coupling.len()];
+        for sample in samples {
+            if sample.signed_distance >= contact_distance {
+                continue;
+            }
+            // The low bits of the affinity flag the colliders, in the coupling order.
+            for (i, count) in counts.iter_mut().enumerate().take(16) {
+                if sample.affinity & (1 << i) != 0 {
+                    *count += 1;
+                }
+            }
+        }
+
+        coupling
+            .iter()
+            .zip(counts)
+            .map(|(entry, count)| (entry.collider, count))
+            .collect()
+    }
+}
+
+/// The number of cells along each axis of the blocks of the solver’s sparse grid.
+const GRID_BLOCK_WIDTH: f32 = 4.0;
+
+fn warn_undersampled(particles: &[Particle], cell_width: f32) {
+    let recommended = recommended_spacing(cell_width);
+    // The particle radius is half the spacing they were sampled with.
+    let max_spacing = particles
+        .iter()
+        .map(|p| p.volume.init_radius() * 2.0)
+        .fold(0.0, f32::max);
+
+    if max_spacing > recommended * 1.001 {
+        warn!(
+            "Particles are spaced up to {max_spacing} apart, but a spacing of at most {recommended} \
+             (see `recommended_spacing`) is needed to sample cells of width {cell_width} properly. \
+             The simulation may be unstable or inaccurate."
+        );
+    }
 }
 
 // #[derive(Resource, Default)]
@@ -36,6 +843,8 @@ pub struct PhysicsContext {
 #[derive(Resource, Default)]
 pub struct Timestamps {
     pub timestamps: Option<GpuTimestamps>,
+    /// Sorting the particles into the grid cells. This runs at every substep, since the
+    /// following stages rely on the sort being up-to-date.
     pub grid_sort: f64,
     pub grid_update_cdf: f64,
     pub p2g_cdf: f64,
@@ -45,9 +854,47 @@ pub struct Timestamps {
     pub g2p: f64,
     pub particles_update: f64,
     pub integrate_bodies: f64,
+    /// CPU time spent uploading the coupling data and encoding the substeps, in milliseconds.
+    ///
+    /// Unlike the other fields, this is measured on the CPU for the latest step and isn’t
+    /// included in [`Self::total_time`].
+    pub cpu_encode: f64,
+    /// The [`AppState::num_steps`] value of the step the GPU timings were measured for.
+    ///
+    /// Zero until the first GPU timings are received. The GPU timings arrive asynchronously, so
+    /// they may lag a few steps behind (or stop updating if timestamps are disabled).
+    pub last_updated_step: u64,
 }
 
 impl Timestamps {
+    /// The names of the GPU stages, in the order of [`Self::stages`].
+    pub const STAGE_NAMES: [&'static str; 9] = [
+        "grid sort",
+        "grid update cdf",
+        "p2g cdf",
+        "g2p cdf",
+        "p2g",
+        "grid update",
+        "g2p",
+        "particles update",
+        "integrate bodies",
+    ];
+
+    /// The GPU time of each stage, in milliseconds, in the order of [`Self::STAGE_NAMES`].
+    pub fn stages(&self) -> [f64; 9] {
+        [
+            self.grid_sort,
+            self.grid_update_cdf,
+            self.p2g_cdf,
+            self.g2p_cdf,
+            self.p2g,
+            self.grid_update,
+            self.g2p,
+            self.particles_update,
+            self.integrate_bodies,
+        ]
+    }
+
     pub fn total_time(&self) -> f64 {
         self.grid_sort
             + self.grid_update_cdf
@@ -61,9 +908,389 @@ impl Timestamps {
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+/// The GPU timings of a single step, recorded in the [`TimingHistory`].
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct TimingSnapshot {
+    /// The step these timings were measured for (see [`Timestamps::last_updated_step`]).
+    pub step: u64,
+    /// See [`Timestamps::total_time`].
+    pub total_time: f64,
+    /// See [`Timestamps::stages`].
+    pub stages: [f64; 9],
+}
+
+/// A ring buffer of the latest GPU timings, e.g., for drawing profiling graphs.
+///
+/// A snapshot is recorded whenever new GPU timings are received. Once full, the oldest snapshot
+/// is dropped.
+#[derive(Resource, Clone, Debug)]
+pub struct TimingHistory {
+    capacity: usize,
+    snapshots: VecDeque<TimingSnapshot>,
+}
+
+impl Default for TimingHistory {
+    fn default() -> Self {
+        Self::new(120)
+    }
+}
+
+impl TimingHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            snapshots: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Changes the maximum number of snapshots, dropping the oldest ones if needed.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.snapshots.len() > capacity {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// Records the timings of `timestamps`, unless they were already recorded.
+    pub fn record(&mut self, timestamps: &Timestamps) {
+        if timestamps.last_updated_step == 0
+            || self.latest().map(|s| s.step) == Some(timestamps.last_updated_step)
+            || self.capacity == 0
+        {
+            return;
+        }
+
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+
+        self.snapshots.push_back(TimingSnapshot {
+            step: timestamps.last_updated_step,
+            total_time: timestamps.total_time(),
+            stages: timestamps.stages(),
+        });
+    }
+
+    pub fn latest(&self) -> Option<&TimingSnapshot> {
+        self.snapshots.back()
+    }
+
+    /// The recorded snapshots, from the oldest to the latest.
+    pub fn iter(&self) -> impl Iterator<Item = &TimingSnapshot> {
+        self.snapshots.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+}
+
+/// Statistics about the simulation state.
+///
+/// The penetration statistics are computed from the latest particle readback, so they are only
+/// updated while the [`ParticleReadback`](crate::readback::ParticleReadback) is enabled or
+/// requested.
+#[derive(Resource, Default, Clone, Debug)]
+pub struct SimulationDiagnostics {
+    /// Deepest penetration of a particle inside a coupled collider.
+    pub max_penetration: f32,
+    /// Average penetration depth of the particles penetrating a coupled collider.
+    pub mean_penetration: f32,
+    /// Number of particles penetrating a coupled collider.
+    pub num_penetrating: usize,
+    /// Number of steps whose GPU timestamps are still being processed.
+    ///
+    /// No new timestamps are recorded while this is non-zero, so this never exceeds one.
+    pub pending_timestamp_tasks: usize,
+    /// Number of particles with a non-finite position or velocity, as found by the latest
+    /// [`NanCheck`](crate::nan_check::NanCheck) scan.
+    pub num_non_finite: usize,
+    /// The value of [`AppState::num_steps`] at the latest [`NanCheck`](crate::nan_check::NanCheck)
+    /// scan that found the simulation stable.
+    pub last_stable_step: u64,
+}
+
+impl SimulationDiagnostics {
+    pub fn update_penetration(&mut self, samples: &[ParticleSample]) {
+        let mut max_penetration = 0.0f32;
+        let mut total_penetration = 0.0;
+        let mut num_penetrating = 0;
+
+        for penetration in samples.iter().map(|s| s.penetration()) {
+            if penetration > 0.0 {
+                max_penetration = max_penetration.max(penetration);
+                total_penetration += penetration;
+                num_penetrating += 1;
+            }
+        }
+
+        self.max_penetration = max_penetration;
+        self.mean_penetration = if num_penetrating > 0 {
+            total_penetration / num_penetrating as f32
+        } else {
+            0.0
+        };
+        self.num_penetrating = num_penetrating;
+    }
+}
+
+/// Whether [`step_simulation`](crate::step::step_simulation) stepped the simulation during the
+/// latest frame, or why it didn’t.
+#[derive(Resource, Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum LastStepOutcome {
+    /// The simulation stepped (possibly a single substep, see [`RunState::StepSubstep`]).
+    Stepped,
+    /// The simulation is paused.
+    SkippedPaused,
+    /// The particles are asleep (see [`ParticleSleep`](crate::sleep::ParticleSleep)).
+    SkippedAsleep,
+    /// The simulation has coupled bodies, but the Rapier context isn’t initialized yet.
+    SkippedNotReady,
+    /// There is no [`PhysicsContext`] yet.
+    #[default]
+    SkippedNoPhysics,
+    /// The GPU device was lost (see [`DeviceLost`](crate::events::DeviceLost)).
+    SkippedDeviceLost,
+}
+
+/// Pauses the simulation once this many steps ran (see [`AppState::num_steps`]), and sends a
+/// [`SimulationFinished`](crate::events::SimulationFinished) event.
+///
+/// Useful for bounded headless runs, e.g., benchmarks or CI. Resuming the simulation afterwards
+/// runs past the limit.
+#[derive(Resource, Copy, Clone, PartialEq, Eq, Debug)]
+pub struct StepLimit(pub u64);
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
 pub enum RunState {
+    #[default]
     Running,
     Paused,
     Step,
+    /// Runs a single substep, then pauses.
+    ///
+    /// The substeps of a step are then spread over several frames, so the position within the
+    /// step is kept in [`AppState::next_substep`]. The rigid-body poses are uploaded at the first
+    /// substep of each step only, and the GPU timestamps are only recorded for steps run at once.
+    StepSubstep,
+}
+
+/// How the GPU timestamps recorded during a step are turned into [`Timestamps`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum TimestampProcessing {
+    /// Wait for the results on the [`ComputeTaskPool`](bevy::tasks::ComputeTaskPool).
+    #[default]
+    Async,
+    /// Wait for the results at the end of the step, stalling until the GPU is done.
+    ///
+    /// Falls back to [`TimestampProcessing::Async`] on `wasm32`, where blocking would deadlock.
+    Blocking,
+    /// Don’t record timestamps at all.
+    Disabled,
+}
+
+/// How the length of the simulation steps is chosen.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum TimeStepMode {
+    /// Use the `dt` of the [`SimulationParams`] as-is, so each frame simulates a fixed duration
+    /// regardless of the frame rate.
+    #[default]
+    Fixed,
+    /// Simulate the duration of the last frame (from Bevy’s [`Time`](bevy::time::Time)),
+    /// divided across the substeps, so the simulation runs at the correct speed at any frame
+    /// rate.
+    ///
+    /// The frame duration is clamped to [`MAX_REALTIME_FRAME_DT`](crate::step::MAX_REALTIME_FRAME_DT)
+    /// so frame hitches don’t make the simulation unstable. Note that longer frames mean longer
+    /// substeps: increase [`AppState::num_substeps`] if the simulation is unstable at low frame
+    /// rates.
+    Realtime,
+}
+
+/// `params` with its gravity multiplied by `scale`.
+fn scale_gravity(params: SimulationParams, scale: f32) -> SimulationParams {
+    SimulationParams {
+        gravity: params.gravity * scale,
+        ..params
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generation::ParticleMaterial;
+    use crate::test_utils::{gpu_device, read_samples, step};
+    use nalgebra::vector;
+
+    /// One particle at the center of each grid block of a `counts[0] x counts[1] x counts[2]`
+    /// box of blocks.
+    fn particles_in_blocks(counts: [usize; 3], cell_width: f32) -> Vec<Particle> {
+        let material = ParticleMaterial::new(
+            1000.0,
+            ElasticCoefficients::from_young_modulus(1_000_000.0, 0.3),
+        );
+        let block_width = cell_width * GRID_BLOCK_WIDTH;
+        let mut particles = vec![];
+        for i in 0..counts[0] {
+            for j in 0..counts[1] {
+                for k in 0..counts[2] {
+                    let block = Vector3::new(i as f32, j as f32, k as f32);
+                    particles.push(material.particle(
+                        (block + Vector3::repeat(0.5)) * block_width,
+                        recommended_spacing(cell_width),
+                    ));
+                }
+            }
+        }
+        particles
+    }
+
+    #[test]
+    fn grid_capacity_exact_fit() {
+        // 6x6x6 blocks and their neighbors touch 8x8x8 = 512 blocks, doubled to exactly 1024.
+        let particles = particles_in_blocks([6, 6, 6], 1.0);
+        assert_eq!(
+            PhysicsContext::recommended_grid_capacity(&particles, 1.0),
+            1024
+        );
+    }
+
+    #[test]
+    fn grid_capacity_over_capacity() {
+        // 7x6x6 blocks touch 9x8x8 = 576 blocks, doubled to 1152, past the 1024 of the exact fit.
+        let particles = particles_in_blocks([7, 6, 6], 1.0);
+        assert_eq!(
+            PhysicsContext::recommended_grid_capacity(&particles, 1.0),
+            2048
+        );
+
+        // The capacity doesn’t depend on the number of particles within each block.
+        let mut denser = particles.clone();
+        denser.extend(particles.iter().map(|p| Particle {
+            position: p.position + Vector3::repeat(0.25),
+            ..p.clone()
+        }));
+        assert_eq!(
+            PhysicsContext::recommended_grid_capacity(&denser, 1.0),
+            2048
+        );
+    }
+
+    #[test]
+    fn grid_capacity_without_particles() {
+        assert_eq!(PhysicsContext::recommended_grid_capacity(&[], 1.0), 1024);
+    }
+
+    #[test]
+    fn gravity_scale_only_scales_gravity() {
+        let params = SimulationParams {
+            gravity: vector![1.0, -10.0, 2.0],
+            dt: 1.0e-3,
+        };
+        let scaled = scale_gravity(params, 0.5);
+        assert_eq!(scaled.gravity, vector![0.5, -5.0, 1.0]);
+        assert_eq!(scaled.dt, params.dt);
+        assert_eq!(scale_gravity(params, 0.0).gravity, Vector3::zeros());
+    }
+
+    #[test]
+    #[ignore = "requires a GPU"]
+    fn reset_particles_uploads_materials() {
+        let (device, queue) = gpu_device();
+        let soft = ParticleMaterial::new(
+            1000.0,
+            ElasticCoefficients::from_young_modulus(1_000_000.0, 0.3),
+        );
+        let stiff = ParticleMaterial::new(
+            1000.0,
+            ElasticCoefficients::from_young_modulus(50_000_000.0, 0.2),
+        );
+        let positions = [Vector3::new(0.0, 10.0, 0.0), Vector3::new(1.0, 10.0, 0.0)];
+        let particles = |material: &ParticleMaterial| -> Vec<Particle> {
+            positions
+                .iter()
+                .map(|p| material.particle(*p, recommended_spacing(1.0)))
+                .collect()
+        };
+        let mut physics = PhysicsContext::from_particles(
+            &device,
+            SimulationParams {
+                gravity: Vector3::zeros(),
+                dt: 1.0e-3,
+            },
+            particles(&soft),
+            &RigidBodySet::new(),
+            &ColliderSet::new(),
+            vec![],
+            1.0,
+        );
+
+        assert!(physics.reset_particles(&device, &particles(&stiff)));
+
+        let models = &physics.data.models.linear_elasticity;
+        let staging = GpuVector::init(
+            &device,
+            &vec![soft.model; positions.len()],
+            BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+        );
+        let mut encoder = device.create_command_encoder(&Default::default());
+        staging.copy_from(&mut encoder, models);
+        queue.submit(Some(encoder.finish()));
+        let uploaded: Vec<ElasticCoefficients> =
+            futures::executor::block_on(staging.read(&device)).unwrap();
+        for model in uploaded {
+            assert_eq!(bytemuck::bytes_of(&model), bytemuck::bytes_of(&stiff.model));
+        }
+    }
+
+    #[test]
+    #[ignore = "requires a GPU"]
+    fn set_sim_params_updates_gpu_params() {
+        let (device, queue) = gpu_device();
+        let pipeline = MpmPipeline::new(&device).unwrap();
+        let material = ParticleMaterial::new(
+            1000.0,
+            ElasticCoefficients::from_young_modulus(1_000_000.0, 0.3),
+        );
+        let particles =
+            vec![material.particle(Vector3::new(0.0, 10.0, 0.0), recommended_spacing(1.0))];
+        let params = SimulationParams {
+            gravity: Vector3::zeros(),
+            dt: 1.0e-3,
+        };
+        let mut physics = PhysicsContext::from_particles(
+            &device,
+            params,
+            particles,
+            &RigidBodySet::new(),
+            &ColliderSet::new(),
+            vec![],
+            1.0,
+        );
+
+        step(&device, &queue, &pipeline, &mut physics, 1);
+        let velocity = read_samples(&device, &queue, &physics)[0].velocity;
+        assert!(velocity.iter().all(|v| v.abs() < 1.0e-6), "{velocity:?}");
+
+        // A single free particle gains exactly the gravity over one substep.
+        let new_params = SimulationParams {
+            gravity: vector![0.0, -10.0, 0.0],
+            dt: 2.0e-3,
+        };
+        physics.set_sim_params(&queue, new_params);
+        assert_eq!(physics.sim_params().gravity, new_params.gravity);
+        assert_eq!(physics.sim_params().dt, new_params.dt);
+
+        step(&device, &queue, &pipeline, &mut physics, 1);
+        let velocity = read_samples(&device, &queue, &physics)[0].velocity;
+        let expected = new_params.gravity.y * new_params.dt;
+        assert!(
+            (velocity[1] - expected).abs() < expected.abs() * 0.05,
+            "{velocity:?}"
+        );
+    }
 }
@@ -0,0 +1,120 @@
+//! Reproducible random numbers for the randomized particle generation.
+
+use bevy::prelude::Resource;
+use nalgebra::Vector3;
+use std::ops::Range;
+
+/// The random number generator read by the randomized helpers of this crate, e.g.,
+/// [`jitter_particles`](crate::generation::jitter_particles).
+///
+/// Scenes generated from the same seed are identical, which makes them reproducible for
+/// recordings and tests. Insert it with the seed of choice (it defaults to zero); thread-local
+/// randomness is never used.
+///
+/// This is a SplitMix64 generator: fast and statistically good enough for sampling particles,
+/// but not cryptographically secure.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct MpmRng {
+    seed: u64,
+    state: u64,
+}
+
+impl MpmRng {
+    pub fn new(seed: u64) -> Self {
+        Self { seed, state: seed }
+    }
+
+    /// The seed this generator was created or last reseeded with.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Restarts the random sequence from `seed`.
+    pub fn reseed(&mut self, seed: u64) {
+        *self = Self::new(seed);
+    }
+
+    /// An independent generator for the `index`-th item, derived from the current state.
+    ///
+    /// Use this to draw random numbers for each particle of [`generate_particles_parallel`]: the
+    /// results don’t depend on the order the particles are generated in.
+    ///
+    /// [`generate_particles_parallel`]: crate::generation::generate_particles_parallel
+    pub fn fork(&self, index: u64) -> Self {
+        Self::new(mix(self.state ^ mix(index)))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        mix(self.state)
+    }
+
+    /// A number uniformly distributed in `[0, 1)`.
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// A number uniformly distributed in `range`.
+    pub fn range(&mut self, range: Range<f32>) -> f32 {
+        range.start + self.next_f32() * (range.end - range.start)
+    }
+
+    /// A vector uniformly distributed in the cube `[-half_extent, half_extent]³`.
+    pub fn vector_in_cube(&mut self, half_extent: f32) -> Vector3<f32> {
+        Vector3::from_fn(|_, _| self.range(-half_extent..half_extent))
+    }
+}
+
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_sequence() {
+        let mut a = MpmRng::new(42);
+        let mut b = MpmRng::new(42);
+        let sequence: Vec<u64> = (0..16).map(|_| a.next_u64()).collect();
+        assert_eq!(sequence, (0..16).map(|_| b.next_u64()).collect::<Vec<_>>());
+
+        let mut c = MpmRng::new(43);
+        assert_ne!(sequence, (0..16).map(|_| c.next_u64()).collect::<Vec<_>>());
+
+        // Reseeding restarts the sequence.
+        a.reseed(42);
+        assert_eq!(a.seed(), 42);
+        assert_eq!(sequence, (0..16).map(|_| a.next_u64()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn splitmix64_reference_value() {
+        assert_eq!(MpmRng::default().next_u64(), 0xe220_a839_7b1d_cdaf);
+    }
+
+    #[test]
+    fn forks_dont_depend_on_order() {
+        let rng = MpmRng::new(7);
+        let forward: Vec<u64> = (0..8).map(|i| rng.fork(i).next_u64()).collect();
+        let backward: Vec<u64> = (0..8).rev().map(|i| rng.fork(i).next_u64()).collect();
+        assert_eq!(forward, backward.into_iter().rev().collect::<Vec<_>>());
+        assert_ne!(forward[0], forward[1]);
+    }
+
+    #[test]
+    fn ranges() {
+        let mut rng = MpmRng::new(3);
+        for _ in 0..1000 {
+            let x = rng.next_f32();
+            assert!((0.0..1.0).contains(&x));
+            let y = rng.range(-2.0..5.0);
+            assert!((-2.0..5.0).contains(&y));
+            let v = rng.vector_in_cube(0.5);
+            assert!(v.iter().all(|c| (-0.5..0.5).contains(c)));
+        }
+    }
+}
@@ -0,0 +1,59 @@
+//! Detection of particles overlapping sensor colliders.
+
+use crate::components::{MpmParticleSensor, MpmRapierContext};
+use crate::events::ParticleSensorEvent;
+use crate::readback::ParticleReadback;
+use bevy::prelude::*;
+use bevy_rapier3d::geometry::RapierColliderHandle;
+use bevy_rapier3d::plugin::ReadRapierContext;
+use wgsparkl3d::rapier::math::Point;
+
+/// Updates the [`MpmParticleSensor`]s from the latest particle readback, and sends the
+/// corresponding [`ParticleSensorEvent`]s.
+///
+/// The particle readback is requested as long as any sensor exists. Each particle is tested against
+/// each sensor on the CPU, so this is meant for a handful of sensors.
+pub fn detect_particle_sensor_overlaps(
+    rapier: ReadRapierContext<With<MpmRapierContext>>,
+    mut readback: ResMut<ParticleReadback>,
+    mut sensors: Query<(Entity, &RapierColliderHandle, &mut MpmParticleSensor)>,
+    mut events: EventWriter<ParticleSensorEvent>,
+    mut last_readback: Local<u64>,
+) {
+    if sensors.is_empty() || rapier.rapier_context.is_empty() {
+        return;
+    }
+
+    readback.request();
+
+    if readback.num_readbacks() == *last_readback {
+        return;
+    }
+    *last_readback = readback.num_readbacks();
+
+    let rapier = rapier.single();
+
+    for (entity, handle, mut sensor) in &mut sensors {
+        let Some(collider) = rapier.colliders.colliders.get(handle.0) else {
+            continue;
+        };
+
+        let aabb = collider.compute_aabb();
+        let num_particles = readback
+            .samples
+            .iter()
+            .map(|sample| Point::from(sample.position))
+            .filter(|pt| {
+                aabb.contains_local_point(pt)
+                    && collider.shape().contains_point(collider.position(), pt)
+            })
+            .count();
+
+        if sensor.num_particles == 0 && num_particles > 0 {
+            events.send(ParticleSensorEvent::Started { sensor: entity });
+        } else if sensor.num_particles > 0 && num_particles == 0 {
+            events.send(ParticleSensorEvent::Stopped { sensor: entity });
+        }
+        sensor.num_particles = num_particles;
+    }
+}
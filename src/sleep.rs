@@ -0,0 +1,129 @@
+//! Skipping the simulation steps once all the particles settled.
+
+use crate::components::MpmRapierContext;
+use crate::readback::ParticleReadback;
+use crate::resources::{AppState, PhysicsContext, RunState};
+use bevy::prelude::*;
+use bevy_rapier3d::plugin::ReadRapierContext;
+
+/// Opt-in sleeping of the simulation once all the particles are at rest.
+///
+/// While enabled, the [`ParticleReadback`] is requested every frame. When the speed of every particle stays
+/// below `velocity_threshold` for `num_readbacks` consecutive readbacks, the simulation falls
+/// asleep: the steps are skipped (but the particles are still rendered) until it wakes up.
+///
+/// The solver has no per-particle active flag, so sleeping applies to the whole simulation:
+/// a single moving particle keeps everything awake. It wakes up when a coupled rigid-body
+/// moves, when the simulation is stepped manually, when a new [`PhysicsContext`] is inserted, or
+/// through [`Self::wake`] (e.g. after spawning particles or changing the gravity).
+///
+/// A threshold too high puts piles to sleep while they are still slowly creeping, which shows
+/// as a visual pop when they wake up and resume moving. A threshold too low never lets piles
+/// sleep, as settled granular materials keep jittering slightly. Start with a threshold of about
+/// 1% of the typical particle speeds of the scene.
+#[derive(Resource, Clone, Debug)]
+pub struct ParticleSleep {
+    pub enabled: bool,
+    /// The speed below which a particle is considered at rest.
+    pub velocity_threshold: f32,
+    /// The number of consecutive readbacks with all the particles at rest before falling asleep.
+    pub num_readbacks: u32,
+    asleep: bool,
+    num_still_readbacks: u32,
+}
+
+impl Default for ParticleSleep {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            velocity_threshold: 0.01,
+            num_readbacks: 60,
+            asleep: false,
+            num_still_readbacks: 0,
+        }
+    }
+}
+
+impl ParticleSleep {
+    /// Whether the simulation steps are currently skipped.
+    pub fn is_asleep(&self) -> bool {
+        self.asleep
+    }
+
+    /// Wakes the simulation up, and restarts the rest detection.
+    pub fn wake(&mut self) {
+        self.asleep = false;
+        self.num_still_readbacks = 0;
+    }
+}
+
+pub fn update_particle_sleep(
+    app_state: Res<AppState>,
+    physics: Option<Res<PhysicsContext>>,
+    rapier: ReadRapierContext<With<MpmRapierContext>>,
+    mut sleep: ResMut<ParticleSleep>,
+    mut readback: ResMut<ParticleReadback>,
+    mut last_readback: Local<u64>,
+) {
+    if !sleep.enabled {
+        if sleep.asleep {
+            sleep.wake();
+        }
+        return;
+    }
+
+    readback.request();
+
+    let Some(physics) = physics else {
+        return;
+    };
+
+    let stepped_manually = matches!(app_state.run_state, RunState::Step | RunState::StepSubstep);
+    if physics.is_added() || stepped_manually {
+        sleep.wake();
+        return;
+    }
+
+    if sleep.asleep {
+        if rapier.rapier_context.is_empty() {
+            return;
+        }
+
+        let rapier = rapier.single();
+        let body_moved = physics.data.coupling().iter().any(|coupling| {
+            rapier
+                .rigidbody_set
+                .bodies
+                .get(coupling.body)
+                .is_some_and(|rb| !rb.is_sleeping() && rb.is_moving())
+        });
+
+        if body_moved {
+            debug!("A coupled body moved, waking the particles up.");
+            sleep.wake();
+        }
+        return;
+    }
+
+    if readback.num_readbacks() == *last_readback {
+        return;
+    }
+    *last_readback = readback.num_readbacks();
+
+    let threshold_sq = sleep.velocity_threshold * sleep.velocity_threshold;
+    let at_rest = readback
+        .samples
+        .iter()
+        .all(|s| Vec3::from(s.velocity).length_squared() <= threshold_sq);
+
+    if !at_rest {
+        sleep.num_still_readbacks = 0;
+        return;
+    }
+
+    sleep.num_still_readbacks += 1;
+    if sleep.num_still_readbacks >= sleep.num_readbacks {
+        debug!("All the particles are at rest, the simulation falls asleep.");
+        sleep.asleep = true;
+    }
+}
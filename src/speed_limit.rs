@@ -0,0 +1,60 @@
+use wgcore::Shader;
+use wgcore::kernel::{KernelInvocationBuilder, KernelInvocationQueue};
+use wgcore::tensor::GpuScalar;
+use wgebra::WgSvd2;
+use wgebra::WgSvd3;
+use wgpu::{BufferUsages, ComputePipeline, Device, Queue};
+use wgsparkl3d::grid::grid::WgGrid;
+use wgsparkl3d::solver::{GpuParticles, WgParticle};
+
+#[derive(Shader)]
+#[shader(
+    src = "speed_limit3d.wgsl",
+    derive(WgParticle, WgGrid, WgSvd2, WgSvd3),
+    composable = false
+)]
+pub struct WgSpeedLimit {
+    main: ComputePipeline,
+}
+
+impl WgSpeedLimit {
+    pub fn queue<'a>(
+        &'a self,
+        queue: &mut KernelInvocationQueue<'a>,
+        particles: &GpuParticles,
+        max_speed: &GpuScalar<f32>,
+    ) {
+        KernelInvocationBuilder::new(queue, &self.main)
+            .bind0([particles.velocities.buffer(), max_speed.buffer()])
+            .queue(particles.velocities.len().div_ceil(64) as u32);
+    }
+}
+
+/// Clamps the speed of each particle after each substep, see
+/// [`AppState::max_particle_speed`](crate::resources::AppState::max_particle_speed).
+pub struct SpeedLimitPass {
+    pub kernel: WgSpeedLimit,
+    max_speed: GpuScalar<f32>,
+}
+
+impl SpeedLimitPass {
+    pub fn from_device(device: &Device) -> Self {
+        Self {
+            kernel: WgSpeedLimit::from_device(device).unwrap(),
+            max_speed: GpuScalar::init(
+                device,
+                f32::MAX,
+                BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            ),
+        }
+    }
+
+    /// Writes the maximum speed used by the next passes.
+    pub fn set_max_speed(&self, compute_queue: &Queue, max_speed: f32) {
+        compute_queue.write_buffer(self.max_speed.buffer(), 0, bytemuck::bytes_of(&max_speed));
+    }
+
+    pub fn queue<'a>(&'a self, queue: &mut KernelInvocationQueue<'a>, particles: &GpuParticles) {
+        self.kernel.queue(queue, particles, &self.max_speed);
+    }
+}
@@ -0,0 +1,25 @@
+#define_import_path wgsparkl::examples::speed_limit
+
+#import wgsparkl::solver::particle as Particle;
+
+@group(0) @binding(0)
+var<storage, read_write> particles_vel: array<Particle::Velocity>;
+@group(0) @binding(1)
+var<storage, read> max_speed: f32;
+
+@compute @workgroup_size(64, 1, 1)
+fn main(
+    @builtin(global_invocation_id) tid: vec3<u32>,
+) {
+    let particle_id = tid.x;
+
+    if particle_id < arrayLength(&particles_vel) {
+        let vel = particles_vel[particle_id].v;
+        let speed = length(vel);
+
+        // Scale the velocity down, keeping its direction.
+        if speed > max_speed {
+            particles_vel[particle_id].v = vel * (max_speed / speed);
+        }
+    }
+}
@@ -1,7 +1,20 @@
+use crate::coloring::ParticleView;
+use crate::events::ResetSimulation;
+use crate::groups::{GroupGravity, MpmParticleBlock};
 use crate::instancing3d::{InstanceBuffer, InstanceData, InstanceMaterialData};
-use crate::prep_vertex_buffer::{GpuRenderConfig, RenderConfig, RenderMode, WgPrepVertexBuffer};
-use crate::resources::{AppState, PhysicsContext, RunState, Timestamps};
-use crate::step::TimestampChannel;
+use crate::packing::PackingPass;
+use crate::particle_age::ParticleAgePass;
+use crate::prep_vertex_buffer::{
+    ColorSource, GpuRenderConfig, RenderConfig, RenderMode, WgPrepVertexBuffer,
+};
+use crate::readback::ParticleReadback;
+use crate::resources::{
+    AppState, PhysicsContext, RunState, SimulationDiagnostics, TimeStepMode, TimestampProcessing,
+    Timestamps, TimingHistory, UnsupportedDevice,
+};
+use crate::sleep::ParticleSleep;
+use crate::speed_limit::SpeedLimitPass;
+use crate::step::{DeviceLostChannel, TimestampChannel};
 use bevy::asset::Assets;
 use bevy::color::Color;
 use bevy::math::{Vec3, Vec4};
@@ -9,16 +22,37 @@ use bevy::prelude::*;
 use bevy::render::render_resource::BufferUsages;
 use bevy::render::renderer::RenderDevice;
 use bevy::render::view::NoFrustumCulling;
+use nalgebra::Vector3;
+use std::ops::Range;
 use std::sync::Arc;
+use std::time::Duration;
 use wgcore::Shader;
 use wgcore::hot_reloading::HotReloadState;
 use wgcore::tensor::GpuVector;
 use wgcore::timestamps::GpuTimestamps;
-use wgpu::Features;
+use wgpu::{Features, Limits};
 use wgsparkl3d::pipeline::MpmPipeline;
+use wgsparkl3d::solver::Particle;
+
+/// The run state of the simulation when the app starts, set through [`WgSparklPlugin`](crate::WgSparklPlugin).
+#[derive(Resource, Copy, Clone, Default, Debug)]
+pub struct InitialRunState {
+    pub run_state: RunState,
+    pub auto_start_after: Option<Duration>,
+}
 
 /// set up a simple 3D scene
-pub fn setup_app(mut commands: Commands, device: Res<RenderDevice>) {
+pub fn setup_app(
+    mut commands: Commands,
+    device: Res<RenderDevice>,
+    initial_run_state: Res<InitialRunState>,
+) {
+    if let Err(unsupported) = validate_device(device.features(), &device.limits()) {
+        // Compiling the kernels would panic, so nothing else is set up.
+        commands.insert_resource(unsupported);
+        return;
+    }
+
     // app state
     let render_config = RenderConfig::new(RenderMode::Default);
     let gpu_render_config = GpuRenderConfig::new(device.wgpu_device(), render_config);
@@ -33,34 +67,181 @@ pub fn setup_app(mut commands: Commands, device: Res<RenderDevice>) {
         gpu_render_config,
         prep_vertex_buffer,
         pipeline,
-        run_state: RunState::Running,
+        run_state: if initial_run_state.auto_start_after.is_some() {
+            RunState::Paused
+        } else {
+            initial_run_state.run_state
+        },
         num_substeps: 1,
         gravity_factor: 1.0,
         restarting: false,
         selected_scene: 0,
         hot_reload,
         particles_initialized: false,
+        render_while_paused: false,
+        timestamp_processing: TimestampProcessing::Async,
+        track_particle_age: false,
+        particle_age: ParticleAgePass::from_device(device.wgpu_device()),
+        packing: PackingPass::from_device(device.wgpu_device()),
+        device_lost: false,
+        custom_coloring: None,
+        auto_start_after: initial_run_state.auto_start_after,
+        debug_coupling_velocity: false,
+        render_coupled_colliders: false,
+        num_steps: 0,
+        palette: None,
+        double_buffer_instances: false,
+        next_substep: 0,
+        sync_rapier_bodies: true,
+        time_step_mode: TimeStepMode::Fixed,
+        gravity_ramp: None,
+        deterministic: false,
+        max_particle_speed: None,
+        speed_limit: SpeedLimitPass::from_device(device.wgpu_device()),
     });
 
     let (snd, rcv) = async_channel::unbounded();
     commands.insert_resource(TimestampChannel { snd, rcv });
 
+    let (snd, rcv) = async_channel::unbounded();
+    device
+        .wgpu_device()
+        .set_device_lost_callback(move |reason, message| {
+            let _ = snd.try_send(format!("{message} ({reason:?})"));
+        });
+    commands.insert_resource(DeviceLostChannel { rcv });
+
     let features = device.features();
     let timestamps = features
         .contains(Features::TIMESTAMP_QUERY)
-        .then(|| GpuTimestamps::new(device.wgpu_device(), 1024));
+        .then(|| GpuTimestamps::new(device.wgpu_device(), MAX_TIMESTAMP_QUERIES));
     commands.insert_resource(Timestamps {
         timestamps,
         ..Default::default()
     });
+
+    commands.insert_resource(ParticleReadback::new(device.wgpu_device()));
+    commands.insert_resource(SimulationDiagnostics::default());
+    commands.insert_resource(TimingHistory::default());
+    commands.insert_resource(ParticleSleep::default());
+    commands.insert_resource(GroupGravity::new(device.wgpu_device()));
+}
+
+/// The number of timestamp queries allocated for profiling the simulation.
+///
+/// This stays well below the WebGPU limit of 4096 queries per query set.
+pub const MAX_TIMESTAMP_QUERIES: u32 = 1024;
+
+/// The workgroup size used by the compute kernels of this crate and of the solver.
+const WORKGROUP_SIZE: u32 = 64;
+
+/// The maximum number of particles that can be rendered given the device’s limits.
+///
+/// The render instance buffer is the largest per-particle buffer, so this is also a good upper
+/// bound for the number of simulated particles on constrained targets (e.g. WebGPU, where storage
+/// buffer bindings are limited to 128MiB by default).
+pub fn max_rendered_particles(limits: &Limits) -> usize {
+    let max_binding = (limits.max_storage_buffer_binding_size as u64).min(limits.max_buffer_size);
+    max_binding as usize / size_of::<InstanceData>()
+}
+
+/// The device features required by the simulation kernels.
+pub const REQUIRED_FEATURES: Features = Features::empty();
+
+/// The number of storage buffers bound by the largest kernel of this crate.
+const MIN_STORAGE_BUFFERS_PER_STAGE: u32 = 8;
+
+/// Lists the requirements of the simulation that a device with `features` and `limits` doesn’t
+/// meet, e.g., to skip the simulation on unsupported adapters or in CI.
+///
+/// The list is empty if the device is supported. The optional `TIMESTAMP_QUERY` feature isn’t
+/// checked: without it, the simulation runs without GPU timings.
+pub fn missing_device_requirements(features: Features, limits: &Limits) -> Vec<String> {
+    let mut missing = vec![];
+
+    let missing_features = REQUIRED_FEATURES - features;
+    if !missing_features.is_empty() {
+        missing.push(format!("the features {missing_features:?}"));
+    }
+
+    if limits.max_compute_workgroup_size_x < WORKGROUP_SIZE {
+        missing.push(format!(
+            "compute workgroups of size {WORKGROUP_SIZE} (max workgroup size: {})",
+            limits.max_compute_workgroup_size_x
+        ));
+    }
+
+    if limits.max_compute_invocations_per_workgroup < WORKGROUP_SIZE {
+        missing.push(format!(
+            "{WORKGROUP_SIZE} invocations per workgroup (max invocations: {})",
+            limits.max_compute_invocations_per_workgroup
+        ));
+    }
+
+    if limits.max_storage_buffers_per_shader_stage < MIN_STORAGE_BUFFERS_PER_STAGE {
+        missing.push(format!(
+            "{MIN_STORAGE_BUFFERS_PER_STAGE} storage buffers per shader stage (max: {})",
+            limits.max_storage_buffers_per_shader_stage
+        ));
+    }
+
+    missing
+}
+
+/// Logs a single error listing the unmet requirements, before the kernels get compiled.
+fn validate_device(features: Features, limits: &Limits) -> Result<(), UnsupportedDevice> {
+    let missing = missing_device_requirements(features, limits);
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    error!(
+        "The GPU device doesn’t support the particle simulation, which will not run. It lacks \
+         support for:\n- {}",
+        missing.join("\n- ")
+    );
+    Err(UnsupportedDevice { missing })
+}
+
+/// The palette the particle base colors are picked from, unless [`AppState::palette`] is set.
+pub const DEFAULT_PALETTE: [Color; 6] = [
+    Color::srgb_u8(234, 208, 168),
+    Color::srgb_u8(182, 159, 102),
+    Color::srgb_u8(107, 84, 40),
+    Color::srgb_u8(118, 85, 43),
+    Color::srgb_u8(64, 41, 5),
+    Color::srgb_u8(89, 58, 14),
+];
+
+/// Handles the [`ResetSimulation`] events.
+pub fn reset_simulation(
+    mut commands: Commands,
+    mut events: EventReader<ResetSimulation>,
+    mut app_state: ResMut<AppState>,
+    particles: Query<Entity, With<InstanceMaterialData>>,
+) {
+    if events.read().count() == 0 {
+        return;
+    }
+
+    app_state.restarting = true;
+    app_state.particles_initialized = false;
+    app_state.num_steps = 0;
+    app_state.next_substep = 0;
+    commands.remove_resource::<PhysicsContext>();
+    for entity in &particles {
+        commands.entity(entity).despawn();
+    }
 }
 
 pub fn setup_graphics(
     mut commands: Commands,
     device: Res<RenderDevice>,
+    app_state: Res<AppState>,
     physics: Option<Res<PhysicsContext>>,
     mut meshes: ResMut<Assets<Mesh>>,
     inited_particles: Query<Entity, With<InstanceMaterialData>>,
+    blocks: Query<&MpmParticleBlock>,
 ) {
     let Some(physics) = physics else {
         return;
@@ -70,50 +251,134 @@ pub fn setup_graphics(
         return; // The render particles are already initialized.
     }
 
-    setup_particles_graphics(&mut commands, &device, &physics, &mut meshes);
+    let block_colors: Vec<_> = blocks
+        .iter()
+        .filter_map(|block| Some((block.particles.clone(), block.color?)))
+        .collect();
+    setup_particles_graphics(
+        &mut commands,
+        &device,
+        &app_state,
+        &physics,
+        &mut meshes,
+        &block_colors,
+    );
 }
 
 fn setup_particles_graphics(
     commands: &mut Commands,
     device: &RenderDevice,
+    app_state: &AppState,
     physics: &PhysicsContext,
     meshes: &mut Assets<Mesh>,
+    block_colors: &[(Range<usize>, Color)],
 ) {
     let device = device.wgpu_device();
-    let colors = [
-        Color::srgb_u8(234, 208, 168),
-        Color::srgb_u8(182, 159, 102),
-        Color::srgb_u8(107, 84, 40),
-        Color::srgb_u8(118, 85, 43),
-        Color::srgb_u8(64, 41, 5),
-        Color::srgb_u8(89, 58, 14),
-    ];
-    let radius = physics.particles[0].volume.init_radius();
+    let render_config = &app_state.render_config;
+    let colors = match &app_state.palette {
+        Some(palette) if !palette.is_empty() => palette.as_slice(),
+        _ => &DEFAULT_PALETTE,
+    };
+    let radius = physics.particle_radius();
     let cube = meshes.add(Cuboid {
         half_size: Vec3::splat(radius),
     });
 
+    let mut color_source = ColorSource::from_u32(render_config.color_source);
+    let color_seed = render_config.color_seed as usize;
+    let mut block_id = 0;
+
+    let particles = physics.particles().unwrap_or_default();
+    if particles.is_empty()
+        && physics.num_particles() > 0
+        && matches!(color_source, ColorSource::Block | ColorSource::SpatialHash)
+    {
+        // Without CPU-side particles, only the index is known.
+        warn!(
+            "The {:?} color source needs the CPU-side particles (see \
+             `PhysicsContext::with_cpu_particles`), falling back to {:?}.",
+            color_source,
+            ColorSource::Index
+        );
+        color_source = ColorSource::Index;
+    }
+
+    let render_stride = render_config.render_stride.max(1) as usize;
+    let num_rendered = physics.num_particles().div_ceil(render_stride);
+    let max_particles = max_rendered_particles(&device.limits());
+    if num_rendered > max_particles {
+        error!(
+            "{} rendered particles exceed the device limit of {}. Only the first {} will be \
+             rendered.",
+            num_rendered, max_particles, max_particles
+        );
+    }
+
     let mut instances = vec![];
-    for (rb_id, particle) in physics.particles.iter().enumerate() {
-        let base_color = colors[rb_id % colors.len()].to_linear().to_f32_array();
+    for rb_id in (0..physics.num_particles())
+        .step_by(render_stride)
+        .take(max_particles)
+    {
+        // The positions will be updated by the vertex buffer preparation pass anyway.
+        let (position, velocity) = particles
+            .get(rb_id)
+            .map(|p| (p.position, p.velocity))
+            .unwrap_or((Vector3::zeros(), Vector3::zeros()));
+
+        if rb_id > 0
+            && color_source == ColorSource::Block
+            && !same_material(&particles[rb_id - render_stride], &particles[rb_id])
+        {
+            block_id += 1;
+        }
+
+        let color_id = match color_source {
+            ColorSource::Index => rb_id + color_seed,
+            ColorSource::Block => block_id + color_seed,
+            ColorSource::SpatialHash => spatial_hash(&position, radius * 2.0, color_seed),
+            ColorSource::Solid => color_seed,
+        };
+        let base_color = block_colors
+            .iter()
+            .find(|(particles, _)| particles.contains(&rb_id))
+            .map_or(colors[color_id % colors.len()], |(_, color)| *color)
+            .to_linear()
+            .to_f32_array();
+        // Without CPU-side particles, the custom colors wait for the first particle readback.
+        let color = match app_state
+            .custom_coloring
+            .as_ref()
+            .filter(|_| !particles.is_empty())
+        {
+            Some(coloring) => coloring.0(&ParticleView {
+                index: rb_id,
+                position: Vec3::new(position.x, position.y, position.z),
+                velocity: Vec3::new(velocity.x, velocity.y, velocity.z),
+                base_color,
+            }),
+            None => base_color,
+        };
         instances.push(InstanceData {
             deformation: [Vec4::X, Vec4::Y, Vec4::Z],
-            position: Vec4::new(
-                particle.position.x,
-                particle.position.y,
-                particle.position.z,
-                0.0,
-            ),
+            position: Vec4::new(position.x, position.y, position.z, 0.0),
             base_color,
-            color: base_color,
+            color,
+            velocity: Vec4::new(velocity.x, velocity.y, velocity.z, velocity.norm()),
         });
     }
 
-    let instances_buffer = GpuVector::init(
-        device,
-        &instances,
-        BufferUsages::STORAGE | BufferUsages::VERTEX,
-    );
+    let usages = BufferUsages::STORAGE
+        | BufferUsages::VERTEX
+        | BufferUsages::COPY_DST
+        | BufferUsages::COPY_SRC;
+    let instances_buffer = GpuVector::init(device, &instances, usages);
+    let back_buffer = app_state.double_buffer_instances.then(|| {
+        Arc::new(
+            GpuVector::init(device, &instances, usages)
+                .into_inner()
+                .into(),
+        )
+    });
 
     let num_instances = instances.len();
     commands.spawn((
@@ -125,8 +390,29 @@ fn setup_particles_graphics(
             buffer: InstanceBuffer {
                 buffer: Arc::new(instances_buffer.into_inner().into()),
                 length: num_instances,
+                back_buffer,
             },
+            lit: RenderMode::from_u32(render_config.mode) == RenderMode::Lit,
+            flow_disk: RenderMode::from_u32(render_config.mode) == RenderMode::FlowDisk,
+            transparent: render_config.is_transparent(),
+            render_stride,
         },
         NoFrustumCulling,
     ));
 }
+
+fn same_material(a: &Particle, b: &Particle) -> bool {
+    a.model.lambda == b.model.lambda
+        && a.model.mu == b.model.mu
+        && a.plasticity.is_some() == b.plasticity.is_some()
+        && a.phase.is_some() == b.phase.is_some()
+}
+
+fn spatial_hash(position: &Vector3<f32>, cell_width: f32, seed: usize) -> usize {
+    let cell = (position / cell_width).map(|e| e.floor() as i64 as u64);
+    let hash = cell.x.wrapping_mul(73_856_093)
+        ^ cell.y.wrapping_mul(19_349_663)
+        ^ cell.z.wrapping_mul(83_492_791)
+        ^ seed as u64;
+    hash as usize
+}
@@ -1,13 +1,23 @@
+use crate::components::MpmRapierContext;
+use crate::events::{DeviceLost, SimulationFinished};
 use crate::instancing3d::InstanceMaterialData;
-use crate::resources::{AppState, PhysicsContext, RunState, Timestamps};
+use crate::prep_vertex_buffer::{RenderConfig, RenderMode};
+use crate::resources::{
+    AppState, LastStepOutcome, PhysicsContext, RunState, SimulationDiagnostics, StepLimit,
+    TimeStepMode, TimestampProcessing, Timestamps, TimingHistory,
+};
+use crate::sleep::ParticleSleep;
 use async_channel::{Receiver, Sender};
 use bevy::prelude::*;
 use bevy::render::renderer::{RenderDevice, RenderQueue};
 use bevy::tasks::ComputeTaskPool;
+use bevy::utils::Instant;
 use bevy_rapier3d::plugin::{RapierContextMut, WriteRapierContext};
+use std::ops::Range;
 use wgcore::kernel::KernelInvocationQueue;
-use wgcore::re_exports::encase::StorageBuffer;
 use wgcore::timestamps::GpuTimestamps;
+use wgpu::{CommandEncoder, Device, Queue};
+use wgsparkl3d::rapier::dynamics::RigidBody;
 use wgsparkl3d::rapier::math::Vector;
 use wgsparkl3d::wgparry::math::GpuSim;
 use wgsparkl3d::wgrapier::dynamics::GpuVelocity;
@@ -18,6 +28,54 @@ pub struct TimestampChannel {
     pub rcv: Receiver<Timestamps>,
 }
 
+/// A callback invoked after encoding each simulation substep.
+///
+/// This is the extension point for injecting custom GPU work between substeps (forces, custom
+/// boundary conditions, etc.). Register hooks through the [`SubstepHooks`] resource.
+///
+/// All the substeps of a frame are recorded into the same command encoder and submitted at once,
+/// so GPU work must be recorded into `encoder` to run between substeps. In particular, writes
+/// through `Queue::write_buffer` are all applied before the frame’s commands execute, regardless
+/// of which substep they were issued from. Reading GPU buffers back from a hook would require
+/// submitting and waiting for the GPU, stalling the whole step.
+pub trait SubstepHook: Send + Sync {
+    fn on_substep(
+        &mut self,
+        ctx: &mut PhysicsContext,
+        encoder: &mut CommandEncoder,
+        substep: usize,
+    );
+}
+
+/// The [`SubstepHook`]s called during each step, in order.
+#[derive(Resource, Default)]
+pub struct SubstepHooks(pub Vec<Box<dyn SubstepHook>>);
+
+/// The longest frame duration simulated in [`TimeStepMode::Realtime`], in seconds.
+pub const MAX_REALTIME_FRAME_DT: f32 = 1.0 / 30.0;
+
+#[derive(Resource)]
+pub struct DeviceLostChannel {
+    pub rcv: Receiver<String>,
+}
+
+pub fn handle_device_lost(
+    channel: Res<DeviceLostChannel>,
+    mut app_state: ResMut<AppState>,
+    mut events: EventWriter<DeviceLost>,
+) {
+    while let Ok(message) = channel.rcv.try_recv() {
+        error!("The GPU device was lost, pausing the simulation: {message}");
+        app_state.device_lost = true;
+        app_state.run_state = RunState::Paused;
+        events.send(DeviceLost { message });
+    }
+}
+
+pub fn record_timing_history(timings: Res<Timestamps>, mut history: ResMut<TimingHistory>) {
+    history.record(&timings);
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn step_simulation(
     mut timings: ResMut<Timestamps>,
@@ -25,21 +83,101 @@ pub fn step_simulation(
     render_queue: Res<RenderQueue>,
     physics: Option<ResMut<PhysicsContext>>,
     mut app_state: ResMut<AppState>,
-    mut rapier: WriteRapierContext,
-    particles: Query<&InstanceMaterialData>,
+    mut rapier: WriteRapierContext<With<MpmRapierContext>>,
+    mut particles: Query<&mut InstanceMaterialData>,
     timings_channel: Res<TimestampChannel>,
+    time: Res<Time>,
+    mut hooks: Option<ResMut<SubstepHooks>>,
+    mut diagnostics: ResMut<SimulationDiagnostics>,
+    sleep: Option<Res<ParticleSleep>>,
+    step_limit: Option<Res<StepLimit>>,
+    mut finished: EventWriter<SimulationFinished>,
+    mut outcome: ResMut<LastStepOutcome>,
 ) {
-    if let Some(mut physics) = physics {
-        step_simulation_multisteps(
-            &mut timings,
-            &render_device,
-            &render_queue,
-            &mut physics,
-            &mut app_state,
-            &mut rapier.single_mut(),
-            &particles,
-            &timings_channel,
-        )
+    if let Some(delay) = app_state.auto_start_after {
+        if app_state.run_state != RunState::Paused {
+            // The simulation was started (or stepped) manually.
+            app_state.auto_start_after = None;
+        } else if time.elapsed() >= delay {
+            app_state.run_state = RunState::Running;
+            app_state.auto_start_after = None;
+        }
+    }
+
+    if app_state.device_lost {
+        *outcome = LastStepOutcome::SkippedDeviceLost;
+        return;
+    }
+
+    let Some(mut physics) = physics else {
+        *outcome = LastStepOutcome::SkippedNoPhysics;
+        return;
+    };
+
+    let realtime = app_state.time_step_mode == TimeStepMode::Realtime && !app_state.deterministic;
+    if realtime && time.delta_secs() > 0.0 {
+        let frame_dt = time.delta_secs().min(MAX_REALTIME_FRAME_DT);
+        let dt = frame_dt / app_state.num_substeps.max(1) as f32;
+        physics.set_realtime_dt(&render_queue, Some(dt));
+    } else if !realtime && physics.realtime_dt().is_some() {
+        // Back to the base `dt` of the simulation parameters.
+        physics.set_realtime_dt(&render_queue, None);
+    }
+
+    if physics.is_added() && app_state.gravity_ramp.is_some() {
+        physics.set_gravity_scale(&render_queue, 0.0);
+    }
+
+    // Without any coupled body, the simulation doesn’t need Rapier at all.
+    let needs_rapier = app_state.sync_rapier_bodies && !physics.data.coupling().is_empty();
+    let rapier = (!rapier.rapier_context.is_empty()).then(|| rapier.single_mut());
+    if needs_rapier && rapier.is_none() {
+        // No Rapier context selected yet.
+        *outcome = LastStepOutcome::SkippedNotReady;
+        return;
+    }
+
+    let asleep = sleep.is_some_and(|sleep| sleep.is_asleep());
+    *outcome = if app_state.run_state == RunState::Paused {
+        LastStepOutcome::SkippedPaused
+    } else if asleep {
+        LastStepOutcome::SkippedAsleep
+    } else {
+        LastStepOutcome::Stepped
+    };
+
+    let prev_num_steps = app_state.num_steps;
+    step_simulation_multisteps(
+        &mut timings,
+        &render_device,
+        &render_queue,
+        &mut physics,
+        &mut app_state,
+        rapier.as_ref(),
+        &mut particles,
+        &timings_channel,
+        hooks.as_deref_mut(),
+        &mut diagnostics,
+        asleep,
+    );
+
+    let num_new_steps = app_state.num_steps - prev_num_steps;
+    if let Some(ramp) = app_state.gravity_ramp {
+        if num_new_steps > 0 && physics.gravity_scale() < 1.0 {
+            let step_dt = physics.substep_dt() * app_state.num_substeps as f32;
+            let scale =
+                physics.gravity_scale() + num_new_steps as f32 * step_dt / ramp.as_secs_f32();
+            physics.set_gravity_scale(&render_queue, scale.min(1.0));
+        }
+    }
+
+    if let Some(limit) = step_limit {
+        if prev_num_steps < limit.0 && app_state.num_steps >= limit.0 {
+            app_state.run_state = RunState::Paused;
+            finished.send(SimulationFinished {
+                num_steps: app_state.num_steps,
+            });
+        }
     }
 }
 
@@ -50,131 +188,185 @@ fn step_simulation_multisteps(
     render_queue: &RenderQueue,
     physics: &mut PhysicsContext,
     app_state: &mut AppState,
-    rapier: &mut RapierContextMut,
-    particles: &Query<&InstanceMaterialData>,
+    rapier: Option<&RapierContextMut>,
+    particles: &mut Query<&mut InstanceMaterialData>,
     timings_channel: &TimestampChannel,
+    hooks: Option<&mut SubstepHooks>,
+    diagnostics: &mut SimulationDiagnostics,
+    asleep: bool,
 ) {
-    if app_state.run_state == RunState::Paused {
-        return;
-    }
-
+    let step_physics = app_state.run_state != RunState::Paused && !asleep;
     let timings = &mut *timings;
 
+    // The `GpuTimestamps` are moved into the task processing them, and come back through the
+    // channel. Receive them even while paused, so they are restored (and the timings are up to
+    // date) before the next step is encoded.
     while let Ok(new_timings) = timings_channel.rcv.try_recv() {
+        debug_assert!(
+            timings.timestamps.is_none(),
+            "a GpuTimestamps was received while another one was available"
+        );
         *timings = new_timings;
+        diagnostics.pending_timestamp_tasks = diagnostics.pending_timestamp_tasks.saturating_sub(1);
     }
 
-    if let Some(t) = timings.timestamps.as_mut() {
-        t.clear()
+    // Without a CPU copy of the particles, new instances are created without their positions:
+    // prepare them once even while paused so they aren't all rendered at the origin.
+    let graphics_added = particles
+        .get_single_mut()
+        .is_ok_and(|instances| instances.is_added());
+    if !step_physics && !app_state.render_while_paused && !graphics_added {
+        return;
     }
 
-    // Run the simulation.
+    if app_state.next_substep >= app_state.num_substeps {
+        app_state.next_substep = 0; // The number of substeps decreased.
+    }
+    let substeps = if app_state.run_state == RunState::StepSubstep {
+        app_state.next_substep..app_state.next_substep + 1
+    } else {
+        app_state.next_substep..app_state.num_substeps
+    };
+
+    // Don’t record new timestamps until the previous ones were processed, so timestamp tasks
+    // can’t pile up if the GPU results lag behind. Partial steps aren’t recorded since the
+    // timings are accumulated over all the substeps.
+    let record_timestamps = app_state.timestamp_processing != TimestampProcessing::Disabled
+        && diagnostics.pending_timestamp_tasks == 0
+        && substeps.len() == app_state.num_substeps;
+
     let device = render_device.wgpu_device();
     let physics = &mut *physics;
     let compute_queue = &*render_queue.0;
-    let mut queue = KernelInvocationQueue::new(device);
     let mut encoder = device.create_command_encoder(&Default::default());
+    // With double-buffering, this isn’t the buffer rendered this frame.
+    let instances_buffer = particles.get_single_mut().ok().map(|mut instances| {
+        (
+            instances.buffer.begin_frame(&mut encoder),
+            instances.buffer.length,
+            instances.render_stride,
+        )
+    });
 
-    // Send updated bodies information to the gpu.
-    // PERF: don’t reallocate the buffers at each step.
-    let poses_data: Vec<GpuSim> = physics
-        .data
-        .coupling()
-        .iter()
-        .map(|coupling| {
-            let c = &rapier.colliders.colliders[coupling.collider];
-            GpuSim::from_isometry(*c.position(), 1.0)
-        })
-        .collect();
-    compute_queue.write_buffer(
-        physics.data.bodies.poses().buffer(),
-        0,
-        bytemuck::cast_slice(&poses_data),
-    );
+    // Run the simulation.
+    if step_physics {
+        if substeps.end == app_state.num_substeps {
+            app_state.num_steps += 1;
+        }
 
-    let gravity = Vector::y() * -9.81;
-    let vels_data: Vec<_> = physics
-        .data
-        .coupling()
-        .iter()
-        .map(|coupling| {
-            let rb = &rapier.rigidbody_set.bodies[coupling.body];
-            GpuVelocity {
-                linear: *rb.linvel()
-                    + gravity
-                        * rapier.simulation.integration_parameters.dt
-                        * (rb.is_dynamic() as u32 as f32)
-                        / (app_state.num_substeps as f32),
-                angular: *rb.angvel(),
-            }
-        })
-        .collect();
-    let mut vels_bytes = vec![];
-    let mut buffer = StorageBuffer::new(&mut vels_bytes);
-    buffer.write(&vels_data).unwrap();
-    compute_queue.write_buffer(physics.data.bodies.vels().buffer(), 0, &vels_bytes);
+        if let Some(t) = timings.timestamps.as_mut() {
+            t.clear()
+        }
 
-    //// Step the simulation.
-    app_state
-        .pipeline
-        .queue_step(&mut physics.data, &mut queue, timings.timestamps.is_some());
+        let encode_start = Instant::now();
+        encode_physics_step(
+            &mut encoder,
+            device,
+            compute_queue,
+            physics,
+            app_state,
+            rapier,
+            hooks,
+            substeps.clone(),
+            timings.timestamps.as_mut().filter(|_| record_timestamps),
+        );
+        app_state.next_substep = substeps.end % app_state.num_substeps.max(1);
+        timings.cpu_encode = encode_start.elapsed().as_secs_f64() * 1000.0;
 
-    for _ in 0..app_state.num_substeps {
-        queue.encode(&mut encoder, timings.timestamps.as_mut());
-    }
-    physics
-        .data
-        .poses_staging
-        .copy_from(&mut encoder, physics.data.bodies.poses());
-    if let Some(t) = timings.timestamps.as_mut() {
-        t.resolve(&mut encoder)
+        if let Some((instances_buffer, num_instances, _)) = instances_buffer
+            .as_ref()
+            .filter(|_| app_state.track_particle_age)
+        {
+            let mut queue = KernelInvocationQueue::new(device);
+            app_state.particle_age.queue(
+                compute_queue,
+                &mut queue,
+                &physics.data.sim_params,
+                substeps.len(),
+                instances_buffer,
+                *num_instances,
+            );
+            queue.encode(&mut encoder, None);
+        }
     }
 
     // Prepare the vertex buffer for rendering the particles.
-    if let Ok(instances_buffer) = particles.get_single() {
-        queue.clear();
+    // This also runs while paused (if enabled) so the instance buffer stays up-to-date.
+    if let Some((instances_buffer, _, render_stride)) = &instances_buffer {
+        // The stride must match the one the instance buffer was sized for.
+        let config = RenderConfig {
+            render_stride: *render_stride as u32,
+            ..app_state.render_config
+        };
+        compute_queue.write_buffer(
+            app_state.gpu_render_config.buffer.buffer(),
+            0,
+            bytemuck::bytes_of(&config),
+        );
+
+        let packing = RenderMode::from_u32(app_state.render_config.mode) == RenderMode::Packing;
+        if packing {
+            app_state
+                .packing
+                .resize(device, physics.data.particles.positions.len() as usize);
+        }
+
+        let mut queue = KernelInvocationQueue::new(device);
+        if packing {
+            app_state
+                .packing
+                .queue(&mut queue, &physics.data.particles, &physics.data.grid);
+        }
         app_state.prep_vertex_buffer.queue(
             &mut queue,
             &app_state.gpu_render_config,
             &physics.data.particles,
             &physics.data.grid,
             &physics.data.sim_params,
-            &instances_buffer.buffer.buffer,
+            app_state.packing.counts(),
+            instances_buffer,
+        );
+        queue.encode(
+            &mut encoder,
+            timings
+                .timestamps
+                .as_mut()
+                .filter(|_| step_physics && record_timestamps),
         );
-        queue.encode(&mut encoder, timings.timestamps.as_mut());
     }
 
     // Submit.
+    // The two-way coupled body poses can be read back with `PhysicsContext::read_body_poses_async`.
     compute_queue.submit(Some(encoder.finish()));
 
-    // let new_poses = futures::executor::block_on(physics.data.poses_staging.read(device)).unwrap();
-    //
-    // for (i, (_, rb)) in rapier.bodies.iter_mut().enumerate() {
-    //     if rb.is_dynamic() {
-    //         let vel_before = *rb.linvel();
-    //         let interpolator = RigidBodyPosition {
-    //             position: *rb.position(),
-    //             next_position: new_poses[i].isometry,
-    //         };
-    //         let vel = interpolator.interpolate_velocity(
-    //             1.0 / (rapier.integration_parameters.dt / divisor),
-    //             &rb.mass_properties().local_mprops.local_com,
-    //         );
-    //         rb.set_linvel(vel.linvel, true);
-    //         rb.set_angvel(vel.angvel, true);
-    //         println!("dvel: {:?}", vel.linvel - vel_before);
-    //     }
-    // }
-
-    if let Some(timestamps) = std::mem::take(&mut timings.timestamps) {
+    if !step_physics {
+        return;
+    }
+
+    if let Some(timestamps) = record_timestamps
+        .then(|| std::mem::take(&mut timings.timestamps))
+        .flatten()
+    {
         let timings_snd = timings_channel.snd.clone();
         let timestamp_period = compute_queue.get_timestamp_period();
         let num_substeps = app_state.num_substeps;
+        let step_id = app_state.num_steps;
+        diagnostics.pending_timestamp_tasks += 1;
         let timestamps_future = async move {
-            let values = timestamps.wait_for_results_async().await.unwrap();
+            let Ok(values) = timestamps.wait_for_results_async().await else {
+                // Reading the results can fail if the device was lost.
+                let _ = timings_snd
+                    .send(Timestamps {
+                        timestamps: Some(timestamps),
+                        ..Default::default()
+                    })
+                    .await;
+                return;
+            };
             let timestamps_ms = GpuTimestamps::timestamps_to_ms(&values, timestamp_period);
             let mut new_timings = Timestamps {
                 timestamps: Some(timestamps),
+                last_updated_step: step_id,
                 ..Default::default()
             };
             // `GpuTimestamps` uses a buffer of 2 `Timestamps`, one for the start and one for the end of the operation,
@@ -205,10 +397,124 @@ fn step_simulation_multisteps(
             timings_snd.send(new_timings).await.unwrap();
         };
 
-        ComputeTaskPool::get().spawn(timestamps_future).detach();
+        match app_state.timestamp_processing {
+            // Blocking on the GPU results would deadlock the browser’s event loop.
+            #[cfg(not(target_arch = "wasm32"))]
+            TimestampProcessing::Blocking => futures::executor::block_on(timestamps_future),
+            _ => ComputeTaskPool::get().spawn(timestamps_future).detach(),
+        }
     }
 
-    if app_state.run_state == RunState::Step {
+    if matches!(app_state.run_state, RunState::Step | RunState::StepSubstep) {
         app_state.run_state = RunState::Paused;
     }
 }
+
+#[allow(clippy::too_many_arguments)]
+fn encode_physics_step(
+    encoder: &mut CommandEncoder,
+    device: &Device,
+    compute_queue: &Queue,
+    physics: &mut PhysicsContext,
+    app_state: &AppState,
+    rapier: Option<&RapierContextMut>,
+    mut hooks: Option<&mut SubstepHooks>,
+    substeps: Range<usize>,
+    mut timestamps: Option<&mut GpuTimestamps>,
+) {
+    let mut queue = KernelInvocationQueue::new(device);
+
+    // NOTE: `MpmPipeline::queue_step` always queues the CDF stages, even without any coupled
+    //       body (wgsparkl3d doesn’t expose per-stage selection). We can at least skip the
+    //       coupling upload in that case.
+    //       The bodies are only uploaded at the start of a step, so their poses stay consistent
+    //       across the substeps of a step spread over several frames.
+    if let Some(rapier) = rapier.filter(|_| {
+        substeps.start == 0 && app_state.sync_rapier_bodies && !physics.data.coupling().is_empty()
+    }) {
+        upload_coupled_bodies(compute_queue, physics, app_state, rapier);
+    }
+
+    //// Step the simulation.
+    let mut speed_limit_queue = KernelInvocationQueue::new(device);
+    if let Some(max_speed) = app_state.max_particle_speed {
+        app_state
+            .speed_limit
+            .set_max_speed(compute_queue, max_speed);
+        app_state
+            .speed_limit
+            .queue(&mut speed_limit_queue, &physics.data.particles);
+    }
+    app_state
+        .pipeline
+        .queue_step(&mut physics.data, &mut queue, timestamps.is_some());
+
+    // NOTE: compute passes can’t be recorded into reusable bundles, so each substep is encoded
+    //       again. The kernels and bind groups are only created once by `queue_step` though,
+    //       so this loop only records the dispatches.
+    //       The grid sort can’t be run less often than every substep: it is queued along with
+    //       the other stages by `queue_step`, and the P2G/G2P stages rely on the sorted particle
+    //       ids and cell lists of the current substep. Skipping it would require support from
+    //       wgsparkl3d.
+    for substep in substeps {
+        queue.encode(encoder, timestamps.as_deref_mut());
+        speed_limit_queue.encode(encoder, None);
+
+        if let Some(hooks) = hooks.as_deref_mut() {
+            for hook in &mut hooks.0 {
+                hook.on_substep(physics, encoder, substep);
+            }
+        }
+    }
+    // Only the two-way coupled bodies are moved by the particles: the other poses are the ones
+    // uploaded at the start of the step, so there is nothing to read back without them.
+    if physics.has_two_way_coupling() {
+        physics
+            .data
+            .poses_staging
+            .copy_from(encoder, physics.data.bodies.poses());
+    }
+    if let Some(t) = timestamps {
+        t.resolve(encoder)
+    }
+}
+
+fn upload_coupled_bodies(
+    compute_queue: &Queue,
+    physics: &PhysicsContext,
+    app_state: &AppState,
+    rapier: &RapierContextMut,
+) {
+    // Send updated bodies information to the gpu.
+    // Both modes upload the Rapier state: one-way bodies are only boundary conditions for the
+    // particles, and two-way bodies start each step from it before the GPU applies the particle
+    // forces (the result is read back from `poses_staging`).
+    // PERF: don’t reallocate the buffers at each step.
+    let dt = rapier.simulation.integration_parameters.dt;
+    let bodies: Vec<_> = physics
+        .data
+        .coupling()
+        .iter()
+        .map(|coupling| {
+            let c = &rapier.colliders.colliders[coupling.collider];
+            let rb = &rapier.rigidbody_set.bodies[coupling.body];
+            (
+                GpuSim::from_isometry(*c.position(), 1.0),
+                coupled_body_velocity(rb, dt, app_state.num_substeps),
+            )
+        })
+        .collect();
+    physics.upload_body_poses(compute_queue, &bodies);
+}
+
+/// The velocity uploaded to the GPU for a coupled rigid-body.
+///
+/// The linear velocity of dynamic bodies includes the gravity pre-integrated over one substep.
+pub fn coupled_body_velocity(rb: &RigidBody, dt: f32, num_substeps: usize) -> GpuVelocity {
+    let gravity = Vector::y() * -9.81;
+    GpuVelocity {
+        linear: *rb.linvel()
+            + gravity * dt * (rb.is_dynamic() as u32 as f32) / (num_substeps as f32),
+        angular: *rb.angvel(),
+    }
+}
@@ -0,0 +1,313 @@
+//! Surface mesh extraction from the particles, for exporting simulation results.
+
+use crate::readback::ParticleSample;
+use bevy::math::Vec3;
+use bevy::prelude::{Mesh, warn};
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::render_resource::PrimitiveTopology;
+
+/// The corners of a grid cell, indexed as in the classic marching cubes tables.
+const CELL_CORNERS: [[usize; 3]; 8] = [
+    [0, 0, 0],
+    [1, 0, 0],
+    [1, 1, 0],
+    [0, 1, 0],
+    [0, 0, 1],
+    [1, 0, 1],
+    [1, 1, 1],
+    [0, 1, 1],
+];
+
+/// The maximum number of nodes of the density grid, i.e., 256 per axis for a cubic cloud.
+const MAX_GRID_NODES: f64 = 16_777_216.0;
+
+/// Decomposition of a grid cell into six tetrahedra sharing the diagonal `0-6`.
+const CELL_TETRAHEDRA: [[usize; 4]; 6] = [
+    [0, 5, 1, 6],
+    [0, 1, 2, 6],
+    [0, 2, 3, 6],
+    [0, 3, 7, 6],
+    [0, 7, 4, 6],
+    [0, 4, 5, 6],
+];
+
+/// Extracts a triangle mesh of the surface of the particle cloud.
+///
+/// A density field is sampled on a grid of cells of width `cell_width`, by splatting a smooth
+/// kernel of radius `2 * particle_radius` around each particle (the kernel evaluates to `1.0` at
+/// the particle center). The surface is the level set of this field at `iso_level` (`0.5` is a
+/// good start), polygonized by marching tetrahedra over each cell (a variant of marching cubes
+/// that doesn’t suffer from ambiguous cases).
+///
+/// The particle samples can be obtained from [`ParticleReadback`](crate::readback::ParticleReadback).
+/// Samples with non-finite positions are ignored. If the particles are so spread out that the
+/// grid would exceed 256³ nodes, the cell width is doubled until it fits, with a warning. This
+/// runs on the CPU and is meant for offline export, not for per-frame use.
+pub fn extract_surface_mesh(
+    samples: &[ParticleSample],
+    particle_radius: f32,
+    iso_level: f32,
+    cell_width: f32,
+) -> Mesh {
+    let mut positions: Vec<[f32; 3]> = vec![];
+    let points: Vec<Vec3> = samples
+        .iter()
+        .map(|s| Vec3::from(s.position))
+        .filter(|pt| pt.is_finite())
+        .collect();
+
+    if !points.is_empty() {
+        let field = DensityField::new(&points, particle_radius * 2.0, cell_width);
+        field.polygonize(iso_level, &mut positions);
+    }
+
+    Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::default(),
+    )
+    .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+    .with_computed_flat_normals()
+}
+
+struct DensityField {
+    origin: Vec3,
+    cell_width: f32,
+    dims: [usize; 3],
+    values: Vec<f32>,
+}
+
+impl DensityField {
+    fn new(points: &[Vec3], kernel_radius: f32, mut cell_width: f32) -> Self {
+        let (mins, maxs) = points.iter().fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(mins, maxs), pt| (mins.min(*pt), maxs.max(*pt)),
+        );
+        let num_nodes = |cell_width: f32| {
+            let extents = maxs - mins + Vec3::splat(kernel_radius + cell_width) * 2.0;
+            let dims = (extents / cell_width).ceil().as_dvec3() + 2.0;
+            dims.element_product()
+        };
+        if num_nodes(cell_width) > MAX_GRID_NODES {
+            let requested = cell_width;
+            while num_nodes(cell_width) > MAX_GRID_NODES {
+                cell_width *= 2.0;
+            }
+            warn!(
+                "The particles are too spread out for a surface grid with cells of width \
+                 {requested}, using cells of width {cell_width} instead."
+            );
+        }
+
+        // Leave room for the kernel support and an empty layer of cells around the particles.
+        let margin = Vec3::splat(kernel_radius + cell_width);
+        let origin = mins - margin;
+        let extents = maxs + margin - origin;
+        let dims = (extents / cell_width).ceil().as_uvec3() + 1;
+        let dims = [dims.x as usize, dims.y as usize, dims.z as usize];
+
+        let mut field = Self {
+            origin,
+            cell_width,
+            dims,
+            values: vec![0.0; dims[0] * dims[1] * dims[2]],
+        };
+
+        let support = (kernel_radius / cell_width).ceil() as isize;
+        for &pt in points {
+            let center = ((pt - origin) / cell_width).round().as_ivec3();
+
+            for k in -support..=support {
+                for j in -support..=support {
+                    for i in -support..=support {
+                        let node = [
+                            (center.x as isize + i) as usize,
+                            (center.y as isize + j) as usize,
+                            (center.z as isize + k) as usize,
+                        ];
+                        let dist_sq = field.node_position(node).distance_squared(pt);
+                        let q = 1.0 - dist_sq / (kernel_radius * kernel_radius);
+                        if q > 0.0 {
+                            let id = field.node_id(node);
+                            field.values[id] += q * q * q;
+                        }
+                    }
+                }
+            }
+        }
+
+        field
+    }
+
+    fn node_id(&self, node: [usize; 3]) -> usize {
+        node[0] + node[1] * self.dims[0] + node[2] * self.dims[0] * self.dims[1]
+    }
+
+    fn node_position(&self, node: [usize; 3]) -> Vec3 {
+        self.origin + Vec3::new(node[0] as f32, node[1] as f32, node[2] as f32) * self.cell_width
+    }
+
+    fn polygonize(&self, iso_level: f32, out: &mut Vec<[f32; 3]>) {
+        for k in 0..self.dims[2] - 1 {
+            for j in 0..self.dims[1] - 1 {
+                for i in 0..self.dims[0] - 1 {
+                    let corners = CELL_CORNERS.map(|[di, dj, dk]| {
+                        let node = [i + di, j + dj, k + dk];
+                        (self.node_position(node), self.values[self.node_id(node)])
+                    });
+
+                    for tet in CELL_TETRAHEDRA {
+                        polygonize_tetrahedron(tet.map(|c| corners[c]), iso_level, out);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn polygonize_tetrahedron(vertices: [(Vec3, f32); 4], iso_level: f32, out: &mut Vec<[f32; 3]>) {
+    let (inside, outside): (Vec<_>, Vec<_>) = vertices.into_iter().partition(|v| v.1 > iso_level);
+
+    if inside.is_empty() || outside.is_empty() {
+        return;
+    }
+
+    let crossing = |a: &(Vec3, f32), b: &(Vec3, f32)| {
+        let t = (iso_level - a.1) / (b.1 - a.1);
+        a.0.lerp(b.0, t)
+    };
+    let centroid = |vs: &[(Vec3, f32)]| vs.iter().map(|v| v.0).sum::<Vec3>() / vs.len() as f32;
+    let outward = centroid(&outside) - centroid(&inside);
+
+    let mut push_triangle = |a: Vec3, b: Vec3, c: Vec3| {
+        // Orient the triangle so it faces away from the particles.
+        if (b - a).cross(c - a).dot(outward) >= 0.0 {
+            out.extend([a.to_array(), b.to_array(), c.to_array()]);
+        } else {
+            out.extend([a.to_array(), c.to_array(), b.to_array()]);
+        }
+    };
+
+    match (inside.as_slice(), outside.as_slice()) {
+        ([lone], [a, b, c]) | ([a, b, c], [lone]) => {
+            push_triangle(crossing(lone, a), crossing(lone, b), crossing(lone, c));
+        }
+        ([a, b], [c, d]) => {
+            let (ac, ad, bc, bd) = (
+                crossing(a, c),
+                crossing(a, d),
+                crossing(b, c),
+                crossing(b, d),
+            );
+            push_triangle(ac, ad, bd);
+            push_triangle(ac, bd, bc);
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::mesh::VertexAttributeValues;
+    use std::collections::HashMap;
+
+    fn sample(position: Vec3) -> ParticleSample {
+        let mut sample = ParticleSample::default();
+        sample.position = position.to_array();
+        sample
+    }
+
+    fn triangles(mesh: &Mesh) -> Vec<[Vec3; 3]> {
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            panic!("the mesh has no positions");
+        };
+        positions
+            .chunks_exact(3)
+            .map(|tri| [0, 1, 2].map(|i| Vec3::from(tri[i])))
+            .collect()
+    }
+
+    /// Checks that every edge of `triangles` is shared by exactly two triangles.
+    fn assert_closed(triangles: &[[Vec3; 3]]) {
+        // The crossing points of an edge are computed from either end, so allow some rounding.
+        let key = |pt: Vec3| (pt * 1.0e4).round().as_ivec3().to_array();
+        let mut edges = HashMap::new();
+        for tri in triangles {
+            for (a, b) in [(0, 1), (1, 2), (2, 0)] {
+                let (a, b) = (key(tri[a]), key(tri[b]));
+                *edges
+                    .entry(if a < b { (a, b) } else { (b, a) })
+                    .or_insert(0) += 1;
+            }
+        }
+        assert!(edges.values().all(|count| *count == 2), "open surface");
+    }
+
+    fn assert_inside(triangles: &[[Vec3; 3]], mins: Vec3, maxs: Vec3) {
+        for pt in triangles.iter().flatten() {
+            assert!(
+                pt.cmpge(mins).all() && pt.cmple(maxs).all(),
+                "{pt} out of bounds"
+            );
+        }
+    }
+
+    #[test]
+    fn empty_samples_give_an_empty_mesh() {
+        let mesh = extract_surface_mesh(&[], 0.25, 0.5, 0.25);
+        assert!(triangles(&mesh).is_empty());
+    }
+
+    #[test]
+    fn single_particle_gives_a_closed_surface() {
+        let center = Vec3::new(1.0, 2.0, 3.0);
+        let mesh = extract_surface_mesh(&[sample(center)], 0.25, 0.5, 0.1);
+        let triangles = triangles(&mesh);
+
+        assert!(!triangles.is_empty());
+        assert_closed(&triangles);
+        // The kernel has a radius of twice the particle radius.
+        assert_inside(&triangles, center - 0.5, center + 0.5);
+    }
+
+    #[test]
+    fn cube_gives_a_closed_surface() {
+        let mut samples = vec![];
+        for i in 0..4 {
+            for j in 0..4 {
+                for k in 0..4 {
+                    samples.push(sample(Vec3::new(i as f32, j as f32, k as f32) * 0.5));
+                }
+            }
+        }
+        let mesh = extract_surface_mesh(&samples, 0.25, 0.5, 0.1);
+        let triangles = triangles(&mesh);
+
+        assert!(!triangles.is_empty());
+        assert_closed(&triangles);
+        assert_inside(&triangles, Vec3::splat(-0.5), Vec3::splat(2.0));
+    }
+
+    #[test]
+    fn non_finite_samples_are_ignored() {
+        let center = Vec3::new(1.0, 2.0, 3.0);
+        let expected = triangles(&extract_surface_mesh(&[sample(center)], 0.25, 0.5, 0.1));
+
+        let samples = [
+            sample(center),
+            sample(Vec3::new(f32::NAN, 0.0, 0.0)),
+            sample(Vec3::new(0.0, f32::INFINITY, 0.0)),
+        ];
+        let mesh = extract_surface_mesh(&samples, 0.25, 0.5, 0.1);
+        assert_eq!(triangles(&mesh), expected);
+    }
+
+    #[test]
+    fn far_outlier_doesnt_allocate_an_unbounded_grid() {
+        let samples = [sample(Vec3::ZERO), sample(Vec3::splat(1.0e9))];
+        let field = DensityField::new(&samples.map(|s| Vec3::from(s.position)), 0.5, 0.1);
+        assert!(field.values.len() as f64 <= MAX_GRID_NODES);
+    }
+}
@@ -0,0 +1,80 @@
+//! Helpers for the unit tests running the solver on a GPU.
+//!
+//! The tests using them are `#[ignore]`d since they need a GPU adapter: run them with
+//! `cargo test -- --ignored`.
+
+use crate::readback::{ParticleSample, WgParticleReadback};
+use crate::resources::PhysicsContext;
+use wgcore::Shader;
+use wgcore::kernel::KernelInvocationQueue;
+use wgcore::tensor::GpuVector;
+use wgpu::{BufferUsages, Device, Queue};
+use wgsparkl3d::pipeline::MpmPipeline;
+
+/// A device created on the default adapter, without any window.
+pub fn gpu_device() -> (Device, Queue) {
+    futures::executor::block_on(async {
+        let instance = wgpu::Instance::default();
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions::default())
+            .await
+            .expect("no GPU adapter available");
+        adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    required_limits: adapter.limits(),
+                    ..Default::default()
+                },
+                None,
+            )
+            .await
+            .unwrap()
+    })
+}
+
+/// Runs one step of `num_substeps` substeps, and waits for the GPU to complete it.
+pub fn step(
+    device: &Device,
+    queue: &Queue,
+    pipeline: &MpmPipeline,
+    physics: &mut PhysicsContext,
+    num_substeps: usize,
+) {
+    let mut encoder = device.create_command_encoder(&Default::default());
+    let mut kernels = KernelInvocationQueue::new(device);
+    pipeline.queue_step(&mut physics.data, &mut kernels, false);
+    for _ in 0..num_substeps {
+        kernels.encode(&mut encoder, None);
+    }
+    queue.submit(Some(encoder.finish()));
+    device.poll(wgpu::Maintain::Wait);
+}
+
+/// Reads the state of every particle back.
+pub fn read_samples(
+    device: &Device,
+    queue: &Queue,
+    physics: &PhysicsContext,
+) -> Vec<ParticleSample> {
+    let readback = WgParticleReadback::from_device(device).unwrap();
+    let zeros = vec![ParticleSample::default(); physics.num_particles()];
+    let samples = GpuVector::init(
+        device,
+        &zeros,
+        BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+    );
+    let staging = GpuVector::init(
+        device,
+        &zeros,
+        BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+    );
+
+    let mut encoder = device.create_command_encoder(&Default::default());
+    let mut kernels = KernelInvocationQueue::new(device);
+    readback.queue(&mut kernels, &physics.data.particles, &samples);
+    kernels.encode(&mut encoder, None);
+    staging.copy_from(&mut encoder, &samples);
+    queue.submit(Some(encoder.finish()));
+
+    futures::executor::block_on(staging.read(device)).unwrap()
+}
@@ -0,0 +1,90 @@
+//! The setup shared by the integration tests.
+//!
+//! They run the simulation on a GPU without opening any window, so they are `#[ignore]`d: run
+//! them with `cargo test -- --ignored`.
+
+#![allow(dead_code)]
+
+use bevy::app::PluginsState;
+use bevy::prelude::*;
+use bevy::render::renderer::RenderDevice;
+use bevy::tasks::tick_global_task_pools_on_main_thread;
+use bevy::window::ExitCondition;
+use bevy::winit::WinitPlugin;
+use bevy_wgsparkl::prelude::*;
+use wgsparkl3d::rapier::dynamics::RigidBodySet;
+use wgsparkl3d::rapier::geometry::ColliderSet;
+
+/// The width of the grid cells of the test simulations.
+pub const CELL_WIDTH: f32 = 1.0;
+
+/// An app with the default plugins and the [`WgSparklPlugin`], without any window.
+///
+/// Call [`finish`] once all the plugins were added.
+pub fn headless_app() -> App {
+    let mut app = App::new();
+    app.add_plugins(
+        DefaultPlugins
+            .set(WindowPlugin {
+                primary_window: None,
+                exit_condition: ExitCondition::DontExit,
+                close_when_requested: false,
+            })
+            .disable::<WinitPlugin>(),
+    )
+    .add_plugins(WgSparklPlugin::default());
+    app
+}
+
+/// Waits for the renderer to be initialized, then runs the startup systems.
+pub fn finish(app: &mut App) {
+    while app.plugins_state() == PluginsState::Adding {
+        tick_global_task_pools_on_main_thread();
+    }
+    app.finish();
+    app.cleanup();
+    app.update();
+}
+
+/// Runs `num_frames` frames of `app`.
+pub fn run(app: &mut App, num_frames: usize) {
+    for _ in 0..num_frames {
+        app.update();
+    }
+}
+
+/// A block of elastic particles centered on `center`.
+pub fn elastic_block(center: Vec3, half_extents: Vec3) -> Vec<Particle> {
+    let material = ParticleMaterial::new(
+        1000.0,
+        ElasticCoefficients::from_young_modulus(1_000_000.0, 0.3),
+    );
+    particles_in_box_local(
+        &Transform::from_translation(center),
+        half_extents,
+        recommended_spacing(CELL_WIDTH),
+        &material,
+    )
+}
+
+/// Inserts a simulation of `particles` without any coupled collider, stepped with 8 substeps.
+pub fn insert_particles(app: &mut App, particles: Vec<Particle>, gravity: Vec3) {
+    let world = app.world_mut();
+    let device = world.resource::<RenderDevice>().clone();
+    let mut app_state = world.resource_mut::<AppState>();
+    app_state.particles_initialized = true;
+    app_state.num_substeps = 8;
+    let params = SimulationParams {
+        gravity: vector![gravity.x, gravity.y, gravity.z],
+        dt: (1.0 / 60.0) / (app_state.num_substeps as f32),
+    };
+    world.insert_resource(PhysicsContext::from_particles(
+        device.wgpu_device(),
+        params,
+        particles,
+        &RigidBodySet::new(),
+        &ColliderSet::new(),
+        vec![],
+        CELL_WIDTH,
+    ));
+}
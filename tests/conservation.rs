@@ -0,0 +1,66 @@
+//! The solver integration doesn’t inject momentum.
+//!
+//! A block of elastic particles floats without gravity nor any coupled collider. Over
+//! `NUM_STEPS` steps, its total momentum must stay close to zero and its center of mass must not
+//! drift.
+
+mod common;
+
+use bevy::prelude::*;
+use bevy_wgsparkl::prelude::*;
+
+const NUM_STEPS: u64 = 600;
+/// The maximum norm of the average particle velocity, in m/s.
+const MAX_MEAN_VELOCITY: f32 = 1.0e-3;
+/// The maximum displacement of the center of mass, in meters.
+const MAX_DRIFT: f32 = 1.0e-2;
+
+#[test]
+#[ignore = "requires a GPU"]
+fn momentum_and_center_of_mass_are_conserved() {
+    let mut app = common::headless_app();
+    common::finish(&mut app);
+    app.world_mut().resource_mut::<ParticleReadback>().enabled = true;
+    let particles = common::elastic_block(Vec3::new(0.0, 10.0, 0.0), Vec3::splat(5.0));
+    common::insert_particles(&mut app, particles, Vec3::ZERO);
+
+    let mut initial_mass = None;
+    let mut initial_center_of_mass = None;
+    let mut last_readback = 0;
+    let mut num_checks = 0;
+    while app.world().resource::<AppState>().num_steps < NUM_STEPS {
+        app.update();
+
+        let readback = app.world().resource::<ParticleReadback>();
+        if readback.num_readbacks() == last_readback {
+            continue;
+        }
+        last_readback = readback.num_readbacks();
+        num_checks += 1;
+
+        let total_mass = readback.total_mass();
+        let expected_mass = *initial_mass.get_or_insert(total_mass);
+        assert!(
+            (total_mass - expected_mass).abs() <= expected_mass * 1.0e-4,
+            "the total mass changed from {expected_mass} to {total_mass}"
+        );
+
+        let center_of_mass = readback.center_of_mass().unwrap();
+        let initial_center_of_mass = *initial_center_of_mass.get_or_insert(center_of_mass);
+        let drift = center_of_mass.distance(initial_center_of_mass);
+        assert!(
+            drift <= MAX_DRIFT,
+            "the center of mass drifted by {drift:.3e}m after {} steps",
+            app.world().resource::<AppState>().num_steps
+        );
+
+        let mean_velocity = readback.total_momentum().length() / total_mass;
+        assert!(
+            mean_velocity <= MAX_MEAN_VELOCITY,
+            "the mean velocity reached {mean_velocity:.3e}m/s after {} steps",
+            app.world().resource::<AppState>().num_steps
+        );
+    }
+
+    assert!(num_checks > 1, "the particles were never read back");
+}
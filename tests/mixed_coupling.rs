@@ -0,0 +1,185 @@
+//! One-way and two-way coupled bodies in the same scene.
+//!
+//! Two balls fall into a block of elastic particles. The two-way coupled ball is stopped by the
+//! particles, while the one-way coupled ball pushes them aside without being slowed down.
+
+mod common;
+
+use bevy::prelude::*;
+use bevy::render::renderer::RenderDevice;
+use bevy_rapier3d::geometry::RapierColliderHandle;
+use bevy_rapier3d::plugin::{
+    RapierPhysicsPlugin, ReadRapierContext, TimestepMode, WriteRapierContext,
+};
+use bevy_rapier3d::prelude::{Collider, RigidBody, Velocity};
+use bevy_wgsparkl::prelude::*;
+use bevy_wgsparkl::step::SubstepHooks;
+use wgsparkl3d::rapier::dynamics::RigidBodyPosition;
+
+const GRAVITY: f32 = -9.81;
+const NUM_FRAMES: usize = 90;
+
+#[derive(Component)]
+struct OneWayBall;
+
+#[derive(Component)]
+struct TwoWayBall;
+
+#[test]
+#[ignore = "requires a GPU"]
+fn only_two_way_bodies_are_slowed_down() {
+    let mut app = common::headless_app();
+    app.add_plugins(RapierPhysicsPlugin::<()>::default())
+        // One Rapier step per frame, like the particles.
+        .insert_resource(TimestepMode::Fixed {
+            dt: 1.0 / 60.0,
+            substeps: 1,
+        })
+        .add_systems(Startup, setup_scene)
+        .add_systems(
+            Update,
+            setup_mpm_particles.in_set(WgSparklSet::PrepareCoupling),
+        )
+        .add_systems(Update, apply_gpu_body_motion.after(WgSparklSet::Step));
+    common::finish(&mut app);
+    common::run(&mut app, NUM_FRAMES);
+
+    let world = app.world_mut();
+    let (one_way, one_way_vel) = world
+        .query_filtered::<(&Transform, &Velocity), With<OneWayBall>>()
+        .single(world);
+    let (one_way_y, one_way_speed) = (one_way.translation.y, one_way_vel.linvel.length());
+    let (two_way, two_way_vel) = world
+        .query_filtered::<(&Transform, &Velocity), With<TwoWayBall>>()
+        .single(world);
+    let (two_way_y, two_way_speed) = (two_way.translation.y, two_way_vel.linvel.length());
+
+    // The one-way ball is in free fall: v² = 2gh.
+    let fall_height = 10.0 - one_way_y;
+    let free_fall_speed = (2.0 * -GRAVITY * fall_height).sqrt();
+    assert!(
+        (one_way_speed - free_fall_speed).abs() <= free_fall_speed * 0.05,
+        "the one-way ball moves at {one_way_speed}m/s instead of {free_fall_speed}m/s"
+    );
+
+    // The two-way ball rests on the particles.
+    assert!(
+        two_way_y > 5.0,
+        "the two-way ball sank into the particles, down to {two_way_y}"
+    );
+    assert!(
+        two_way_speed < one_way_speed * 0.2,
+        "the two-way ball moves at {two_way_speed}m/s, the one-way ball at {one_way_speed}m/s"
+    );
+}
+
+fn setup_scene(mut commands: Commands) {
+    for (x, two_way) in [(-4.0, false), (4.0, true)] {
+        let mut ball = commands.spawn((
+            Transform::from_xyz(x, 10.0, 0.0),
+            Collider::ball(1.0),
+            RigidBody::Dynamic,
+            Velocity::zero(),
+            MpmCouplingEnabled,
+        ));
+        if two_way {
+            ball.insert(TwoWayBall);
+        } else {
+            ball.insert(OneWayBall);
+        }
+    }
+}
+
+fn setup_mpm_particles(
+    mut commands: Commands,
+    device: Res<RenderDevice>,
+    mut app_state: ResMut<AppState>,
+    rapier: ReadRapierContext,
+    coupling: Query<(&RapierColliderHandle, Has<TwoWayBall>), With<MpmCouplingEnabled>>,
+) {
+    if rapier.rapier_context.get_single().is_err() {
+        return; // Rapier isn’t initialized yet.
+    }
+
+    let rapier = rapier.single();
+
+    if rapier.colliders.colliders.is_empty() || app_state.particles_initialized {
+        return;
+    }
+
+    app_state.particles_initialized = true;
+    app_state.num_substeps = 16;
+
+    let coupling: Vec<_> = coupling
+        .iter()
+        .map(|(co_handle, two_way)| BodyCouplingEntry {
+            body: rapier.colliders.colliders[co_handle.0].parent().unwrap(),
+            collider: co_handle.0,
+            mode: if two_way {
+                BodyCoupling::TwoWays
+            } else {
+                BodyCoupling::OneWay
+            },
+        })
+        .collect();
+
+    let params = SimulationParams {
+        gravity: vector![0.0, GRAVITY, 0.0],
+        dt: (1.0 / 60.0) / (app_state.num_substeps as f32),
+    };
+    commands.insert_resource(SubstepHooks(vec![Box::new(SimulationBounds::floor(
+        &device, 0.0,
+    ))]));
+    commands.insert_resource(PhysicsContext::from_particles(
+        device.wgpu_device(),
+        params,
+        common::elastic_block(Vec3::new(0.0, 4.0, 0.0), Vec3::new(8.0, 4.0, 4.0)),
+        &rapier.rigidbody_set.bodies,
+        &rapier.colliders.colliders,
+        coupling,
+        common::CELL_WIDTH,
+    ));
+}
+
+/// Moves the two-way coupled bodies towards the poses integrated on the GPU, like the
+/// `floating` example.
+fn apply_gpu_body_motion(
+    device: Res<RenderDevice>,
+    app_state: Res<AppState>,
+    physics: Option<Res<PhysicsContext>>,
+    mut rapier: WriteRapierContext<With<MpmRapierContext>>,
+    mut last_step: Local<u64>,
+) {
+    let Some(physics) = physics else {
+        return;
+    };
+
+    if app_state.num_steps == *last_step || rapier.rapier_context.is_empty() {
+        return;
+    }
+    *last_step = app_state.num_steps;
+
+    let Some(poses) =
+        futures::executor::block_on(physics.read_body_poses_async(device.wgpu_device()))
+    else {
+        return;
+    };
+
+    let step_dt = physics.substep_dt() * app_state.num_substeps as f32;
+    let mut rapier = rapier.single_mut();
+    for (coupling, pose) in physics.data.coupling().iter().zip(poses) {
+        if !matches!(coupling.mode, BodyCoupling::TwoWays) {
+            continue;
+        }
+
+        let rb = &mut rapier.rigidbody_set.bodies[coupling.body];
+        let interpolator = RigidBodyPosition {
+            position: *rb.position(),
+            next_position: pose.isometry,
+        };
+        let vel = interpolator
+            .interpolate_velocity(1.0 / step_dt, &rb.mass_properties().local_mprops.local_com);
+        rb.set_linvel(vel.linvel, true);
+        rb.set_angvel(vel.angvel, true);
+    }
+}
@@ -0,0 +1,61 @@
+//! Resetting the simulation only despawns what the plugin spawned.
+
+mod common;
+
+use bevy::prelude::*;
+use bevy_wgsparkl::instancing3d::InstanceMaterialData;
+use bevy_wgsparkl::prelude::*;
+
+#[test]
+#[ignore = "requires a GPU"]
+fn reset_keeps_camera_and_lights() {
+    let mut app = common::headless_app();
+    common::finish(&mut app);
+
+    let camera_transform =
+        Transform::from_xyz(-30.0, 30.0, 100.0).looking_at(Vec3::new(0.0, 10.0, 0.0), Vec3::Y);
+    let light_transform = Transform::from_xyz(10.0, 20.0, 10.0);
+    let world = app.world_mut();
+    let camera = world.spawn((Camera3d::default(), camera_transform)).id();
+    let light = world.spawn((PointLight::default(), light_transform)).id();
+
+    common::insert_particles(
+        &mut app,
+        common::elastic_block(Vec3::new(0.0, 10.0, 0.0), Vec3::splat(2.0)),
+        Vec3::new(0.0, -9.81, 0.0),
+    );
+    common::run(&mut app, 3);
+    let world = app.world_mut();
+    assert_eq!(
+        world.query::<&InstanceMaterialData>().iter(world).count(),
+        1
+    );
+
+    world.send_event(ResetSimulation);
+    common::run(&mut app, 2);
+
+    let world = app.world_mut();
+    assert!(world.get_resource::<PhysicsContext>().is_none());
+    assert!(!world.resource::<AppState>().particles_initialized);
+    assert_eq!(
+        world.query::<&InstanceMaterialData>().iter(world).count(),
+        0
+    );
+
+    assert!(world.get::<Camera3d>(camera).is_some());
+    assert_eq!(world.get::<Transform>(camera), Some(&camera_transform));
+    assert!(world.get::<PointLight>(light).is_some());
+    assert_eq!(world.get::<Transform>(light), Some(&light_transform));
+
+    // The simulation can be rebuilt and stepped after the reset.
+    world.resource_mut::<AppState>().run_state = RunState::Running;
+    common::insert_particles(
+        &mut app,
+        common::elastic_block(Vec3::new(0.0, 10.0, 0.0), Vec3::splat(2.0)),
+        Vec3::new(0.0, -9.81, 0.0),
+    );
+    common::run(&mut app, 5);
+    let world = app.world_mut();
+    assert!(world.get_resource::<PhysicsContext>().is_some());
+    assert!(world.resource::<AppState>().num_steps > 0);
+}
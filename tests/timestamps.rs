@@ -0,0 +1,86 @@
+//! The GPU timings survive pausing and resuming the simulation.
+
+mod common;
+
+use bevy::prelude::*;
+use bevy_wgsparkl::prelude::*;
+use bevy_wgsparkl::resources::TimestampProcessing;
+
+/// The step ids the timings were received for, after each frame.
+fn timed_steps(app: &mut App, num_frames: usize) -> Vec<(u64, u64)> {
+    (0..num_frames)
+        .map(|_| {
+            app.update();
+            let world = app.world();
+            let timings = world.resource::<Timestamps>();
+            let diagnostics = world.resource::<SimulationDiagnostics>();
+            assert!(diagnostics.pending_timestamp_tasks <= 1);
+            (
+                world.resource::<AppState>().num_steps,
+                timings.last_updated_step,
+            )
+        })
+        .collect()
+}
+
+#[test]
+#[ignore = "requires a GPU"]
+fn pausing_neither_loses_nor_duplicates_timestamps() {
+    let mut app = common::headless_app();
+    common::finish(&mut app);
+
+    if app.world().resource::<Timestamps>().timestamps.is_none() {
+        eprintln!("The device doesn’t support timestamp queries, skipping.");
+        return;
+    }
+
+    // Blocking makes the timings of each step arrive at the start of the next frame.
+    app.world_mut()
+        .resource_mut::<AppState>()
+        .timestamp_processing = TimestampProcessing::Blocking;
+    common::insert_particles(
+        &mut app,
+        common::elastic_block(Vec3::new(0.0, 10.0, 0.0), Vec3::splat(2.0)),
+        Vec3::new(0.0, -9.81, 0.0),
+    );
+
+    let running = timed_steps(&mut app, 5);
+    // Each frame receives the timings of the step of the previous frame.
+    for (num_steps, last_updated_step) in &running[1..] {
+        assert_eq!(*last_updated_step, num_steps - 1);
+    }
+
+    app.world_mut().resource_mut::<AppState>().run_state = RunState::Paused;
+    let paused = timed_steps(&mut app, 3);
+    // The timings of the last step are still received, and nothing else after that.
+    for (num_steps, last_updated_step) in &paused {
+        assert_eq!(*last_updated_step, *num_steps);
+    }
+    let world = app.world();
+    assert!(world.resource::<Timestamps>().timestamps.is_some());
+    assert_eq!(
+        world
+            .resource::<SimulationDiagnostics>()
+            .pending_timestamp_tasks,
+        0
+    );
+
+    app.world_mut().resource_mut::<AppState>().run_state = RunState::Running;
+    let resumed = timed_steps(&mut app, 5);
+    for (num_steps, last_updated_step) in &resumed {
+        assert_eq!(*last_updated_step, num_steps - 1);
+    }
+
+    // Every step was timed exactly once (a duplicated `GpuTimestamps` also trips a debug
+    // assertion when it is received).
+    let mut timed: Vec<_> = running
+        .iter()
+        .chain(&paused)
+        .chain(&resumed)
+        .map(|(_, step)| *step)
+        .filter(|step| *step > 0)
+        .collect();
+    timed.dedup();
+    let last_step = resumed.last().unwrap().0;
+    assert_eq!(timed, (1..last_step).collect::<Vec<_>>());
+}